@@ -0,0 +1,325 @@
+use crate::auth::{AuthMethod, Identity, Role};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    StopPlc,
+    HotStart,
+    ColdStart,
+    ChangePlcConnectionSettings,
+    SetSessionPassword,
+    SetPlcClock,
+    EnableHeater,
+    DisableHeater,
+    ChangeHeaterSettings, // Setpoint and tuning
+    WriteTag,
+    FillDb,
+    RestoreDb,
+    DownloadBlock,
+    AcceptBlockBaseline,
+    ReloadConfig, // Sections applied without a dedicated action, the section is the target
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Ok,
+    Failed,
+}
+
+/// One line of the audit log. `hash` is the SHA-256 of the entry with an empty `hash`,
+/// and `prev_hash` the hash of the entry before, so no entry can be changed or removed
+/// without breaking the chain.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub time: DateTime<FixedOffset>,
+    pub who: String,
+    pub auth: AuthMethod,
+    pub role: Role,
+    pub ip: Option<IpAddr>,
+    pub action: AuditAction,
+    pub plc: String,            // Address of the PLC written to
+    pub target: Option<String>, // Tag, DB or block within the PLC
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub result: AuditResult,
+    pub error: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let unhashed = AuditEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&unhashed).expect("audit entries always serialize");
+        hex::encode(Sha256::digest(json))
+    }
+}
+
+/// What a handler did, recorded together with the caller and the outcome.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    action: AuditAction,
+    plc: String,
+    target: Option<String>,
+    old_value: Option<Value>,
+    new_value: Option<Value>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, plc: &str) -> Self {
+        AuditEvent {
+            action,
+            plc: plc.to_string(),
+            target: None,
+            old_value: None,
+            new_value: None,
+        }
+    }
+
+    pub fn target(self, target: impl Into<String>) -> Self {
+        AuditEvent {
+            target: Some(target.into()),
+            ..self
+        }
+    }
+
+    pub fn old_value(self, value: impl Serialize) -> Self {
+        AuditEvent {
+            old_value: serde_json::to_value(value).ok(),
+            ..self
+        }
+    }
+
+    pub fn new_value(self, value: impl Serialize) -> Self {
+        AuditEvent {
+            new_value: serde_json::to_value(value).ok(),
+            ..self
+        }
+    }
+}
+
+/// Query parameters of `GET /audit`. Every given field has to match.
+#[derive(Deserialize, Debug, Default)]
+pub struct AuditFilter {
+    pub who: Option<String>,
+    pub action: Option<AuditAction>,
+    pub result: Option<AuditResult>,
+    pub plc: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+    pub limit: Option<usize>, // The most recent entries, 100 by default
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.who.as_ref().is_none_or(|who| *who == entry.who)
+            && self.action.is_none_or(|action| action == entry.action)
+            && self.result.is_none_or(|result| result == entry.result)
+            && self.plc.as_ref().is_none_or(|plc| *plc == entry.plc)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| Some(target) == entry.target.as_ref())
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time <= until)
+    }
+}
+
+/// Result of checking the whole chain.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: u64,
+    pub first_invalid: Option<u64>, // Line number, counted from 1
+    pub message: Option<String>,
+}
+
+struct Tail {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+/// Append-only log of every write to the PLC and its settings, one JSON entry per line.
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    tail: Arc<Mutex<Tail>>,
+}
+
+impl AuditLog {
+    /// Opens the log and continues its chain. A broken chain is reported, not repaired:
+    /// the damage stays visible to `verify`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| anyhow::anyhow!("Can't create audit directory {:?}: {}", dir, e))?;
+        }
+
+        let (seq, last_hash) = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .rev()
+                .find_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+                .map(|entry| (entry.seq, entry.hash))
+                .unwrap_or((0, GENESIS_HASH.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, GENESIS_HASH.to_string()),
+            Err(e) => anyhow::bail!("Can't read audit log {:?}: {}", path, e),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| anyhow::anyhow!("Can't open audit log {:?}: {}", path, e))?;
+        // An entry cut short by a crash stays on a line of its own
+        if file.metadata()?.len() > 0 && !fs::read(&path)?.ends_with(b"\n") {
+            file.write_all(b"\n")?;
+        }
+        let log = AuditLog {
+            path,
+            tail: Arc::new(Mutex::new(Tail {
+                file,
+                seq,
+                last_hash,
+            })),
+        };
+
+        let verification = log.verify()?;
+        if !verification.valid {
+            println!(
+                "** Audit log {:?} failed verification: {}",
+                log.path,
+                verification.message.unwrap_or_default()
+            );
+        }
+        Ok(log)
+    }
+
+    /// Appends an entry for `event` done by `identity`. The operation has already happened
+    /// when this is called, so a failure to write the entry is printed, not returned.
+    pub fn record<T>(
+        &self,
+        identity: &Identity,
+        event: AuditEvent,
+        result: &Result<T, anyhow::Error>,
+    ) {
+        if let Err(e) = self.append(identity, event, result) {
+            println!("** Failed to write audit log {:?}: {:?}", self.path, e);
+        }
+    }
+
+    fn append<T>(
+        &self,
+        identity: &Identity,
+        event: AuditEvent,
+        result: &Result<T, anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let mut tail = self.tail.lock().unwrap();
+        let mut entry = AuditEntry {
+            seq: tail.seq + 1,
+            time: Local::now().fixed_offset(),
+            who: identity.name.clone(),
+            auth: identity.method,
+            role: identity.role,
+            ip: identity.address,
+            action: event.action,
+            plc: event.plc,
+            target: event.target,
+            old_value: event.old_value,
+            new_value: event.new_value,
+            result: match result {
+                Ok(_) => AuditResult::Ok,
+                Err(_) => AuditResult::Failed,
+            },
+            error: result.as_ref().err().map(|e| format!("{:?}", e)),
+            prev_hash: tail.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        tail.file.write_all(&line)?;
+        tail.file.sync_data()?;
+        tail.seq = entry.seq;
+        tail.last_hash = entry.hash;
+        Ok(())
+    }
+
+    /// The most recent entries matching `filter`, oldest first.
+    pub fn entries(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, anyhow::Error> {
+        let _tail = self.tail.lock().unwrap(); // No entry half written while reading
+        let contents = fs::read_to_string(&self.path)?;
+        let mut entries: Vec<AuditEntry> = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .filter(|entry| filter.matches(entry))
+            .collect();
+        let limit = filter.limit.unwrap_or(DEFAULT_LIMIT);
+        let skip = entries.len().saturating_sub(limit);
+        Ok(entries.split_off(skip))
+    }
+
+    /// Checks every hash and every link of the chain.
+    pub fn verify(&self) -> Result<AuditVerification, anyhow::Error> {
+        let _tail = self.tail.lock().unwrap();
+        let contents = fs::read_to_string(&self.path)?;
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut entries = 0;
+
+        for (index, line) in contents.lines().enumerate() {
+            let problem = match serde_json::from_str::<AuditEntry>(line) {
+                Err(e) => Some(format!("unreadable entry: {}", e)),
+                Ok(entry) if entry.prev_hash != prev_hash => Some(
+                    "does not follow the entry before, an entry was removed or inserted"
+                        .to_string(),
+                ),
+                Ok(entry) if entry.compute_hash() != entry.hash => {
+                    Some("the contents do not match the hash, the entry was modified".to_string())
+                }
+                Ok(entry) => {
+                    prev_hash = entry.hash;
+                    entries += 1;
+                    None
+                }
+            };
+
+            if let Some(problem) = problem {
+                let line_number = index as u64 + 1;
+                return Ok(AuditVerification {
+                    valid: false,
+                    entries,
+                    first_invalid: Some(line_number),
+                    message: Some(format!("line {} {}", line_number, problem)),
+                });
+            }
+        }
+
+        Ok(AuditVerification {
+            valid: true,
+            entries,
+            first_invalid: None,
+            message: None,
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use axum::http::{HeaderName, HeaderValue};
+use headers::{Error, Header};
+
+static X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// The `X-API-Key` request header.
+#[derive(Debug, Clone)]
+pub struct ApiKey(pub String);
+
+impl Header for ApiKey {
+    fn name() -> &'static HeaderName {
+        &X_API_KEY
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values.next().ok_or_else(Error::invalid)?;
+        let key = value.to_str().map_err(|_| Error::invalid())?.trim();
+        if key.is_empty() {
+            return Err(Error::invalid());
+        }
+        Ok(ApiKey(key.to_string()))
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(value));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use super::{Role, WriteScope};
+use crate::config::AuthConfig;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    ApiKey,
+    Jwt,
+    Anonymous, // Authentication is disabled and no credentials were sent
+    Internal,  // Done by the service itself, such as a reload on a config file change
+}
+
+/// The caller of a request and what they may do, as established by the authentication
+/// middleware.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub method: AuthMethod,
+    pub role: Role,
+    pub scope: WriteScope,
+    pub address: Option<IpAddr>, // Where the request came from
+}
+
+impl Identity {
+    /// A caller without credentials while authentication is disabled. They may only read
+    /// unless anonymous access is allowed explicitly.
+    pub fn anonymous() -> Self {
+        Identity {
+            name: "anonymous".to_string(),
+            method: AuthMethod::Anonymous,
+            role: Role::Viewer,
+            scope: WriteScope::default(),
+            address: None,
+        }
+    }
+
+    pub fn internal(name: &str) -> Self {
+        Identity {
+            name: name.to_string(),
+            method: AuthMethod::Internal,
+            role: Role::Admin,
+            ..Identity::anonymous()
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.method {
+            AuthMethod::ApiKey => write!(f, "{} (API key)", self.name),
+            AuthMethod::Jwt => write!(f, "{} (JWT)", self.name),
+            AuthMethod::Anonymous => write!(f, "{}", self.name),
+            AuthMethod::Internal => write!(f, "{} (internal)", self.name),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    role: Role,
+    #[serde(flatten)]
+    scope: WriteScope, // `plcs` and `tags` claims
+}
+
+#[derive(Clone)]
+struct ConfiguredKey {
+    sha256: Vec<u8>,
+    identity: Identity,
+}
+
+#[derive(Clone)]
+struct Keys {
+    enabled: bool,
+    allow_anonymous: bool,
+    api_keys: Vec<ConfiguredKey>,
+    hs256: Option<DecodingKey>,
+    rs256: Option<DecodingKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway_s: u64,
+}
+
+/// Checks API keys and bearer tokens against the configured keys. The keys can be
+/// swapped while requests are served.
+#[derive(Clone)]
+pub struct Authenticator {
+    keys: Arc<RwLock<Keys>>,
+}
+
+impl Authenticator {
+    /// Loads the keys of a validated configuration, reading the RS256 public key file.
+    pub fn new(config: &AuthConfig) -> Result<Self, anyhow::Error> {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|key| {
+                Ok(ConfiguredKey {
+                    sha256: hex::decode(&key.sha256)?,
+                    identity: Identity {
+                        name: key.name.clone(),
+                        method: AuthMethod::ApiKey,
+                        role: key.role,
+                        scope: WriteScope {
+                            plcs: key.plcs.clone(),
+                            tags: key.tags.clone(),
+                        },
+                        address: None,
+                    },
+                })
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+        let hs256 = config
+            .jwt
+            .hs256_secret
+            .as_ref()
+            .map(|secret| DecodingKey::from_secret(secret.as_bytes()));
+        let rs256 = match &config.jwt.rs256_public_key {
+            Some(path) => {
+                let pem = std::fs::read(path).map_err(|e| {
+                    anyhow::anyhow!("Can't read RS256 public key {:?}: {}", path, e)
+                })?;
+                let key = DecodingKey::from_rsa_pem(&pem)
+                    .map_err(|e| anyhow::anyhow!("Invalid RS256 public key {:?}: {}", path, e))?;
+                Some(key)
+            }
+            None => None,
+        };
+
+        Ok(Authenticator {
+            keys: Arc::new(RwLock::new(Keys {
+                enabled: config.enabled,
+                allow_anonymous: config.allow_anonymous,
+                api_keys,
+                hs256,
+                rs256,
+                issuer: config.jwt.issuer.clone(),
+                audience: config.jwt.audience.clone(),
+                leeway_s: config.jwt.leeway_s,
+            })),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.keys.read().unwrap().enabled
+    }
+
+    /// Whether callers without credentials may do everything, see `AuthConfig`.
+    pub fn allows_anonymous(&self) -> bool {
+        let keys = self.keys.read().unwrap();
+        !keys.enabled && keys.allow_anonymous
+    }
+
+    /// Switches to the keys of `other`.
+    pub fn reconfigure(&self, other: &Authenticator) {
+        let keys = other.keys.read().unwrap().clone();
+        *self.keys.write().unwrap() = keys;
+    }
+
+    /// Identifies the caller from an `X-API-Key` header or a bearer token. While
+    /// authentication is disabled callers without valid credentials are anonymous viewers,
+    /// or may do everything when anonymous access is allowed. Callers with valid
+    /// credentials are still named.
+    pub fn authenticate(
+        &self,
+        api_key: Option<&str>,
+        bearer: Option<&str>,
+    ) -> Result<Identity, anyhow::Error> {
+        let keys = self.keys.read().unwrap();
+        let result = match (api_key, bearer) {
+            (Some(api_key), _) => keys.check_api_key(api_key),
+            (None, Some(token)) => keys.check_token(token),
+            (None, None) => Err(anyhow::anyhow!("No API key or bearer token")),
+        };
+
+        if keys.enabled {
+            return result;
+        }
+        Ok(match result {
+            Ok(identity) if !keys.allow_anonymous => identity,
+            Ok(identity) => Identity {
+                name: identity.name,
+                method: identity.method,
+                role: Role::Admin,
+                ..Identity::anonymous()
+            },
+            Err(_) if keys.allow_anonymous => Identity {
+                role: Role::Admin,
+                ..Identity::anonymous()
+            },
+            Err(_) => Identity::anonymous(),
+        })
+    }
+}
+
+impl Keys {
+    fn check_api_key(&self, api_key: &str) -> Result<Identity, anyhow::Error> {
+        let hash = Sha256::digest(api_key.as_bytes());
+        self.api_keys
+            .iter()
+            .find(|key| constant_time_eq(&key.sha256, &hash))
+            .map(|key| key.identity.clone())
+            .ok_or_else(|| anyhow::anyhow!("Unknown API key"))
+    }
+
+    fn check_token(&self, token: &str) -> Result<Identity, anyhow::Error> {
+        let header =
+            decode_header(token).map_err(|e| anyhow::anyhow!("Invalid bearer token: {}", e))?;
+        let key = match header.alg {
+            Algorithm::HS256 => self.hs256.as_ref(),
+            Algorithm::RS256 => self.rs256.as_ref(),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Bearer tokens signed with {:?} are not accepted",
+                header.alg
+            )
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        validation.leeway = self.leeway_s;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = decode::<Claims>(token, key, &validation)
+            .map_err(|e| anyhow::anyhow!("Invalid bearer token: {}", e))?;
+        Ok(Identity {
+            name: data.claims.sub,
+            method: AuthMethod::Jwt,
+            role: data.claims.role,
+            scope: data.claims.scope,
+            address: None,
+        })
+    }
+}
+
+/// Compares without stopping at the first difference, the time taken says nothing about the key.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
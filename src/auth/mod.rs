@@ -0,0 +1,7 @@
+mod api_key_header;
+mod authenticator;
+mod permissions;
+
+pub use api_key_header::ApiKey;
+pub use authenticator::{AuthMethod, Authenticator, Identity};
+pub use permissions::{Role, WriteScope};
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a caller may do, each role including the ones before it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Viewer, // Reads values, status and health
+    Operator, // Enables the heater and writes tags
+    Engineer, // Stops and starts the CPU, changes connections, blocks and DBs
+    Admin,    // Reloads the configuration
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Engineer => "engineer",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The PLCs and tags a caller may write to. An empty list allows all of them, tag
+/// names ending in `*` match every tag starting with the rest.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct WriteScope {
+    #[serde(default)]
+    pub plcs: Vec<String>, // PLC addresses
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl WriteScope {
+    pub fn allows_plc(&self, address: &str) -> bool {
+        self.plcs.is_empty() || self.plcs.iter().any(|plc| plc == address)
+    }
+
+    pub fn allows_tag(&self, name: &str) -> bool {
+        self.tags.is_empty()
+            || self
+                .tags
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => pattern == name,
+                })
+    }
+}
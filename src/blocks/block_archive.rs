@@ -0,0 +1,219 @@
+use super::{BlockDetails, BlockKind};
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+    Full,
+    Single,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupManifest {
+    pub plc: String,
+    pub version: String,
+    pub created_at: NaiveDateTime,
+    pub kind: BackupKind,
+    pub blocks: Vec<BlockDetails>,
+    #[serde(default)]
+    pub db_images: Vec<i32>, // DBs whose current data was saved next to the block
+    pub failed: Vec<String>,
+}
+
+impl BackupManifest {
+    pub fn find_block(&self, kind: BlockKind, number: i32) -> Option<&BlockDetails> {
+        self.blocks
+            .iter()
+            .find(|info| info.block_type == Some(kind) && info.number == number)
+    }
+}
+
+/// A block uploaded from the PLC, ready to be archived.
+pub struct ArchivedBlock {
+    pub kind: BlockKind,
+    pub info: BlockDetails,
+    pub data: Vec<u8>,
+    pub db_image: Option<Vec<u8>>, // Actual DB contents as read with db_get
+}
+
+/// Keeps a PLC identifier safe to use as a directory name.
+pub fn plc_dir_name(plc: &str) -> String {
+    plc.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// On-disk program archive, laid out as `<root>/<plc>/<version>/<TYPE><number>.blk`
+/// (plus `DB<number>.dat` data images) with a `manifest.json` describing every version.
+#[derive(Debug, Clone)]
+pub struct BlockArchive {
+    pub root: PathBuf,
+    pub keep_versions: usize,
+}
+
+impl BlockArchive {
+    pub fn new(root: impl Into<PathBuf>, keep_versions: usize) -> Self {
+        BlockArchive {
+            root: root.into(),
+            keep_versions,
+        }
+    }
+
+    pub fn block_file_name(kind: BlockKind, number: i32) -> String {
+        format!("{}{}.blk", kind, number)
+    }
+
+    pub fn db_image_file_name(number: i32) -> String {
+        format!("DB{}.dat", number)
+    }
+
+    fn plc_dir(&self, plc: &str) -> PathBuf {
+        self.root.join(plc_dir_name(plc))
+    }
+
+    /// Writes the uploaded blocks as a new version and prunes the oldest versions.
+    pub fn store(
+        &self,
+        plc: &str,
+        kind: BackupKind,
+        blocks: Vec<ArchivedBlock>,
+        failed: Vec<String>,
+    ) -> Result<BackupManifest, anyhow::Error> {
+        let created_at = Local::now().naive_local();
+        let plc_dir = self.plc_dir(plc);
+
+        let mut version = created_at.format("%Y%m%d-%H%M%S").to_string();
+        let mut suffix = 1;
+        while plc_dir.join(&version).exists() {
+            suffix += 1;
+            version = format!("{}-{}", created_at.format("%Y%m%d-%H%M%S"), suffix);
+        }
+
+        let version_dir = plc_dir.join(&version);
+        fs::create_dir_all(&version_dir)?;
+
+        let mut details = Vec::with_capacity(blocks.len());
+        let mut db_images = Vec::new();
+        for block in blocks {
+            fs::write(
+                version_dir.join(Self::block_file_name(block.kind, block.info.number)),
+                block.data,
+            )?;
+            if let Some(image) = block.db_image {
+                fs::write(
+                    version_dir.join(Self::db_image_file_name(block.info.number)),
+                    image,
+                )?;
+                db_images.push(block.info.number);
+            }
+            details.push(block.info);
+        }
+
+        let manifest = BackupManifest {
+            plc: plc.to_string(),
+            version,
+            created_at,
+            kind,
+            blocks: details,
+            db_images,
+            failed,
+        };
+        fs::write(
+            version_dir.join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(&manifest)?,
+        )?;
+
+        self.prune(plc)?;
+        Ok(manifest)
+    }
+
+    /// Lists all archived versions of a PLC, oldest first.
+    pub fn list_versions(&self, plc: &str) -> Result<Vec<BackupManifest>, anyhow::Error> {
+        let plc_dir = self.plc_dir(plc);
+        if !plc_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut manifests = Vec::new();
+        for entry in fs::read_dir(plc_dir)? {
+            let manifest_path = entry?.path().join(MANIFEST_FILE);
+            match fs::read(&manifest_path) {
+                Ok(bytes) => match serde_json::from_slice::<BackupManifest>(&bytes) {
+                    Ok(manifest) => manifests.push(manifest),
+                    Err(e) => println!("** Skipping unreadable {:?}: {:?}", manifest_path, e),
+                },
+                Err(_) => continue,
+            }
+        }
+
+        manifests.sort_by_key(|manifest| manifest.created_at);
+        Ok(manifests)
+    }
+
+    pub fn find_version(&self, plc: &str, version: &str) -> Result<BackupManifest, anyhow::Error> {
+        self.list_versions(plc)?
+            .into_iter()
+            .find(|manifest| manifest.version == version)
+            .ok_or_else(|| anyhow::anyhow!("Backup version {} not found for {}", version, plc))
+    }
+
+    fn read_file(
+        &self,
+        plc: &str,
+        version: &str,
+        file_name: &str,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        if version.contains(['/', '\\']) || version.starts_with('.') {
+            anyhow::bail!("Invalid backup version: {}", version);
+        }
+        let path = self.plc_dir(plc).join(version).join(file_name);
+        fs::read(&path).map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))
+    }
+
+    pub fn read_block(
+        &self,
+        plc: &str,
+        version: &str,
+        kind: BlockKind,
+        number: i32,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        self.read_file(plc, version, &Self::block_file_name(kind, number))
+    }
+
+    pub fn read_db_image(
+        &self,
+        plc: &str,
+        version: &str,
+        number: i32,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        self.read_file(plc, version, &Self::db_image_file_name(number))
+    }
+
+    fn prune(&self, plc: &str) -> Result<(), anyhow::Error> {
+        if self.keep_versions == 0 {
+            return Ok(());
+        }
+
+        let versions = self.list_versions(plc)?;
+        if versions.len() <= self.keep_versions {
+            return Ok(());
+        }
+
+        let plc_dir = self.plc_dir(plc);
+        for manifest in &versions[..versions.len() - self.keep_versions] {
+            fs::remove_dir_all(plc_dir.join(&manifest.version))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use super::{ArchivedBlock, BackupKind, BackupManifest, BlockArchive, BlockKind};
+use crate::routes::AppState;
+use chrono::{Local, NaiveTime, TimeDelta};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+#[derive(Debug, Clone)]
+pub struct BlockBackup {
+    pub archive: BlockArchive,
+    pub run_at: NaiveTime, // Local time of the nightly backup
+}
+
+impl BlockBackup {
+    /// Uploads every program block into a new archive version.
+    /// Blocks that fail to upload are listed in the manifest instead of aborting the backup.
+    pub async fn backup_program(
+        state: &AppState,
+        archive: &BlockArchive,
+    ) -> Result<BackupManifest, anyhow::Error> {
+        let mut blocks = Vec::new();
+        let mut failed = Vec::new();
+
+        for kind in BlockKind::PROGRAM {
+            let numbers = match state.list_blocks_of_type(kind).await {
+                Ok(numbers) => numbers,
+                Err(e) => {
+                    failed.push(format!("{}: {:?}", kind, e));
+                    continue;
+                }
+            };
+
+            for number in numbers {
+                let number = number as i32;
+                match Self::upload(state, kind, number).await {
+                    Ok(block) => blocks.push(block),
+                    Err(e) => failed.push(format!("{}{}: {:?}", kind, number, e)),
+                }
+            }
+        }
+
+        archive.store(&state.plc_id(), BackupKind::Full, blocks, failed)
+    }
+
+    pub async fn backup_block(
+        state: &AppState,
+        archive: &BlockArchive,
+        kind: BlockKind,
+        number: i32,
+    ) -> Result<BackupManifest, anyhow::Error> {
+        let block = Self::upload(state, kind, number).await?;
+        archive.store(&state.plc_id(), BackupKind::Single, vec![block], Vec::new())
+    }
+
+    /// Uploads a block together with the current contents of DBs, which a block
+    /// upload alone does not capture.
+    async fn upload(
+        state: &AppState,
+        kind: BlockKind,
+        number: i32,
+    ) -> Result<ArchivedBlock, anyhow::Error> {
+        let info = state.get_block_info(kind, number).await?;
+        let data = state.upload_block(kind, number).await?;
+        let db_image = match kind {
+            BlockKind::Db => Some(state.read_db(number).await?),
+            _ => None,
+        };
+
+        Ok(ArchivedBlock {
+            kind,
+            info,
+            data,
+            db_image,
+        })
+    }
+
+    fn until_next_run(&self) -> Duration {
+        let now = Local::now().naive_local();
+        let mut next = now.date().and_time(self.run_at);
+        if next <= now {
+            next += TimeDelta::days(1);
+        }
+        (next - now).to_std().unwrap_or_default()
+    }
+
+    pub async fn run(self, app_state: Arc<Mutex<AppState>>) {
+        loop {
+            sleep(self.until_next_run()).await;
+
+            // Uploading the whole program takes a while, don't hold the settings meanwhile
+            let state = app_state.lock().await.clone();
+            match Self::backup_program(&state, &self.archive).await {
+                Ok(manifest) => println!(
+                    "** Nightly backup {} stored: {} blocks, {} failed",
+                    manifest.version,
+                    manifest.blocks.len(),
+                    manifest.failed.len()
+                ),
+                Err(e) => println!("** Nightly backup failed: {:?}", e),
+            }
+        }
+    }
+}
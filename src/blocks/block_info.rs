@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use snap7_rs::{BlockType, TS7BlockInfo};
+use std::fmt;
+use std::os::raw::c_char;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum BlockKind {
+    #[serde(alias = "ob")]
+    Ob,
+    #[serde(alias = "fb")]
+    Fb,
+    #[serde(alias = "fc")]
+    Fc,
+    #[serde(alias = "db")]
+    Db,
+    #[serde(alias = "sdb")]
+    Sdb,
+    #[serde(alias = "sfc")]
+    Sfc,
+    #[serde(alias = "sfb")]
+    Sfb,
+}
+
+impl BlockKind {
+    /// Block types that make up the user program and are included in a full backup.
+    pub const PROGRAM: [BlockKind; 5] = [
+        BlockKind::Ob,
+        BlockKind::Fb,
+        BlockKind::Fc,
+        BlockKind::Db,
+        BlockKind::Sdb,
+    ];
+
+    pub fn block_type(&self) -> BlockType {
+        match self {
+            BlockKind::Ob => BlockType::BlockOB,
+            BlockKind::Fb => BlockType::BlockFB,
+            BlockKind::Fc => BlockType::BlockFC,
+            BlockKind::Db => BlockType::BlockDB,
+            BlockKind::Sdb => BlockType::BlockSDB,
+            BlockKind::Sfc => BlockType::BlockSFC,
+            BlockKind::Sfb => BlockType::BlockSFB,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<BlockKind> {
+        match code {
+            0x38 => Some(BlockKind::Ob),
+            0x45 => Some(BlockKind::Fb),
+            0x43 => Some(BlockKind::Fc),
+            0x41 => Some(BlockKind::Db),
+            0x42 => Some(BlockKind::Sdb),
+            0x44 => Some(BlockKind::Sfc),
+            0x46 => Some(BlockKind::Sfb),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format!("{:?}", self).to_uppercase())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockDetails {
+    pub block_type: Option<BlockKind>,
+    pub number: i32,
+    pub language: i32,
+    pub flags: i32,
+    pub mc7_size: i32,
+    pub load_size: i32,
+    pub local_data: i32,
+    pub sbb_length: i32,
+    pub checksum: i32,
+    pub version: i32,
+    pub code_date: String,
+    pub interface_date: String,
+    pub author: String,
+    pub family: String,
+    pub header: String,
+}
+
+impl From<&TS7BlockInfo> for BlockDetails {
+    fn from(info: &TS7BlockInfo) -> Self {
+        BlockDetails {
+            block_type: BlockKind::from_code(info.BlkType),
+            number: info.BlkNumber,
+            language: info.BlkLang,
+            flags: info.BlkFlags,
+            mc7_size: info.MC7Size,
+            load_size: info.LoadSize,
+            local_data: info.LocalData,
+            sbb_length: info.SBBLength,
+            checksum: info.CheckSum,
+            version: info.Version,
+            code_date: c_chars_to_string(&info.CodeDate),
+            interface_date: c_chars_to_string(&info.IntfDate),
+            author: c_chars_to_string(&info.Author),
+            family: c_chars_to_string(&info.Family),
+            header: c_chars_to_string(&info.Header),
+        }
+    }
+}
+
+fn c_chars_to_string(chars: &[c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
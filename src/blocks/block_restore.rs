@@ -0,0 +1,196 @@
+use super::{db_diff::diff_bytes, BlockArchive, BlockDetails, BlockKind, ChangedRange};
+use crate::{auth::Identity, routes::AppState};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const PLC_STATUS_STOP: i32 = 0x04;
+const TOKEN_BYTES: usize = 32;
+
+/// Single-use tokens handed out by a dry run. The real operation is only executed
+/// when the caller who ran the dry run presents the token issued for exactly the
+/// same operation.
+#[derive(Debug, Clone)]
+pub struct ConfirmationTokens {
+    pub ttl: Duration,
+    pending: Arc<Mutex<HashMap<String, (String, Instant)>>>, // token -> (caller and operation, issued at)
+}
+
+impl ConfirmationTokens {
+    pub fn new(ttl: Duration) -> Self {
+        ConfirmationTokens {
+            ttl,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn operation_key(identity: &Identity, operation: &str) -> String {
+        format!("{:?}:{}:{}", identity.method, identity.name, operation)
+    }
+
+    pub async fn issue(
+        &self,
+        identity: &Identity,
+        operation: &str,
+    ) -> Result<String, anyhow::Error> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|e| anyhow::anyhow!("no random numbers for a confirmation token: {}", e))?;
+        let token = hex::encode(bytes);
+
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, (_, issued_at)| issued_at.elapsed() < self.ttl);
+        pending.insert(
+            token.clone(),
+            (Self::operation_key(identity, operation), Instant::now()),
+        );
+        Ok(token)
+    }
+
+    /// Consumes the token, returns whether it was issued to this caller for this operation.
+    pub async fn consume(&self, token: &str, identity: &Identity, operation: &str) -> bool {
+        let mut pending = self.pending.lock().await;
+        // Someone else presenting the token must not use it up
+        match pending.get(token) {
+            Some((issued_for, _)) if *issued_for == Self::operation_key(identity, operation) => {
+                pending
+                    .remove(token)
+                    .is_some_and(|(_, issued_at)| issued_at.elapsed() < self.ttl)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct DownloadPlan {
+    pub block: String,
+    pub version: String,
+    pub archived: Option<BlockDetails>,
+    pub current: Option<BlockDetails>,
+    pub replaces_existing: bool,
+    pub identical: bool,
+    pub size: usize,
+    pub requires_stop: bool,
+    pub cpu_in_stop: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DbRestorePlan {
+    pub db: i32,
+    pub version: String,
+    pub size: usize,
+    pub changed_bytes: usize,
+    pub changes: Vec<ChangedRange>,
+}
+
+/// Why a block download could not be planned.
+#[derive(Debug)]
+pub enum PlanError {
+    /// The version or the block is not in the archive.
+    NotArchived(anyhow::Error),
+    /// Reading the live block or the CPU state failed.
+    Plc(anyhow::Error),
+}
+
+pub struct BlockRestore;
+
+impl BlockRestore {
+    pub fn download_operation(plc: &str, version: &str, kind: BlockKind, number: i32) -> String {
+        format!("download:{}:{}:{}{}", plc, version, kind, number)
+    }
+
+    pub fn db_restore_operation(plc: &str, version: &str, number: i32) -> String {
+        format!("db_restore:{}:{}:DB{}", plc, version, number)
+    }
+
+    pub async fn cpu_in_stop(state: &AppState) -> Result<bool, anyhow::Error> {
+        Ok(state.get_plc_status().await? == PLC_STATUS_STOP)
+    }
+
+    /// Works out what downloading an archived block would do, without touching the PLC.
+    pub async fn plan_download(
+        state: &AppState,
+        archive: &BlockArchive,
+        version: &str,
+        kind: BlockKind,
+        number: i32,
+    ) -> Result<(DownloadPlan, Vec<u8>), PlanError> {
+        let plc = state.plc_id();
+        let manifest = archive
+            .find_version(&plc, version)
+            .map_err(PlanError::NotArchived)?;
+        let data = archive
+            .read_block(&plc, version, kind, number)
+            .map_err(PlanError::NotArchived)?;
+        let archived = manifest.find_block(kind, number).cloned();
+
+        // Only ask for the info of a block the PLC lists, so a failing read is a real failure
+        let listed = state
+            .list_blocks_of_type(kind)
+            .await
+            .map_err(PlanError::Plc)?;
+        let current = if u16::try_from(number).is_ok_and(|number| listed.contains(&number)) {
+            Some(
+                state
+                    .get_block_info(kind, number)
+                    .await
+                    .map_err(PlanError::Plc)?,
+            )
+        } else {
+            None
+        };
+        let identical = match (&archived, &current) {
+            (Some(archived), Some(current)) => {
+                archived.checksum == current.checksum && archived.load_size == current.load_size
+            }
+            _ => false,
+        };
+
+        let plan = DownloadPlan {
+            block: format!("{}{}", kind, number),
+            version: version.to_string(),
+            replaces_existing: current.is_some(),
+            archived,
+            current,
+            identical,
+            size: data.len(),
+            // Downloading blocks into a running CPU is never allowed here
+            requires_stop: true,
+            cpu_in_stop: Self::cpu_in_stop(state).await.map_err(PlanError::Plc)?,
+        };
+        Ok((plan, data))
+    }
+
+    pub async fn plan_db_restore(
+        state: &AppState,
+        archive: &BlockArchive,
+        version: &str,
+        number: i32,
+    ) -> Result<(DbRestorePlan, Vec<u8>), anyhow::Error> {
+        let plc = state.plc_id();
+        let image = archive.read_db_image(&plc, version, number)?;
+        let current = state.read_db(number).await?;
+
+        if current.len() != image.len() {
+            anyhow::bail!(
+                "DB{} is {} bytes on the PLC but the saved image has {} bytes",
+                number,
+                current.len(),
+                image.len()
+            );
+        }
+
+        let changes = diff_bytes(&current, &image);
+        let plan = DbRestorePlan {
+            db: number,
+            version: version.to_string(),
+            size: image.len(),
+            changed_bytes: changes.iter().map(|range| range.length).sum(),
+            changes,
+        };
+        Ok((plan, image))
+    }
+}
@@ -0,0 +1,252 @@
+use super::{block_archive::plc_dir_name, BlockDetails, BlockKind};
+use crate::routes::AppState;
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Duration;
+
+const CHANGE_CHANNEL_CAPACITY: usize = 16;
+
+/// The parts of a block's info that change when the block is modified.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockFingerprint {
+    pub checksum: i32,
+    pub load_size: i32,
+    pub mc7_size: i32,
+    pub code_date: String,
+    pub interface_date: String,
+}
+
+impl From<&BlockDetails> for BlockFingerprint {
+    fn from(info: &BlockDetails) -> Self {
+        BlockFingerprint {
+            checksum: info.checksum,
+            load_size: info.load_size,
+            mc7_size: info.mc7_size,
+            code_date: info.code_date.clone(),
+            interface_date: info.interface_date.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockSnapshot {
+    pub taken_at: NaiveDateTime,
+    pub blocks: BTreeMap<String, BlockFingerprint>, // Keyed by block name, e.g. "OB1"
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockModification {
+    pub block: String,
+    pub before: BlockFingerprint,
+    pub after: BlockFingerprint,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockChangeRecord {
+    pub plc: String,
+    pub detected_at: NaiveDateTime,
+    pub baseline_taken_at: NaiveDateTime,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<BlockModification>,
+}
+
+impl BlockChangeRecord {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// Whether both records report the same difference to the same baseline.
+    pub fn same_change(&self, other: &BlockChangeRecord) -> bool {
+        self.plc == other.plc
+            && self.baseline_taken_at == other.baseline_taken_at
+            && self.added == other.added
+            && self.removed == other.removed
+            && self.modified == other.modified
+    }
+}
+
+/// Periodically snapshots the block checksums of the PLC and reports any
+/// difference to the accepted baseline. Baselines and change records are
+/// kept under `<root>/<plc>/`, detected changes are also published to subscribers.
+#[derive(Debug, Clone)]
+pub struct BlockWatch {
+    pub root: PathBuf,
+    pub check_interval: Duration,
+    changes: broadcast::Sender<BlockChangeRecord>,
+}
+
+impl BlockWatch {
+    pub fn new(root: impl Into<PathBuf>, check_interval: Duration) -> Self {
+        let (changes, _) = broadcast::channel(CHANGE_CHANNEL_CAPACITY);
+        BlockWatch {
+            root: root.into(),
+            check_interval,
+            changes,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BlockChangeRecord> {
+        self.changes.subscribe()
+    }
+
+    fn plc_dir(&self, plc: &str) -> PathBuf {
+        self.root.join(plc_dir_name(plc))
+    }
+
+    pub async fn take_snapshot(state: &AppState) -> Result<BlockSnapshot, anyhow::Error> {
+        let mut blocks = BTreeMap::new();
+        for kind in BlockKind::PROGRAM {
+            for number in state.list_blocks_of_type(kind).await? {
+                let info = state.get_block_info(kind, number as i32).await?;
+                blocks.insert(format!("{}{}", kind, number), BlockFingerprint::from(&info));
+            }
+        }
+
+        Ok(BlockSnapshot {
+            taken_at: Local::now().naive_local(),
+            blocks,
+        })
+    }
+
+    pub fn compare(
+        plc: &str,
+        baseline: &BlockSnapshot,
+        current: &BlockSnapshot,
+    ) -> BlockChangeRecord {
+        let mut record = BlockChangeRecord {
+            plc: plc.to_string(),
+            detected_at: current.taken_at,
+            baseline_taken_at: baseline.taken_at,
+            added: Vec::new(),
+            removed: Vec::new(),
+            modified: Vec::new(),
+        };
+
+        for (block, after) in &current.blocks {
+            match baseline.blocks.get(block) {
+                None => record.added.push(block.clone()),
+                Some(before) if before != after => record.modified.push(BlockModification {
+                    block: block.clone(),
+                    before: before.clone(),
+                    after: after.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+        for block in baseline.blocks.keys() {
+            if !current.blocks.contains_key(block) {
+                record.removed.push(block.clone());
+            }
+        }
+
+        record
+    }
+
+    pub fn load_baseline(&self, plc: &str) -> Result<Option<BlockSnapshot>, anyhow::Error> {
+        let path = self.plc_dir(plc).join("baseline.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    pub fn save_baseline(&self, plc: &str, snapshot: &BlockSnapshot) -> Result<(), anyhow::Error> {
+        let dir = self.plc_dir(plc);
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join("baseline.json"),
+            serde_json::to_vec_pretty(snapshot)?,
+        )?;
+        Ok(())
+    }
+
+    fn append_record(&self, record: &BlockChangeRecord) -> Result<(), anyhow::Error> {
+        let dir = self.plc_dir(&record.plc);
+        fs::create_dir_all(&dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("changes.jsonl"))?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    /// Reads every change record stored for a PLC, oldest first.
+    pub fn load_records(&self, plc: &str) -> Result<Vec<BlockChangeRecord>, anyhow::Error> {
+        let path = self.plc_dir(plc).join("changes.jsonl");
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Takes a snapshot and returns its difference to the baseline. A difference not
+    /// recorded yet raises an alarm and is stored and published. The baseline stays as it is
+    /// until an operator accepts the current program, so the alarm stands until then.
+    pub async fn check(
+        &self,
+        state: &AppState,
+    ) -> Result<Option<BlockChangeRecord>, anyhow::Error> {
+        let plc = state.plc_id();
+        let current = Self::take_snapshot(state).await?;
+
+        let Some(baseline) = self.load_baseline(&plc)? else {
+            println!(
+                "** No block baseline for {}, storing the current program",
+                plc
+            );
+            self.save_baseline(&plc, &current)?;
+            return Ok(None);
+        };
+
+        let record = Self::compare(&plc, &baseline, &current);
+        if record.is_empty() {
+            return Ok(None);
+        }
+
+        // Already reported, don't repeat the alarm on every check
+        let last = self.load_records(&plc)?.pop();
+        if last.is_some_and(|last| last.same_change(&record)) {
+            return Ok(Some(record));
+        }
+
+        println!(
+            "** ALARM: PLC program changed on {}: {} added, {} removed, {} modified",
+            plc,
+            record.added.len(),
+            record.removed.len(),
+            record.modified.len()
+        );
+        self.append_record(&record)?;
+
+        // Nobody listening is fine, the record is on disk
+        let _ = self.changes.send(record.clone());
+
+        Ok(Some(record))
+    }
+
+    pub async fn run(self, app_state: Arc<Mutex<AppState>>) {
+        let mut interval = tokio::time::interval(self.check_interval);
+
+        loop {
+            interval.tick().await;
+            // The snapshot takes a while, don't keep others from the settings meanwhile
+            let state = app_state.lock().await.clone();
+            if let Err(e) = self.check(&state).await {
+                println!("** Failed to check PLC program for changes: {:?}", e);
+            }
+        }
+    }
+}
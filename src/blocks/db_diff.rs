@@ -0,0 +1,25 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChangedRange {
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Returns the byte ranges where `after` differs from `before`. Bytes beyond the
+/// shorter buffer count as changed.
+pub fn diff_bytes(before: &[u8], after: &[u8]) -> Vec<ChangedRange> {
+    let mut ranges: Vec<ChangedRange> = Vec::new();
+
+    for offset in 0..before.len().max(after.len()) {
+        if before.get(offset) == after.get(offset) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if range.offset + range.length == offset => range.length += 1,
+            _ => ranges.push(ChangedRange { offset, length: 1 }),
+        }
+    }
+
+    ranges
+}
@@ -0,0 +1,64 @@
+use super::block_archive::plc_dir_name;
+use chrono::Local;
+use std::fs;
+use std::path::PathBuf;
+
+/// Stored DB images used as compare references, laid out as `<root>/<plc>/DB<number>/<id>.bin`.
+#[derive(Debug, Clone)]
+pub struct DbSnapshots {
+    pub root: PathBuf,
+}
+
+impl DbSnapshots {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DbSnapshots { root: root.into() }
+    }
+
+    fn db_dir(&self, plc: &str, number: i32) -> PathBuf {
+        self.root
+            .join(plc_dir_name(plc))
+            .join(format!("DB{}", number))
+    }
+
+    pub fn save(&self, plc: &str, number: i32, data: &[u8]) -> Result<String, anyhow::Error> {
+        let dir = self.db_dir(plc, number);
+        fs::create_dir_all(&dir)?;
+
+        let now = Local::now().naive_local();
+        let mut id = now.format("%Y%m%d-%H%M%S").to_string();
+        let mut suffix = 1;
+        while dir.join(format!("{}.bin", id)).exists() {
+            suffix += 1;
+            id = format!("{}-{}", now.format("%Y%m%d-%H%M%S"), suffix);
+        }
+
+        fs::write(dir.join(format!("{}.bin", id)), data)?;
+        Ok(id)
+    }
+
+    pub fn load(&self, plc: &str, number: i32, id: &str) -> Result<Vec<u8>, anyhow::Error> {
+        if id.contains(['/', '\\']) || id.starts_with('.') {
+            anyhow::bail!("Invalid snapshot id: {}", id);
+        }
+        let path = self.db_dir(plc, number).join(format!("{}.bin", id));
+        fs::read(&path).map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))
+    }
+
+    /// Lists the snapshot ids of a DB, oldest first.
+    pub fn list(&self, plc: &str, number: i32) -> Result<Vec<String>, anyhow::Error> {
+        let dir = self.db_dir(plc, number);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids: Vec<String> = fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                (path.extension()? == "bin")
+                    .then(|| path.file_stem()?.to_str().map(String::from))?
+            })
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
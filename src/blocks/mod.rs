@@ -0,0 +1,14 @@
+mod block_archive;
+mod block_backup;
+mod block_info;
+mod block_restore;
+mod block_watch;
+mod db_diff;
+mod db_snapshots;
+pub use block_archive::{ArchivedBlock, BackupKind, BackupManifest, BlockArchive};
+pub use block_backup::BlockBackup;
+pub use block_info::{BlockDetails, BlockKind};
+pub use block_restore::{BlockRestore, ConfirmationTokens, DbRestorePlan, DownloadPlan, PlanError};
+pub use block_watch::{BlockChangeRecord, BlockWatch};
+pub use db_diff::{diff_bytes, ChangedRange};
+pub use db_snapshots::DbSnapshots;
@@ -0,0 +1,103 @@
+use crate::routes::AppState;
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+const MAX_HISTORY: usize = 100;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DriftSample {
+    pub measured_at: NaiveDateTime,
+    pub plc_time: NaiveDateTime,
+    pub drift_ms: i64,
+    pub resynchronised: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    pub check_interval: Duration,
+    pub drift_threshold_ms: i64,
+    pub history: Arc<Mutex<VecDeque<DriftSample>>>, // Shared drift history, newest last
+}
+
+impl ClockSync {
+    pub fn new(check_interval: Duration, drift_threshold_ms: i64) -> Self {
+        ClockSync {
+            check_interval,
+            drift_threshold_ms,
+            history: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_HISTORY))),
+        }
+    }
+
+    /// Host wall-clock time in the same (zone-less, local) form the PLC uses.
+    pub fn host_time() -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+
+    /// Reads the PLC clock and returns it together with its drift from the host clock.
+    /// The host reference is taken halfway through the request to cancel out the round trip.
+    pub async fn measure(state: &AppState) -> Result<(NaiveDateTime, i64), anyhow::Error> {
+        let before = Self::host_time();
+        let plc_time = state.get_plc_clock().await?;
+        let after = Self::host_time();
+
+        let host_time = before + (after - before) / 2;
+        Ok((plc_time, (plc_time - host_time).num_milliseconds()))
+    }
+
+    pub async fn record(&self, sample: DriftSample) {
+        let mut history = self.history.lock().await;
+        if history.len() == MAX_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+
+    /// Checks the drift once and re-synchronises the CPU when it exceeds the threshold.
+    pub async fn check(
+        &self,
+        app_state: &Arc<Mutex<AppState>>,
+    ) -> Result<DriftSample, anyhow::Error> {
+        let state = app_state.lock().await.clone();
+        let (plc_time, drift_ms) = Self::measure(&state).await?;
+
+        let mut resynchronised = false;
+        if drift_ms.abs() > self.drift_threshold_ms {
+            // The PLC clock only has second resolution, round to the nearest second
+            let target = Self::host_time() + TimeDelta::milliseconds(500);
+            match state.set_plc_clock(target).await {
+                Ok(_) => {
+                    println!(
+                        "** PLC clock drifted {} ms, re-synchronised to host time",
+                        drift_ms
+                    );
+                    resynchronised = true;
+                }
+                Err(e) => println!("** Failed to re-synchronise PLC clock: {:?}", e),
+            }
+        }
+
+        let sample = DriftSample {
+            measured_at: Self::host_time(),
+            plc_time,
+            drift_ms,
+            resynchronised,
+        };
+        self.record(sample.clone()).await;
+        Ok(sample)
+    }
+
+    pub async fn run(self, app_state: Arc<Mutex<AppState>>) {
+        let mut interval = tokio::time::interval(self.check_interval);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.check(&app_state).await {
+                println!("** Failed to check PLC clock drift: {:?}", e);
+            }
+        }
+    }
+}
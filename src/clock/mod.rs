@@ -0,0 +1,2 @@
+mod clock_sync;
+pub use clock_sync::{ClockSync, DriftSample};
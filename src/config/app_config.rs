@@ -0,0 +1,610 @@
+use crate::{
+    auth::Role,
+    plc::PoolConfig,
+    routes::PLCConfig,
+    tags::{Tag, TagTable, TagType},
+};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use validator::{Validate, ValidationError};
+
+/// Environment variables starting with this override config keys, `__` separating the
+/// levels: `SNAP7_HTTP__BIND` sets `http.bind`.
+pub const ENV_PREFIX: &str = "SNAP7_";
+/// Names the config file when `--config` is not given.
+pub const CONFIG_ENV: &str = "SNAP7_CONFIG";
+
+const TIME_FORMAT: &str = "%H:%M";
+
+/// Tags the heater control loop reads and writes.
+const HEATER_TAGS: [&str; 6] = [
+    "temp_ai",
+    "heater_enabled",
+    "heater_on",
+    "power_percentage",
+    "target_temp",
+    "current_temp",
+];
+
+/// Tags the heater control loop writes. The API refuses to write them, the loop would
+/// overwrite the value on its next tick.
+pub const HEATER_OUTPUT_TAGS: [&str; 5] = [
+    "heater_enabled",
+    "heater_on",
+    "power_percentage",
+    "target_temp",
+    "current_temp",
+];
+
+/// Startup configuration. Every key has a default, so an empty file is valid.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_heater_tags", skip_on_field_errors = false))]
+pub struct AppConfig {
+    #[validate(nested)]
+    pub http: HttpConfig,
+    #[validate(nested)]
+    pub plc: PLCConfig,
+    #[validate(nested)]
+    pub pool: PoolSettings,
+    #[validate(nested)]
+    pub tags: TagSettings,
+    #[validate(nested)]
+    pub heater: HeaterConfig,
+    pub logging: LoggingConfig,
+    #[validate(nested)]
+    pub storage: StorageConfig,
+    #[validate(nested)]
+    pub clock: ClockConfig,
+    pub state: StateConfig,
+    #[validate(nested)]
+    pub shutdown: ShutdownConfig,
+    #[validate(nested)]
+    pub auth: AuthConfig,
+    pub audit: AuditConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    #[validate(custom(function = "validate_socket_addr"))]
+    pub bind: String,
+    #[validate(nested)]
+    pub tls: Option<TlsConfig>, // Plain HTTP when omitted
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            bind: "0.0.0.0:3000".to_string(),
+            tls: None,
+        }
+    }
+}
+
+/// PEM encoded certificate chain and private key.
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    #[validate(custom(function = "validate_file"))]
+    pub cert: PathBuf,
+    #[validate(custom(function = "validate_file"))]
+    pub key: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolSettings {
+    #[validate(range(max = 16))]
+    pub read_connections: usize,
+    #[validate(range(min = 1, max = 16))]
+    pub write_connections: usize,
+    #[validate(range(min = 1))]
+    pub request_timeout_ms: u64,
+    #[validate(range(min = 1))]
+    pub health_check_timeout_ms: u64,
+    #[validate(range(min = 100))]
+    pub health_check_interval_ms: u64,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            read_connections: 2,
+            write_connections: 1,
+            request_timeout_ms: 10000,
+            health_check_timeout_ms: 2000,
+            health_check_interval_ms: 5000,
+        }
+    }
+}
+
+impl PoolSettings {
+    /// Pool settings for the snap7 backend, without fault injection.
+    pub fn pool_config(&self) -> PoolConfig {
+        PoolConfig {
+            read_connections: self.read_connections,
+            write_connections: self.write_connections,
+            request_timeout: Duration::from_millis(self.request_timeout_ms),
+            health_check_timeout: Duration::from_millis(self.health_check_timeout_ms),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_tag_table"))]
+pub struct TagSettings {
+    #[validate(range(min = 10))]
+    pub scan_rate_ms: u64,
+    pub table: Vec<Tag>, // The heater DB1 layout when empty
+}
+
+impl Default for TagSettings {
+    fn default() -> Self {
+        TagSettings {
+            scan_rate_ms: 100,
+            table: Vec::new(),
+        }
+    }
+}
+
+impl TagSettings {
+    pub fn table(&self) -> TagTable {
+        if self.table.is_empty() {
+            TagTable::heater_db1()
+        } else {
+            TagTable {
+                tags: self.table.clone(),
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeaterConfig {
+    pub enabled: bool,
+    #[validate(range(min = -40.0, max = 100.0))]
+    pub target_temp: f32,
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    #[validate(range(min = 1.0))]
+    pub output_limit: f64,
+    #[validate(range(min = 10))]
+    pub update_interval_ms: u64,
+    #[validate(range(min = 1000))]
+    pub cycle_ms: u64, // Heater on/off period the power percentage applies to
+}
+
+impl Default for HeaterConfig {
+    fn default() -> Self {
+        HeaterConfig {
+            enabled: true,
+            target_temp: 30.0,
+            kp: 1.0,
+            ki: 0.1,
+            kd: 0.01,
+            output_limit: 100.0,
+            update_interval_ms: 100,
+            cycle_ms: 10000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    #[default]
+    Info, // Connections, backups, changes and errors
+    Debug, // Also every control cycle
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+}
+
+static DEBUG_LOGGING: AtomicBool = AtomicBool::new(false);
+
+impl LoggingConfig {
+    pub fn apply(&self) {
+        DEBUG_LOGGING.store(self.level == LogLevel::Debug, Ordering::Relaxed);
+    }
+}
+
+/// Whether per-cycle messages should be printed.
+pub fn debug_logging() -> bool {
+    DEBUG_LOGGING.load(Ordering::Relaxed)
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backups_dir: PathBuf,
+    #[validate(range(min = 1))]
+    pub backups_keep: usize,
+    #[validate(custom(function = "validate_time"))]
+    pub backup_at: String, // Local time of the nightly program backup, `HH:MM`
+    pub snapshots_dir: PathBuf,
+    pub block_watch_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backups_dir: PathBuf::from("backups"),
+            backups_keep: 30,
+            backup_at: "02:00".to_string(),
+            snapshots_dir: PathBuf::from("snapshots"),
+            block_watch_dir: PathBuf::from("block_watch"),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn backup_time(&self) -> NaiveTime {
+        NaiveTime::parse_from_str(&self.backup_at, TIME_FORMAT).unwrap_or_default()
+    }
+}
+
+/// How often the PLC clock is compared with the host clock and how far it may drift
+/// before it is set again.
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    #[validate(range(min = 1))]
+    pub check_interval_s: u64,
+    #[validate(range(min = 1))]
+    pub max_drift_ms: i64,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            check_interval_s: 60,
+            max_drift_ms: 2000,
+        }
+    }
+}
+
+/// What to bring back from the state store after a restart. Connection settings are
+/// always restored.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartPolicy {
+    #[default]
+    Disabled, // The heater starts disabled, with a fresh controller
+    Restore, // Heater enable state and controller integrator as before the restart
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateConfig {
+    pub dir: PathBuf,
+    pub start: StartPolicy,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            dir: PathBuf::from("data"),
+            start: StartPolicy::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    // How long to wait for open HTTP requests, and then for the PLC to take the safe state
+    #[validate(range(min = 100))]
+    pub timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { timeout_ms: 10000 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub file: PathBuf, // Append-only, one JSON entry per line
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            file: PathBuf::from("data/audit.jsonl"),
+        }
+    }
+}
+
+/// Who may call the API. API keys and bearer tokens are both accepted once enabled.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_auth", skip_on_field_errors = false))]
+pub struct AuthConfig {
+    pub enabled: bool, // Callers without credentials may only read when false
+    pub allow_anonymous: bool, // Lets them do everything while authentication is disabled
+    #[validate(nested)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    #[validate(nested)]
+    pub jwt: JwtConfig,
+}
+
+/// An API key, sent in the `X-API-Key` header. Only its SHA-256 is configured, so the
+/// config file does not hold the key itself: `printf %s "$KEY" | sha256sum`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    #[validate(length(min = 1, message = "name is required"))]
+    pub name: String, // Identity of the caller in logs
+    #[validate(custom(function = "validate_sha256"))]
+    pub sha256: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub plcs: Vec<String>, // PLC addresses the key may write to, all when empty
+    #[serde(default)]
+    pub tags: Vec<String>, // Tags the key may write, all when empty. `heater_*` matches a prefix
+}
+
+/// Bearer tokens, checked against locally configured keys only. The `sub` claim names the
+/// caller, the `role`, `plcs` and `tags` claims grant what an API key grants.
+#[derive(Deserialize, Clone, PartialEq, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    #[validate(length(min = 32, message = "the HS256 secret needs at least 32 characters"))]
+    pub hs256_secret: Option<String>,
+    #[validate(custom(function = "validate_file"))]
+    pub rs256_public_key: Option<PathBuf>, // PEM file
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub leeway_s: u64, // Clock difference allowed on `exp` and `nbf`
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            hs256_secret: None,
+            rs256_public_key: None,
+            issuer: None,
+            audience: None,
+            leeway_s: 60,
+        }
+    }
+}
+
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtConfig")
+            .field("hs256_secret", &self.hs256_secret.as_ref().map(|_| "***"))
+            .field("rs256_public_key", &self.rs256_public_key)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("leeway_s", &self.leeway_s)
+            .finish()
+    }
+}
+
+impl AppConfig {
+    /// Defaults, overridden by the TOML `file`, then by `SNAP7_*` variables in `env`, then
+    /// by `key=value` `overrides` from the command line. The result is validated.
+    pub fn load(
+        file: Option<&str>,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[String],
+    ) -> Result<Self, anyhow::Error> {
+        let mut table = match file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Can't read config file {}: {}", path, e))?;
+                toml::from_str(&contents)
+                    .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path, e))?
+            }
+            None => toml::Table::new(),
+        };
+
+        // The connection endpoint requires every PLC field, the config file only overrides them
+        let defaults = PLCConfig::default();
+        for (key, value) in [
+            ("plc.address", toml::Value::String(defaults.address)),
+            ("plc.rack", toml::Value::Integer(defaults.rack.into())),
+            ("plc.slot", toml::Value::Integer(defaults.slot.into())),
+        ] {
+            set_default(&mut table, key, value)?;
+        }
+
+        for (name, value) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if name == CONFIG_ENV {
+                continue;
+            }
+            let key = key.to_lowercase().replace("__", ".");
+            set_key(&mut table, &key, &value)
+                .map_err(|e| anyhow::anyhow!("Invalid environment variable {}: {}", name, e))?;
+        }
+
+        for entry in overrides {
+            let (key, value) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected key=value, got {}", entry))?;
+            set_key(&mut table, key.trim(), value.trim())
+                .map_err(|e| anyhow::anyhow!("Invalid override {}: {}", entry, e))?;
+        }
+
+        let config: AppConfig = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))?;
+        config
+            .validate()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))?;
+        Ok(config)
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        // Checked by validation
+        self.http
+            .bind
+            .parse()
+            .expect("http.bind is not a socket address")
+    }
+}
+
+/// Sets a dotted `key` in the table. Values are read as TOML (numbers, booleans, arrays)
+/// and fall back to plain strings, so `192.168.0.10` needs no quotes.
+fn set_key(table: &mut toml::Table, key: &str, raw: &str) -> Result<(), anyhow::Error> {
+    let value = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+
+    let (parent, last) = parent_table(table, key)?;
+    parent.insert(last.to_string(), value);
+    Ok(())
+}
+
+/// Sets a dotted `key` in the table unless a layer already did.
+fn set_default(
+    table: &mut toml::Table,
+    key: &str,
+    value: toml::Value,
+) -> Result<(), anyhow::Error> {
+    let (parent, last) = parent_table(table, key)?;
+    parent.entry(last).or_insert(value);
+    Ok(())
+}
+
+/// The table holding the last part of a dotted `key`, created as needed.
+fn parent_table<'a, 'k>(
+    table: &'a mut toml::Table,
+    key: &'k str,
+) -> Result<(&'a mut toml::Table, &'k str), anyhow::Error> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|last| !last.is_empty());
+    let Some(last) = last else {
+        anyhow::bail!("empty key");
+    };
+
+    let mut current = table;
+    for part in parts {
+        let entry = current
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        current = entry
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("{} is not a table", part))?;
+    }
+    Ok((current, last))
+}
+
+fn validate_socket_addr(bind: &str) -> Result<(), ValidationError> {
+    if bind.parse::<SocketAddr>().is_err() {
+        let mut error = ValidationError::new("bind");
+        error.message = Some(format!("{} is not an address:port", bind).into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_time(time: &str) -> Result<(), ValidationError> {
+    if NaiveTime::parse_from_str(time, TIME_FORMAT).is_err() {
+        let mut error = ValidationError::new("time");
+        error.message = Some(format!("{} is not a time of day (HH:MM)", time).into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_file(path: &Path) -> Result<(), ValidationError> {
+    if !path.is_file() {
+        let mut error = ValidationError::new("file");
+        error.message = Some(format!("{} does not exist", path.display()).into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_tag_table(settings: &TagSettings) -> Result<(), ValidationError> {
+    let mut names = HashSet::new();
+    for tag in &settings.table {
+        let problem = if !names.insert(tag.name.as_str()) {
+            Some("is defined twice")
+        } else if tag.db < 1 {
+            Some("needs a DB number of 1 or more")
+        } else if tag.data_type == TagType::Bool && tag.bit > 7 {
+            Some("has a bit number above 7")
+        } else if tag.scan_rate_ms == Some(0) {
+            Some("has a scan rate of 0")
+        } else {
+            None
+        };
+
+        if let Some(problem) = problem {
+            let mut error = ValidationError::new("tags");
+            error.message = Some(format!("tag {} {}", tag.name, problem).into());
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+fn validate_heater_tags(config: &AppConfig) -> Result<(), ValidationError> {
+    let table = config.tags.table();
+    let missing: Vec<&str> = HEATER_TAGS
+        .into_iter()
+        .filter(|name| table.find(name).is_none())
+        .collect();
+    if !missing.is_empty() {
+        let mut error = ValidationError::new("tags");
+        error.message =
+            Some(format!("the heater loop needs the tags {}", missing.join(", ")).into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_sha256(value: &str) -> Result<(), ValidationError> {
+    if value.len() != 64 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        let mut error = ValidationError::new("sha256");
+        error.message = Some("expected 64 hex digits".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_auth(auth: &AuthConfig) -> Result<(), ValidationError> {
+    let mut names = HashSet::new();
+    let problem = if let Some(key) = auth.api_keys.iter().find(|key| !names.insert(&key.name)) {
+        Some(format!("API key {} is defined twice", key.name))
+    } else if auth.enabled
+        && auth.api_keys.is_empty()
+        && auth.jwt.hs256_secret.is_none()
+        && auth.jwt.rs256_public_key.is_none()
+    {
+        Some("authentication is enabled without API keys or JWT keys, nobody could log in".to_string())
+    } else {
+        None
+    };
+
+    if let Some(problem) = problem {
+        let mut error = ValidationError::new("auth");
+        error.message = Some(problem.into());
+        return Err(error);
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+use super::{AppConfig, AuthConfig};
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::{Authenticator, Identity},
+    controllers::SharedState,
+    persistence::SavedConnection,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+/// Where the running configuration came from, so it can be loaded again.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    pub file: Option<String>,
+    pub overrides: Vec<String>, // `key=value`, applied over the file and the environment
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<AppConfig, anyhow::Error> {
+        AppConfig::load(self.file.as_deref(), std::env::vars(), &self.overrides)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let file = self.file.as_ref()?;
+        std::fs::metadata(file)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<&'static str>, // Sections now running with the new values
+    pub restart_required: Vec<&'static str>, // Changed, but only read at startup
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    /// The new configuration did not load or validate, nothing was changed.
+    Invalid(anyhow::Error),
+    /// The PLC did not accept the new connection settings, the previous ones were restored.
+    Rejected {
+        error: anyhow::Error,
+        rollback: Result<(), anyhow::Error>,
+    },
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Invalid(e) => write!(f, "{}", e),
+            ReloadError::Rejected {
+                error,
+                rollback: Ok(()),
+            } => write!(
+                f,
+                "Can't connect with the new PLC settings, reconnected with the previous ones. Reason: {:?}",
+                error
+            ),
+            ReloadError::Rejected {
+                error,
+                rollback: Err(rollback_error),
+            } => write!(
+                f,
+                "Can't connect with the new PLC settings ({:?}), reconnecting with the previous ones failed too: {:?}",
+                error, rollback_error
+            ),
+        }
+    }
+}
+
+/// What the audit log keeps of the auth settings: who may do what, but no key hashes or secrets.
+fn auth_summary(auth: &AuthConfig) -> Value {
+    json!({
+        "enabled": auth.enabled,
+        "allow_anonymous": auth.allow_anonymous,
+        "api_keys": auth
+            .api_keys
+            .iter()
+            .map(|key| json!({ "name": key.name, "role": key.role, "plcs": key.plcs, "tags": key.tags }))
+            .collect::<Vec<_>>(),
+        "jwt": {
+            "hs256": auth.jwt.hs256_secret.is_some(),
+            "rs256_public_key": auth.jwt.rs256_public_key,
+            "issuer": auth.jwt.issuer,
+            "audience": auth.jwt.audience,
+        },
+    })
+}
+
+/// Loads the configuration again and applies the sections that changed. Reconnecting to
+/// the PLC is the only step that can fail, so it runs first, without holding the state
+/// locks, and the previous settings are restored if it does; nothing else has been touched
+/// at that point. Every applied change except logging is audited as done by `identity`.
+pub async fn reload(
+    shared_state: &Arc<Mutex<SharedState>>,
+    identity: &Identity,
+) -> Result<ReloadReport, ReloadError> {
+    let mut full_state = shared_state.lock().await;
+    let mut new = full_state
+        .config_source
+        .load()
+        .map_err(ReloadError::Invalid)?;
+    let old = full_state.config.clone();
+    let mut report = ReloadReport::default();
+
+    // The listener, the pool and the storage tasks are set up once, keep running with the old values
+    if new.http != old.http {
+        report.restart_required.push("http");
+        new.http = old.http.clone();
+    }
+    if new.pool != old.pool {
+        report.restart_required.push("pool");
+        new.pool = old.pool.clone();
+    }
+    if new.storage != old.storage {
+        report.restart_required.push("storage");
+        new.storage = old.storage.clone();
+    }
+    if new.clock != old.clock {
+        report.restart_required.push("clock");
+        new.clock = old.clock.clone();
+    }
+    if new.state != old.state {
+        report.restart_required.push("state");
+        new.state = old.state.clone();
+    }
+    if new.audit != old.audit {
+        report.restart_required.push("audit");
+        new.audit = old.audit.clone();
+    }
+    // Whether the heater runs is up to the operator once started, see /heater/enable
+    if new.heater.enabled != old.heater.enabled {
+        report.restart_required.push("heater.enabled");
+        new.heater.enabled = old.heater.enabled;
+    }
+
+    // Read the new keys before anything changes, an unreadable key file rejects the reload
+    let authenticator = if new.auth != old.auth {
+        Some(Authenticator::new(&new.auth).map_err(ReloadError::Invalid)?)
+    } else {
+        None
+    };
+
+    if new.plc != old.plc {
+        // Switch the settings right away so replaced connections use them too, then
+        // reconnect without holding the locks so requests and the control loops carry on
+        let app_state = full_state.app_state.clone();
+        let audit = full_state.audit.clone();
+        let (previous, plc_state, event) = {
+            let mut app_state = app_state.lock().await;
+            let previous = app_state.clone();
+            let event = AuditEvent::new(AuditAction::ChangePlcConnectionSettings, &app_state.address)
+                .old_value(SavedConnection::from(&old.plc))
+                .new_value(SavedConnection::from(&new.plc));
+            app_state.update_config(new.plc.clone());
+            (previous, app_state.clone(), event)
+        };
+        drop(full_state);
+
+        let result = plc_state.connect_to_plc().await;
+        audit.record(identity, event, &result);
+        if let Err(error) = result {
+            // Back to the previous settings, including a session password set through the API
+            *app_state.lock().await = previous.clone();
+            let plc_state = previous;
+            let rollback = plc_state.connect_to_plc().await;
+            return Err(ReloadError::Rejected { error, rollback });
+        }
+        full_state = shared_state.lock().await;
+        // The edited config replaces settings changed through the API
+        if let Err(e) = full_state
+            .state_store
+            .update(|state| state.connection = None)
+        {
+            println!("** Failed to save runtime state: {:?}", e);
+        }
+        report.applied.push("plc");
+    }
+
+    let plc = full_state.app_state.lock().await.address.clone();
+
+    if new.tags != old.tags {
+        let table = new.tags.table();
+        full_state
+            .tag_poller
+            .reconfigure(table.clone(), Duration::from_millis(new.tags.scan_rate_ms));
+        full_state.tags = table;
+        full_state.audit.record(
+            identity,
+            AuditEvent::new(AuditAction::ReloadConfig, &plc)
+                .target("tags")
+                .old_value(&old.tags)
+                .new_value(&new.tags),
+            &Ok::<_, anyhow::Error>(()),
+        );
+        report.applied.push("tags");
+    }
+
+    if new.heater != old.heater {
+        let heater = &new.heater;
+        {
+            let mut pid = full_state.pid.lock().await;
+            pid.setpoint(heater.target_temp);
+            pid.output_limit = heater.output_limit;
+            pid.p(heater.kp, heater.output_limit)
+                .i(heater.ki, heater.output_limit)
+                .d(heater.kd, heater.output_limit);
+            if let Err(e) = full_state.state_store.save_pid(&pid, heater) {
+                println!("** Failed to save runtime state: {:?}", e);
+            }
+        }
+        full_state.audit.record(
+            identity,
+            AuditEvent::new(AuditAction::ChangeHeaterSettings, &plc)
+                .old_value(&old.heater)
+                .new_value(heater),
+            &Ok::<_, anyhow::Error>(()),
+        );
+        full_state.heater.target_temp = heater.target_temp;
+        report.applied.push("heater");
+    }
+
+    if new.logging != old.logging {
+        new.logging.apply();
+        report.applied.push("logging");
+    }
+
+    if let Some(authenticator) = authenticator {
+        full_state.authenticator.reconfigure(&authenticator);
+        full_state.audit.record(
+            identity,
+            AuditEvent::new(AuditAction::ReloadConfig, &plc)
+                .target("auth")
+                .old_value(auth_summary(&old.auth))
+                .new_value(auth_summary(&new.auth)),
+            &Ok::<_, anyhow::Error>(()),
+        );
+        report.applied.push("auth");
+    }
+
+    // Read when the shutdown starts
+    if new.shutdown != old.shutdown {
+        report.applied.push("shutdown");
+    }
+
+    full_state.config = new;
+    Ok(report)
+}
+
+/// Reloads the configuration whenever the config file is modified.
+pub async fn watch_config_file(shared_state: Arc<Mutex<SharedState>>, interval: Duration) {
+    let source = shared_state.lock().await.config_source.clone();
+    let Some(file) = source.file.clone() else {
+        return;
+    };
+    let mut last_modified = source.modified();
+
+    loop {
+        sleep(interval).await;
+
+        let modified = source.modified();
+        if modified.is_none() || modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        match reload(&shared_state, &Identity::internal("config file watcher")).await {
+            Ok(report) => println!(
+                "** Reloaded {}. Applied: {:?}, restart required: {:?}",
+                file, report.applied, report.restart_required
+            ),
+            Err(e) => println!("** Failed to reload {}: {}", file, e),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+mod app_config;
+mod config_reload;
+
+pub use app_config::{
+    debug_logging, AppConfig, AuthConfig, HeaterConfig, StartPolicy, CONFIG_ENV,
+    HEATER_OUTPUT_TAGS,
+};
+#[cfg(test)]
+pub use app_config::{AuditConfig, StateConfig, StorageConfig, TagSettings};
+pub use config_reload::{reload, watch_config_file, ConfigSource, ReloadError};
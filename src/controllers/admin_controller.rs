@@ -0,0 +1,55 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::{
+    auth::Identity,
+    config::{self, ReloadError},
+};
+
+use super::SharedState;
+
+#[derive(Serialize)]
+struct AdminMessageResponse {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    message: String,
+    applied: Vec<&'static str>,
+    restart_required: Vec<&'static str>,
+}
+
+/// Reloads the configuration file and applies what changed without restarting.
+pub async fn reload_config(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
+    println!("** Configuration reload requested by {}", identity);
+    match config::reload(&state, &identity).await {
+        Ok(report) => (
+            StatusCode::OK,
+            Json(ReloadResponse {
+                message: "Configuration reloaded".to_string(),
+                applied: report.applied,
+                restart_required: report.restart_required,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            let status_code = match e {
+                ReloadError::Invalid(_) => StatusCode::BAD_REQUEST,
+                ReloadError::Rejected { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status_code,
+                Json(AdminMessageResponse {
+                    message: format!("Configuration not reloaded: {}", e),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
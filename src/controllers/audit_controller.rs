@@ -0,0 +1,53 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::audit::AuditFilter;
+
+use super::SharedState;
+
+#[derive(Serialize)]
+struct AuditMessageResponse {
+    message: String,
+}
+
+/// Audit entries matching the query, oldest first.
+pub async fn get_audit_entries(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Query(filter): Query<AuditFilter>,
+) -> impl IntoResponse {
+    let audit = state.lock().await.audit.clone();
+
+    match audit.entries(&filter) {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(AuditMessageResponse {
+                message: format!("Failed to read the audit log: {:?}", e),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+/// Checks that no entry was modified, removed or inserted.
+pub async fn verify_audit_log(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let audit = state.lock().await.audit.clone();
+
+    match audit.verify() {
+        Ok(verification) => (StatusCode::OK, Json(verification)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(AuditMessageResponse {
+                message: format!("Failed to read the audit log: {:?}", e),
+            }),
+        )
+            .into_response(),
+    }
+}
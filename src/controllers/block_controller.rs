@@ -0,0 +1,450 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::Identity,
+    blocks::{
+        BackupManifest, BlockBackup, BlockChangeRecord, BlockDetails, BlockKind, BlockRestore,
+        BlockWatch, DownloadPlan, PlanError,
+    },
+};
+
+use super::{protection_error, SharedState};
+
+#[derive(Serialize)]
+struct BlockMessageResponse {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct BlockCount {
+    block_type: BlockKind,
+    count: i32,
+}
+
+#[derive(Serialize)]
+struct BlockListResponse {
+    blocks: Vec<BlockCount>,
+}
+
+#[derive(Serialize)]
+struct BlocksOfTypeResponse {
+    block_type: BlockKind,
+    numbers: Vec<u16>,
+}
+
+#[derive(Serialize)]
+struct BackupResponse {
+    message: String,
+    manifest: BackupManifest,
+}
+
+#[derive(Serialize)]
+struct BackupListResponse {
+    plc: String,
+    versions: Vec<BackupManifest>,
+}
+
+fn message_response(status_code: StatusCode, message: String) -> axum::response::Response {
+    (status_code, Json(BlockMessageResponse { message })).into_response()
+}
+
+/// Reports CPU protection rejections as 403 with an explanation, other PLC errors as 500.
+fn plc_error_response(context: String, e: &anyhow::Error) -> axum::response::Response {
+    match protection_error(e) {
+        Some(message) => message_response(StatusCode::FORBIDDEN, message),
+        None => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("{}: {:?}", context, e),
+        ),
+    }
+}
+
+pub async fn list_blocks(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    match state.list_blocks().await {
+        Ok(counts) => (
+            StatusCode::OK,
+            Json(BlockListResponse {
+                blocks: counts
+                    .into_iter()
+                    .map(|(block_type, count)| BlockCount { block_type, count })
+                    .collect(),
+            }),
+        )
+            .into_response(),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list blocks: {:?}", e),
+        ),
+    }
+}
+
+pub async fn list_blocks_of_type(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path(block_type): Path<BlockKind>,
+) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    match state.list_blocks_of_type(block_type).await {
+        Ok(numbers) => (
+            StatusCode::OK,
+            Json(BlocksOfTypeResponse {
+                block_type,
+                numbers,
+            }),
+        )
+            .into_response(),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list {} blocks: {:?}", block_type, e),
+        ),
+    }
+}
+
+pub async fn get_block_info(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path((block_type, number)): Path<(BlockKind, i32)>,
+) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    match state.get_block_info(block_type, number).await {
+        Ok(info) => (StatusCode::OK, Json::<BlockDetails>(info)).into_response(),
+        Err(e) => message_response(
+            StatusCode::NOT_FOUND,
+            format!("Failed to get info of {}{}: {:?}", block_type, number, e),
+        ),
+    }
+}
+
+pub async fn upload_block(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path((block_type, number)): Path<(BlockKind, i32)>,
+) -> impl IntoResponse {
+    // The upload takes a while, only copy what it needs under the locks
+    let (state, archive) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.block_backup.archive.clone())
+    };
+
+    match BlockBackup::backup_block(&state, &archive, block_type, number).await {
+        Ok(manifest) => (
+            StatusCode::OK,
+            Json(BackupResponse {
+                message: format!("{}{} uploaded", block_type, number),
+                manifest,
+            }),
+        )
+            .into_response(),
+        Err(e) => plc_error_response(format!("Failed to upload {}{}", block_type, number), &e),
+    }
+}
+
+pub async fn backup_program(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let (state, archive) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.block_backup.archive.clone())
+    };
+
+    match BlockBackup::backup_program(&state, &archive).await {
+        Ok(manifest) => (
+            StatusCode::OK,
+            Json(BackupResponse {
+                message: format!(
+                    "Program backed up: {} blocks, {} failed",
+                    manifest.blocks.len(),
+                    manifest.failed.len()
+                ),
+                manifest,
+            }),
+        )
+            .into_response(),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to back up program: {:?}", e),
+        ),
+    }
+}
+
+pub async fn list_backups(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let full_state = state.lock().await;
+    let plc = full_state.app_state.lock().await.plc_id();
+
+    match full_state.block_backup.archive.list_versions(&plc) {
+        Ok(versions) => {
+            (StatusCode::OK, Json(BackupListResponse { plc, versions })).into_response()
+        }
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list backups: {:?}", e),
+        ),
+    }
+}
+
+#[derive(Serialize)]
+struct BlockChangesResponse {
+    plc: String,
+    changes: Vec<BlockChangeRecord>,
+}
+
+#[derive(Serialize)]
+struct BlockCheckResponse {
+    message: String,
+    change: Option<BlockChangeRecord>,
+}
+
+pub async fn list_block_changes(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let full_state = state.lock().await;
+    let plc = full_state.app_state.lock().await.plc_id();
+
+    match full_state.block_watch.load_records(&plc) {
+        Ok(changes) => {
+            (StatusCode::OK, Json(BlockChangesResponse { plc, changes })).into_response()
+        }
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read block change records: {:?}", e),
+        ),
+    }
+}
+
+/// Streams program changes as server-sent events as soon as the watch detects them.
+pub async fn stream_block_changes(
+    State(state): State<Arc<Mutex<SharedState>>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let changes = state.lock().await.block_watch.subscribe();
+
+    let stream = BroadcastStream::new(changes).filter_map(|change| {
+        let change = change.ok()?;
+        Event::default()
+            .event("block_change")
+            .json_data(change)
+            .ok()
+            .map(Ok)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub async fn check_block_changes(
+    State(state): State<Arc<Mutex<SharedState>>>,
+) -> impl IntoResponse {
+    let (state, block_watch) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.block_watch.clone())
+    };
+
+    match block_watch.check(&state).await {
+        Ok(change) => (
+            StatusCode::OK,
+            Json(BlockCheckResponse {
+                message: match change {
+                    Some(_) => "PLC program differs from the accepted baseline".to_string(),
+                    None => "No program changes detected".to_string(),
+                },
+                change,
+            }),
+        )
+            .into_response(),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to check PLC program for changes: {:?}", e),
+        ),
+    }
+}
+
+/// Accepts the program currently on the PLC as the new baseline, e.g. after an approved change.
+/// The watch keeps reporting a change until it is accepted here.
+pub async fn reset_block_baseline(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
+    let (state, block_watch, audit) = {
+        let full_state = state.lock().await;
+        (
+            full_state.plc_state().await,
+            full_state.block_watch.clone(),
+            full_state.audit.clone(),
+        )
+    };
+
+    let plc = state.plc_id();
+    let previous = block_watch.load_baseline(&plc).ok().flatten();
+    let result = BlockWatch::take_snapshot(&state)
+        .await
+        .and_then(|snapshot| block_watch.save_baseline(&plc, &snapshot).map(|_| snapshot));
+    let mut event = AuditEvent::new(AuditAction::AcceptBlockBaseline, &state.address)
+        .old_value(&previous);
+    if let Ok(snapshot) = &result {
+        event = event.new_value(snapshot);
+    }
+    audit.record(&identity, event, &result);
+
+    match result {
+        Ok(_) => message_response(StatusCode::OK, "Block baseline updated".to_string()),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to update block baseline: {:?}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlockDownloadRequest {
+    pub version: String,
+    pub block_type: BlockKind,
+    pub number: i32,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub confirmation_token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BlockDownloadResponse {
+    message: String,
+    dry_run: bool,
+    plan: DownloadPlan,
+    confirmation_token: Option<String>,
+    backup_version: Option<String>,
+}
+
+/// Downloads an archived block to the PLC. A dry run reports what would change and
+/// issues the confirmation token the real download has to present.
+pub async fn download_block(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+    Json(request): Json<BlockDownloadRequest>,
+) -> impl IntoResponse {
+    let (state, archive, confirmations, audit) = {
+        let full_state = state.lock().await;
+        (
+            full_state.plc_state().await,
+            full_state.block_backup.archive.clone(),
+            full_state.confirmations.clone(),
+            full_state.audit.clone(),
+        )
+    };
+    let (kind, number) = (request.block_type, request.number);
+
+    let (plan, data) =
+        match BlockRestore::plan_download(&state, &archive, &request.version, kind, number).await {
+            Ok(planned) => planned,
+            Err(PlanError::NotArchived(e)) => {
+                return message_response(
+                    StatusCode::NOT_FOUND,
+                    format!("Can't download {}{}: {:?}", kind, number, e),
+                )
+            }
+            Err(PlanError::Plc(e)) => {
+                return plc_error_response(format!("Can't download {}{}", kind, number), &e)
+            }
+        };
+
+    let operation =
+        BlockRestore::download_operation(&state.plc_id(), &request.version, kind, number);
+
+    if request.dry_run {
+        let token = match confirmations.issue(&identity, &operation).await {
+            Ok(token) => token,
+            Err(e) => {
+                return message_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to issue a confirmation token: {:?}", e),
+                )
+            }
+        };
+        return (
+            StatusCode::OK,
+            Json(BlockDownloadResponse {
+                message: format!(
+                    "Dry run only, repeat with the confirmation token within {} seconds",
+                    confirmations.ttl.as_secs()
+                ),
+                dry_run: true,
+                plan,
+                confirmation_token: Some(token),
+                backup_version: None,
+            }),
+        )
+            .into_response();
+    }
+
+    let Some(token) = request.confirmation_token else {
+        return message_response(
+            StatusCode::PRECONDITION_REQUIRED,
+            "Run a dry run first and pass its confirmation_token".to_string(),
+        );
+    };
+    if !confirmations.consume(&token, &identity, &operation).await {
+        return message_response(
+            StatusCode::FORBIDDEN,
+            "Confirmation token is invalid, expired or issued for another caller or operation"
+                .to_string(),
+        );
+    }
+
+    if plan.requires_stop && !plan.cpu_in_stop {
+        return message_response(
+            StatusCode::CONFLICT,
+            "The CPU must be in STOP to download blocks".to_string(),
+        );
+    }
+
+    // Keep a copy of the block we are about to overwrite
+    let backup_version = if plan.replaces_existing {
+        match BlockBackup::backup_block(&state, &archive, kind, number).await {
+            Ok(manifest) => Some(manifest.version),
+            Err(e) => {
+                return message_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!(
+                        "Aborted, failed to back up the current {}{}: {:?}",
+                        kind, number, e
+                    ),
+                )
+            }
+        }
+    } else {
+        None
+    };
+
+    // The replaced block, if any, is kept as the backup version
+    let result = state.download_block(&data).await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::DownloadBlock, &state.address)
+            .target(format!("{}{}", kind, number))
+            .old_value(&backup_version)
+            .new_value(&request.version),
+        &result,
+    );
+
+    match result {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(BlockDownloadResponse {
+                message: format!("{}{} downloaded", kind, number),
+                dry_run: false,
+                plan,
+                confirmation_token: None,
+                backup_version,
+            }),
+        )
+            .into_response(),
+        Err(e) => plc_error_response(format!("Failed to download {}{}", kind, number), &e),
+    }
+}
@@ -0,0 +1,126 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::Identity,
+    clock::{ClockSync, DriftSample},
+};
+
+use super::{protection_error, SharedState};
+
+#[derive(Serialize)]
+struct ClockResponse {
+    plc_time: Option<NaiveDateTime>,
+    host_time: NaiveDateTime,
+    drift_ms: Option<i64>,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetClockRequest {
+    // When omitted the PLC is set to the current host time
+    pub date_time: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+struct DriftHistoryResponse {
+    drift_threshold_ms: i64,
+    check_interval_secs: u64,
+    samples: Vec<DriftSample>,
+}
+
+pub async fn get_plc_clock(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    match ClockSync::measure(&state).await {
+        Ok((plc_time, drift_ms)) => (
+            StatusCode::OK,
+            Json(ClockResponse {
+                plc_time: Some(plc_time),
+                host_time: ClockSync::host_time(),
+                drift_ms: Some(drift_ms),
+                message: "PLC clock read".to_string(),
+            }),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ClockResponse {
+                plc_time: None,
+                host_time: ClockSync::host_time(),
+                drift_ms: None,
+                message: format!("Failed to read PLC clock: {:?}", e),
+            }),
+        ),
+    }
+}
+
+pub async fn set_plc_clock(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+    Json(request): Json<SetClockRequest>,
+) -> impl IntoResponse {
+    let (state, audit) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.audit.clone())
+    };
+
+    let date_time = request.date_time.unwrap_or_else(ClockSync::host_time);
+
+    let result = state.set_plc_clock(date_time).await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::SetPlcClock, &state.address).new_value(date_time),
+        &result,
+    );
+
+    match result {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ClockResponse {
+                plc_time: Some(date_time),
+                host_time: ClockSync::host_time(),
+                drift_ms: None,
+                message: "PLC clock updated".to_string(),
+            }),
+        ),
+        Err(e) => {
+            let (status_code, message) = match protection_error(&e) {
+                Some(message) => (StatusCode::FORBIDDEN, message),
+                None => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to set PLC clock: {:?}", e),
+                ),
+            };
+            (
+                status_code,
+                Json(ClockResponse {
+                    plc_time: None,
+                    host_time: ClockSync::host_time(),
+                    drift_ms: None,
+                    message,
+                }),
+            )
+        }
+    }
+}
+
+pub async fn get_clock_drift_history(
+    State(state): State<Arc<Mutex<SharedState>>>,
+) -> impl IntoResponse {
+    let full_state = state.lock().await;
+    let clock_sync = &full_state.clock_sync;
+    let samples = clock_sync.history.lock().await.iter().cloned().collect();
+
+    (
+        StatusCode::OK,
+        Json(DriftHistoryResponse {
+            drift_threshold_ms: clock_sync.drift_threshold_ms,
+            check_interval_secs: clock_sync.check_interval.as_secs(),
+            samples,
+        }),
+    )
+}
@@ -0,0 +1,432 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::Identity,
+    blocks::{diff_bytes, BlockBackup, BlockKind, BlockRestore, DbRestorePlan},
+    tags::{Quality, TagValue},
+};
+
+use super::{protection_error, SharedState};
+
+#[derive(Serialize)]
+struct DbMessageResponse {
+    message: String,
+}
+
+fn message_response(status_code: StatusCode, message: String) -> axum::response::Response {
+    (status_code, Json(DbMessageResponse { message })).into_response()
+}
+
+/// Reports CPU protection rejections as 403 with an explanation, other PLC errors as 500.
+fn plc_error_response(context: String, e: &anyhow::Error) -> axum::response::Response {
+    match protection_error(e) {
+        Some(message) => message_response(StatusCode::FORBIDDEN, message),
+        None => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("{}: {:?}", context, e),
+        ),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DbRestoreRequest {
+    pub version: String,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub confirmation_token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DbRestoreResponse {
+    message: String,
+    dry_run: bool,
+    plan: DbRestorePlan,
+    confirmation_token: Option<String>,
+    backup_version: Option<String>,
+}
+
+/// Writes a saved DB image back to the PLC. Only the data is written, so the CPU may stay in RUN.
+pub async fn restore_db(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+    Path(number): Path<i32>,
+    Json(request): Json<DbRestoreRequest>,
+) -> impl IntoResponse {
+    let (state, archive, confirmations, audit) = {
+        let full_state = state.lock().await;
+        (
+            full_state.plc_state().await,
+            full_state.block_backup.archive.clone(),
+            full_state.confirmations.clone(),
+            full_state.audit.clone(),
+        )
+    };
+
+    let (plan, image) =
+        match BlockRestore::plan_db_restore(&state, &archive, &request.version, number).await {
+            Ok(planned) => planned,
+            Err(e) => {
+                return message_response(
+                    StatusCode::CONFLICT,
+                    format!("Can't restore DB{}: {:?}", number, e),
+                )
+            }
+        };
+
+    let operation = BlockRestore::db_restore_operation(&state.plc_id(), &request.version, number);
+
+    if request.dry_run {
+        let token = match confirmations.issue(&identity, &operation).await {
+            Ok(token) => token,
+            Err(e) => {
+                return message_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to issue a confirmation token: {:?}", e),
+                )
+            }
+        };
+        return (
+            StatusCode::OK,
+            Json(DbRestoreResponse {
+                message: format!(
+                    "Dry run only, repeat with the confirmation token within {} seconds",
+                    confirmations.ttl.as_secs()
+                ),
+                dry_run: true,
+                plan,
+                confirmation_token: Some(token),
+                backup_version: None,
+            }),
+        )
+            .into_response();
+    }
+
+    let Some(token) = request.confirmation_token else {
+        return message_response(
+            StatusCode::PRECONDITION_REQUIRED,
+            "Run a dry run first and pass its confirmation_token".to_string(),
+        );
+    };
+    if !confirmations.consume(&token, &identity, &operation).await {
+        return message_response(
+            StatusCode::FORBIDDEN,
+            "Confirmation token is invalid, expired or issued for another caller or operation"
+                .to_string(),
+        );
+    }
+
+    // Keep the current contents before overwriting them
+    let backup_version =
+        match BlockBackup::backup_block(&state, &archive, BlockKind::Db, number).await {
+            Ok(manifest) => manifest.version,
+            Err(e) => {
+                return message_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!(
+                        "Aborted, failed to back up the current DB{}: {:?}",
+                        number, e
+                    ),
+                )
+            }
+        };
+
+    // The replaced contents are kept as the backup version
+    let result = state.write_db(number, 0, &image).await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::RestoreDb, &state.address)
+            .target(format!("DB{}", number))
+            .old_value(&backup_version)
+            .new_value(&request.version),
+        &result,
+    );
+
+    match result {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(DbRestoreResponse {
+                message: format!("DB{} restored from {}", number, request.version),
+                dry_run: false,
+                plan,
+                confirmation_token: None,
+                backup_version: Some(backup_version),
+            }),
+        )
+            .into_response(),
+        Err(e) => plc_error_response(format!("Failed to write DB{}", number), &e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SnapshotQuery {
+    #[serde(default)]
+    pub format: SnapshotFormat,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    #[default]
+    Hex,
+    Binary,
+}
+
+#[derive(Serialize)]
+struct DbSnapshotResponse {
+    db: i32,
+    size: usize,
+    snapshot_id: Option<String>,
+    hex: String,
+}
+
+#[derive(Serialize)]
+struct DbSnapshotListResponse {
+    db: i32,
+    snapshots: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DbFillRequest {
+    pub value: u8,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DbCompareRequest {
+    // Either a stored snapshot id or a hex encoded image
+    pub snapshot_id: Option<String>,
+    pub hex: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChangedTag {
+    name: String,
+    before: Option<TagValue>,
+    after: Option<TagValue>,
+    before_quality: Quality,
+    after_quality: Quality,
+}
+
+#[derive(Serialize)]
+struct DbChange {
+    offset: usize,
+    length: usize,
+    before: String,
+    after: String,
+    tags: Vec<ChangedTag>,
+}
+
+#[derive(Serialize)]
+struct DbCompareResponse {
+    db: i32,
+    snapshot_size: usize,
+    live_size: usize,
+    changed_bytes: usize,
+    changes: Vec<DbChange>,
+}
+
+pub async fn get_db_snapshot(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path(number): Path<i32>,
+    Query(query): Query<SnapshotQuery>,
+) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    match state.read_db(number).await {
+        Ok(data) if query.format == SnapshotFormat::Binary => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            data,
+        )
+            .into_response(),
+        Ok(data) => (
+            StatusCode::OK,
+            Json(DbSnapshotResponse {
+                db: number,
+                size: data.len(),
+                snapshot_id: None,
+                hex: hex::encode(data),
+            }),
+        )
+            .into_response(),
+        Err(e) => plc_error_response(format!("Failed to read DB{}", number), &e),
+    }
+}
+
+/// Reads the DB and stores it as a reference for later compares.
+pub async fn save_db_snapshot(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path(number): Path<i32>,
+) -> impl IntoResponse {
+    let (state, db_snapshots) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.db_snapshots.clone())
+    };
+
+    let data = match state.read_db(number).await {
+        Ok(data) => data,
+        Err(e) => return plc_error_response(format!("Failed to read DB{}", number), &e),
+    };
+
+    match db_snapshots.save(&state.plc_id(), number, &data) {
+        Ok(id) => (
+            StatusCode::OK,
+            Json(DbSnapshotResponse {
+                db: number,
+                size: data.len(),
+                snapshot_id: Some(id),
+                hex: hex::encode(data),
+            }),
+        )
+            .into_response(),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to store DB{} snapshot: {:?}", number, e),
+        ),
+    }
+}
+
+pub async fn list_db_snapshots(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path(number): Path<i32>,
+) -> impl IntoResponse {
+    let full_state = state.lock().await;
+    let plc = full_state.app_state.lock().await.plc_id();
+
+    match full_state.db_snapshots.list(&plc, number) {
+        Ok(snapshots) => (
+            StatusCode::OK,
+            Json(DbSnapshotListResponse {
+                db: number,
+                snapshots,
+            }),
+        )
+            .into_response(),
+        Err(e) => message_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list DB{} snapshots: {:?}", number, e),
+        ),
+    }
+}
+
+pub async fn fill_db(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+    Path(number): Path<i32>,
+    Json(request): Json<DbFillRequest>,
+) -> impl IntoResponse {
+    let (state, audit) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.audit.clone())
+    };
+
+    let result = state.fill_db(number, request.value).await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::FillDb, &state.address)
+            .target(format!("DB{}", number))
+            .new_value(request.value),
+        &result,
+    );
+
+    match result {
+        Ok(_) => message_response(
+            StatusCode::OK,
+            format!("DB{} filled with 0x{:02X}", number, request.value),
+        ),
+        Err(e) => plc_error_response(format!("Failed to fill DB{}", number), &e),
+    }
+}
+
+/// Diffs the live DB against a stored snapshot, naming the tags in every changed range.
+pub async fn compare_db(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Path(number): Path<i32>,
+    Json(request): Json<DbCompareRequest>,
+) -> impl IntoResponse {
+    let (state, db_snapshots, tags) = {
+        let full_state = state.lock().await;
+        (
+            full_state.plc_state().await,
+            full_state.db_snapshots.clone(),
+            full_state.tags.clone(),
+        )
+    };
+
+    let snapshot = match (&request.snapshot_id, &request.hex) {
+        (Some(id), _) => db_snapshots.load(&state.plc_id(), number, id),
+        (None, Some(hex)) => hex::decode(hex.trim()).map_err(anyhow::Error::from),
+        (None, None) => Err(anyhow::anyhow!("Either snapshot_id or hex is required")),
+    };
+    let snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            return message_response(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid compare reference: {:?}", e),
+            )
+        }
+    };
+
+    let live = match state.read_db(number).await {
+        Ok(live) => live,
+        Err(e) => return plc_error_response(format!("Failed to read DB{}", number), &e),
+    };
+
+    let ranges = diff_bytes(&snapshot, &live);
+    let changes = ranges
+        .iter()
+        .map(|range| {
+            let end = range.offset + range.length;
+            let tags = tags
+                .tags_in(number, range.offset, range.length)
+                .map(|tag| {
+                    let (before, after) = (tag.decode(&snapshot), tag.decode(&live));
+                    ChangedTag {
+                        name: tag.name.clone(),
+                        before,
+                        after,
+                        before_quality: tag.assess(before),
+                        after_quality: tag.assess(after),
+                    }
+                })
+                .filter(|tag| tag.before != tag.after)
+                .collect();
+
+            DbChange {
+                offset: range.offset,
+                length: range.length,
+                before: hex::encode(
+                    snapshot
+                        .get(range.offset..end.min(snapshot.len()))
+                        .unwrap_or_default(),
+                ),
+                after: hex::encode(
+                    live.get(range.offset..end.min(live.len()))
+                        .unwrap_or_default(),
+                ),
+                tags,
+            }
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(DbCompareResponse {
+            db: number,
+            snapshot_size: snapshot.len(),
+            live_size: live.len(),
+            changed_bytes: ranges.iter().map(|range| range.length).sum(),
+            changes,
+        }),
+    )
+        .into_response()
+}
@@ -5,6 +5,8 @@ use axum::{
 };
 use serde::Serialize;
 
+use crate::plc::ConnectionStatus;
+
 use super::SharedState;
 
 #[derive(Serialize)]
@@ -25,37 +27,40 @@ pub struct PLCResponse {
     rack: i32,
     slot: i32,
     message: String,
+    connections: Vec<ConnectionStatus>,
 }
 
+/// Reports the connection health the pool tracked last, without talking to the PLC:
+/// the connections are kept up by the startup connect and the connection monitor.
 pub async fn plc_connection_check(
     State(state): State<Arc<Mutex<SharedState>>>,
 ) -> impl IntoResponse {
-    // Lock the state to get a mutable reference
-    let state = state.lock().await;
-    let app_state = state.app_state.lock().await;
-    // Attempt to connect to the PLC
-    let connection_result = app_state.connect_to_plc();
-
-    // Determine the response based on the connection result
-    let (status_code, message) = match connection_result {
-        Ok(_) => (
-            StatusCode::OK,
-            "Connected to PLC".to_string(),
-        ),
-        Err(e) => (
+    let app_state = state.lock().await.plc_state().await;
+    let connections = app_state.plc.status();
+    let down = connections
+        .iter()
+        .filter(|connection| !connection.healthy)
+        .count();
+
+    let (status_code, message) = match down {
+        0 => (StatusCode::OK, "Connected to PLC".to_string()),
+        down => (
             StatusCode::SERVICE_UNAVAILABLE,
-            format!("Error connecting to PLC: {:?}", e),
+            format!(
+                "{} of {} PLC connections are down",
+                down,
+                connections.len()
+            ),
         ),
     };
 
-    // Create the response struct with the current state information
     let response = PLCResponse {
-        address: app_state.address.clone(),
+        address: app_state.address,
         rack: app_state.rack,
         slot: app_state.slot,
         message,
+        connections,
     };
 
-    // Return the response
     (status_code, Json(response)).into_response()
 }
@@ -1,24 +1,89 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use crate::{heater::Heater, routes::AppState};
+use crate::{
+    audit::{AuditAction, AuditEvent, AuditLog},
+    auth::{Authenticator, Identity},
+    blocks::{BlockBackup, BlockWatch, ConfirmationTokens, DbSnapshots},
+    clock::ClockSync,
+    config::{AppConfig, ConfigSource},
+    heater::Heater,
+    lifecycle::BackgroundTasks,
+    persistence::StateStore,
+    routes::AppState,
+    tags::{TagPoller, TagTable},
+};
 use pid::Pid;
 
 pub struct SharedState {
     pub app_state: Arc<Mutex<AppState>>,
     pub heater: Heater,
     pub pid: Arc<Mutex<Pid<f64>>>,
+    pub clock_sync: ClockSync,
+    pub block_backup: BlockBackup,
+    pub block_watch: BlockWatch,
+    pub confirmations: ConfirmationTokens,
+    pub db_snapshots: DbSnapshots,
+    pub tags: TagTable,
+    pub tag_poller: TagPoller,
+    pub config: AppConfig, // As currently applied
+    pub config_source: ConfigSource,
+    pub state_store: StateStore,
+    pub tasks: BackgroundTasks,
+    pub authenticator: Authenticator,
+    pub audit: AuditLog,
 }
 
+impl SharedState {
+    /// Copies the connection settings and the pool handle, so PLC requests can be awaited
+    /// after the state locks are released instead of holding up every other request.
+    pub async fn plc_state(&self) -> AppState {
+        self.app_state.lock().await.clone()
+    }
+}
 
-pub async fn enable_heater(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+pub async fn enable_heater(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
     let state = state.lock().await;
+    println!("** Heater enabled by {}", identity);
+    let was_enabled = *state.heater.enabled.lock().await;
     state.heater.enable(&state.app_state, &state.pid).await;
+    save_heater_enabled(&state.state_store, true);
+    let plc = state.app_state.lock().await.address.clone();
+    state.audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::EnableHeater, &plc)
+            .old_value(was_enabled)
+            .new_value(true),
+        &Ok::<_, anyhow::Error>(()),
+    );
     (StatusCode::OK, Json("Heater enabled".to_string()))
 }
 
-pub async fn disable_heater(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+pub async fn disable_heater(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
     let state = state.lock().await;
+    println!("** Heater disabled by {}", identity);
+    let was_enabled = *state.heater.enabled.lock().await;
     state.heater.disable().await;
+    save_heater_enabled(&state.state_store, false);
+    let plc = state.app_state.lock().await.address.clone();
+    state.audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::DisableHeater, &plc)
+            .old_value(was_enabled)
+            .new_value(false),
+        &Ok::<_, anyhow::Error>(()),
+    );
     (StatusCode::OK, Json("Heater disabled".to_string()))
 }
+
+fn save_heater_enabled(state_store: &StateStore, enabled: bool) {
+    if let Err(e) = state_store.update(|state| state.heater_enabled = Some(enabled)) {
+        println!("** Failed to save runtime state: {:?}", e);
+    }
+}
@@ -1,7 +1,21 @@
 mod health_check_controller;
 mod plc_controller;
 mod heater_controller;
+mod clock_controller;
+mod block_controller;
+mod db_controller;
+mod protection_controller;
+mod tag_controller;
+mod admin_controller;
+mod audit_controller;
 pub use health_check_controller::*;
 pub use plc_controller::*;
 pub use heater_controller::*;
+pub use clock_controller::*;
+pub use block_controller::*;
+pub use db_controller::*;
+pub use protection_controller::*;
+pub use tag_controller::*;
+pub use admin_controller::*;
+pub use audit_controller::*;
 
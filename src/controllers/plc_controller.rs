@@ -1,11 +1,26 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
 use serde::Serialize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use validator::Validate;
 
-use crate::routes::PLCConfig;
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::Identity,
+    persistence::SavedConnection,
+    routes::PLCConfig,
+};
 
-use super::SharedState;
+use super::{protection_error, SharedState};
+
+fn operating_mode(status: i32) -> &'static str {
+    match status {
+        0x00 => "Status Unknown",
+        0x08 => "Running",
+        0x04 => "Stopped",
+        _ => "Unknown Status Code",
+    }
+}
 
 #[derive(Serialize)]
 struct PlcStatusResponse {
@@ -16,23 +31,15 @@ struct PlcStatusResponse {
 pub async fn get_plc_operating_mode(
     State(state): State<Arc<Mutex<SharedState>>>,
 ) -> impl IntoResponse {
-    // Lock the state to get access
-     let full_state = state.lock().await;
-    let state = full_state.app_state.lock().await;
+    let state = state.lock().await.plc_state().await;
 
-    match state.get_plc_status() {
+    match state.get_plc_status().await {
         Ok(status) => {
-            let message = match status {
-                0x00 => "Status Unknown".to_string(),
-                0x08 => "Running".to_string(),
-                0x04 => "Stopped".to_string(),
-                _ => "Unknown Status Code".to_string(),
-            };
             (
                 StatusCode::OK,
                 Json(PlcStatusResponse {
                     status_code: status,
-                    message,
+                    message: operating_mode(status).to_string(),
                 }),
             )
         }
@@ -56,17 +63,58 @@ struct ChangeConnectionResponse {
 
 pub async fn change_plc_connection_settings(
     State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
     Json(new_config): Json<PLCConfig>,
 ) -> impl IntoResponse {
-    // Lock the state to get mutable access
-     let full_state = state.lock().await;
-    let mut state = full_state.app_state.lock().await;
+    // Reject invalid settings before touching the current connection
+    if let Err(e) = new_config.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ChangeConnectionResponse {
+                message: format!("Invalid connection settings: {}", e),
+            }),
+        );
+    }
 
-    // Update the configuration
-    state.update_config(new_config);
+    if !identity.scope.allows_plc(&new_config.address) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ChangeConnectionResponse {
+                message: format!("{} may not connect to the PLC at {}", identity, new_config.address),
+            }),
+        );
+    }
+
+    let full_state = state.lock().await;
+    let new_connection = SavedConnection::from(&new_config);
+    println!(
+        "** PLC connection changed to {} (rack {}, slot {}) by {}",
+        new_config.address, new_config.rack, new_config.slot, identity
+    );
+
+    // Switch the settings right away so replaced connections use them too, then
+    // reconnect without holding the locks
+    let (state, old_connection, audit, state_store, configured) = {
+        let mut state = full_state.app_state.lock().await;
+        let old_connection = SavedConnection {
+            address: state.address.clone(),
+            rack: state.rack,
+            slot: state.slot,
+            connection: state.connection.clone(),
+        };
+        state.update_config(new_config.clone());
+        (
+            state.clone(),
+            old_connection,
+            full_state.audit.clone(),
+            full_state.state_store.clone(),
+            full_state.config.plc.clone(),
+        )
+    };
+    drop(full_state);
 
     // Attempt to disconnect
-    let disconnect_result = state.s7_client.disconnect();
+    let disconnect_result = state.disconnect().await;
     match disconnect_result {
         Ok(_) => (),
         Err(e) => {
@@ -75,7 +123,21 @@ pub async fn change_plc_connection_settings(
     };
 
     // Attempt to reconnect
-    let connection_result = state.connect_to_plc();
+    let connection_result = state.connect_to_plc().await;
+
+    // Keep the new settings across restarts, but only once they are known to work
+    if connection_result.is_ok() {
+        if let Err(e) = state_store.save_connection(&new_config, &configured) {
+            println!("** Failed to save runtime state: {:?}", e);
+        }
+    }
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::ChangePlcConnectionSettings, &old_connection.address)
+            .old_value(&old_connection)
+            .new_value(&new_connection),
+        &connection_result,
+    );
 
     let (status_code, response) = match connection_result {
         Ok(_) => (
@@ -95,12 +157,25 @@ pub async fn change_plc_connection_settings(
     (status_code, response)
 }
 
-pub async fn stop_plc(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
-    // Lock the state to get access
-    let full_state = state.lock().await;
-    let state = full_state.app_state.lock().await;
+pub async fn stop_plc(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
+    println!("** PLC stop requested by {}", identity);
+    let (state, audit) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.audit.clone())
+    };
+    let before = state.get_plc_status().await.ok().map(operating_mode);
     // Attempt to stop the PLC
-    let result = state.s7_client.plc_stop();
+    let result = state.stop_plc().await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::StopPlc, &state.address)
+            .old_value(before)
+            .new_value(operating_mode(0x04)),
+        &result,
+    );
 
     // Determine the response based on the result
     let (status_code, response) = match result {
@@ -110,24 +185,35 @@ pub async fn stop_plc(State(state): State<Arc<Mutex<SharedState>>>) -> impl Into
                 message: "PLC Stopped".to_string(),
             }),
         ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ChangeConnectionResponse {
-                message: format!("Couldn't stop PLC. Reason: {:?}", e),
-            }),
-        ),
+        Err(e) => match protection_error(&e) {
+            Some(message) => (
+                StatusCode::FORBIDDEN,
+                Json(ChangeConnectionResponse { message }),
+            ),
+            None => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ChangeConnectionResponse {
+                    message: format!("Couldn't stop PLC. Reason: {:?}", e),
+                }),
+            ),
+        },
     };
 
     (status_code, response)
 }
 
-pub async fn hot_start(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
-    // Lock the state to get access
-     let full_state = state.lock().await;
-    let state = full_state.app_state.lock().await;
+pub async fn hot_start(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
+    println!("** PLC hot start requested by {}", identity);
+    let (state, audit) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.audit.clone())
+    };
 
     // Check the current PLC status
-    let status = match state.get_plc_status() {
+    let status = match state.get_plc_status().await {
         Ok(status) => status,
         Err(e) => {
             return (
@@ -152,7 +238,14 @@ pub async fn hot_start(State(state): State<Arc<Mutex<SharedState>>>) -> impl Int
     }
 
     // Attempt to start the PLC in hot mode
-    let result = state.s7_client.plc_hot_start();
+    let result = state.hot_start_plc().await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::HotStart, &state.address)
+            .old_value(operating_mode(status))
+            .new_value(operating_mode(0x08)),
+        &result,
+    );
 
     // Determine the response based on the result
     let (status_code, response) = match result {
@@ -162,22 +255,34 @@ pub async fn hot_start(State(state): State<Arc<Mutex<SharedState>>>) -> impl Int
                 message: "PLC Started - Mode: HOT".to_string(),
             }),
         ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ChangeConnectionResponse {
-                message: format!("Couldn't start PLC. Reason: {:?}", e),
-            }),
-        ),
+        Err(e) => match protection_error(&e) {
+            Some(message) => (
+                StatusCode::FORBIDDEN,
+                Json(ChangeConnectionResponse { message }),
+            ),
+            None => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ChangeConnectionResponse {
+                    message: format!("Couldn't start PLC. Reason: {:?}", e),
+                }),
+            ),
+        },
     };
 
     (status_code, response).into_response()
 }
 
-pub async fn cold_start(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
-    let full_state = state.lock().await;
-    let state = full_state.app_state.lock().await;
+pub async fn cold_start(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+) -> impl IntoResponse {
+    println!("** PLC cold start requested by {}", identity);
+    let (state, audit) = {
+        let full_state = state.lock().await;
+        (full_state.plc_state().await, full_state.audit.clone())
+    };
     // Check the current PLC status
-    let status = match state.get_plc_status() {
+    let status = match state.get_plc_status().await {
         Ok(status) => status,
         Err(e) => {
             return (
@@ -201,8 +306,15 @@ pub async fn cold_start(State(state): State<Arc<Mutex<SharedState>>>) -> impl In
             .into_response();
     }
 
-    // Attempt to stop the PLC
-    let result = state.s7_client.plc_cold_start();
+    // Attempt to cold start the PLC
+    let result = state.cold_start_plc().await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::ColdStart, &state.address)
+            .old_value(operating_mode(status))
+            .new_value(operating_mode(0x08)),
+        &result,
+    );
 
     // Determine the response based on the result
     let (status_code, response) = match result {
@@ -212,13 +324,28 @@ pub async fn cold_start(State(state): State<Arc<Mutex<SharedState>>>) -> impl In
                 message: "PLC Started - Mode: Cold".to_string(),
             }),
         ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ChangeConnectionResponse {
-                message: format!("Couldn't start PLC. Reason: {:?}", e),
-            }),
-        ),
+        Err(e) => match protection_error(&e) {
+            Some(message) => (
+                StatusCode::FORBIDDEN,
+                Json(ChangeConnectionResponse { message }),
+            ),
+            None => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ChangeConnectionResponse {
+                    message: format!("Couldn't start PLC. Reason: {:?}", e),
+                }),
+            ),
+        },
     };
 
     (status_code, response).into_response()
 }
+
+/// Health of each pooled PLC connection with its queue depth and latency per request class.
+pub async fn get_scheduler_metrics(
+    State(state): State<Arc<Mutex<SharedState>>>,
+) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    (StatusCode::OK, Json(state.plc.status()))
+}
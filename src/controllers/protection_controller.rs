@@ -0,0 +1,146 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::Identity,
+};
+
+use super::SharedState;
+
+const NEED_PASSWORD: &str = "not authorized for current protection level";
+const INVALID_PASSWORD: &str = "Invalid password";
+
+/// Explains errors caused by the CPU protection, which snap7 only reports as error text.
+pub fn protection_error(e: &anyhow::Error) -> Option<String> {
+    let text = e.to_string();
+    if text.contains(NEED_PASSWORD) {
+        Some(format!(
+            "Rejected by the CPU protection level ({}). Set the session password in /plc/configure_connection or /plc/session_password",
+            text
+        ))
+    } else if text.contains(INVALID_PASSWORD) {
+        Some(format!(
+            "The CPU rejected the session password ({}). Check the password configured for this PLC",
+            text
+        ))
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct ProtectionResponse {
+    protection_level: u16,
+    description: String,
+    selector_level: u16,
+    password_level: u16,
+    mode_selector: String,
+    startup_switch: String,
+    session_password_set: bool,
+}
+
+#[derive(Serialize)]
+struct ProtectionMessageResponse {
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub struct SessionPasswordRequest {
+    // Clears the session password when omitted
+    pub password: Option<String>,
+}
+
+pub async fn get_protection(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let state = state.lock().await.plc_state().await;
+
+    match state.get_protection().await {
+        Ok(protection) => {
+            let description = match protection.protection_level {
+                1 => "No protection",
+                2 => "Write protection",
+                3 => "Read/write protection",
+                _ => "Unknown protection level",
+            };
+            let mode_selector = match protection.mode_selector {
+                1 => "RUN",
+                2 => "RUN-P",
+                3 => "STOP",
+                4 => "MRES",
+                _ => "Unknown",
+            };
+            let startup_switch = match protection.startup_switch {
+                1 => "CRST",
+                2 => "WRST",
+                _ => "Unknown",
+            };
+            (
+                StatusCode::OK,
+                Json(ProtectionResponse {
+                    protection_level: protection.protection_level,
+                    description: description.to_string(),
+                    selector_level: protection.selector_level,
+                    password_level: protection.password_level,
+                    mode_selector: mode_selector.to_string(),
+                    startup_switch: startup_switch.to_string(),
+                    session_password_set: state.password.is_some(),
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ProtectionMessageResponse {
+                message: format!("Failed to read protection level: {:?}", e),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn set_session_password(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+    Json(request): Json<SessionPasswordRequest>,
+) -> impl IntoResponse {
+    let (app_state, mut state, audit) = {
+        let full_state = state.lock().await;
+        (
+            full_state.app_state.clone(),
+            full_state.plc_state().await,
+            full_state.audit.clone(),
+        )
+    };
+    let clearing = request.password.is_none();
+    let was_set = state.password.is_some();
+
+    let result = state.set_session_password(request.password).await;
+    if result.is_ok() {
+        // Replaced connections log in with it from now on
+        app_state.lock().await.password = state.password.clone();
+    }
+    // Only whether a password is set, never the password
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::SetSessionPassword, &state.address)
+            .old_value(was_set)
+            .new_value(!clearing),
+        &result,
+    );
+
+    let (status_code, message) = match result {
+        Ok(_) if clearing => (StatusCode::OK, "Session password cleared".to_string()),
+        Ok(_) => (StatusCode::OK, "Session password set".to_string()),
+        Err(e) => match protection_error(&e) {
+            Some(message) => (StatusCode::FORBIDDEN, message),
+            None => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to apply session password: {:?}", e),
+            ),
+        },
+    };
+
+    (status_code, Json(ProtectionMessageResponse { message }))
+}
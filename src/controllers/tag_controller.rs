@@ -0,0 +1,136 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    auth::Identity,
+    config::HEATER_OUTPUT_TAGS,
+    tags::{TagType, TagValue},
+};
+
+use super::SharedState;
+
+#[derive(Serialize)]
+struct TagMessageResponse {
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub struct TagWriteRequest {
+    pub value: TagValue,
+}
+
+/// Last reported value, quality and timestamps of every polled tag.
+pub async fn get_tag_values(State(state): State<Arc<Mutex<SharedState>>>) -> impl IntoResponse {
+    let full_state = state.lock().await;
+
+    (StatusCode::OK, Json(full_state.tag_poller.values()))
+}
+
+/// Streams tag changes as server-sent events. Changes missed by a slow client are dropped.
+pub async fn stream_tag_changes(
+    State(state): State<Arc<Mutex<SharedState>>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let changes = state.lock().await.tag_poller.subscribe();
+
+    let stream = BroadcastStream::new(changes).filter_map(|change| {
+        let change = change.ok()?;
+        Event::default()
+            .event("tag_change")
+            .json_data(change)
+            .ok()
+            .map(Ok)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Writes one tag to the PLC, if the caller may write it and the heater loop does not own it.
+pub async fn write_tag(
+    State(state): State<Arc<Mutex<SharedState>>>,
+    Extension(identity): Extension<Identity>,
+    Path(name): Path<String>,
+    Json(request): Json<TagWriteRequest>,
+) -> impl IntoResponse {
+    let response = |status_code, message| (status_code, Json(TagMessageResponse { message }));
+
+    if !identity.scope.allows_tag(&name) {
+        println!("** Denied: {} may not write tag {}", identity, name);
+        return response(
+            StatusCode::FORBIDDEN,
+            format!("{} may not write tag {}", identity, name),
+        );
+    }
+
+    let (tag_poller, audit, plc, address) = {
+        let full_state = state.lock().await;
+        let app_state = full_state.app_state.lock().await;
+        (
+            full_state.tag_poller.clone(),
+            full_state.audit.clone(),
+            app_state.plc.clone(),
+            app_state.address.clone(),
+        )
+    };
+    let Some(tag) = tag_poller.table().find(&name).cloned() else {
+        return response(StatusCode::NOT_FOUND, format!("Unknown tag {}", name));
+    };
+    if HEATER_OUTPUT_TAGS.contains(&name.as_str()) {
+        return response(
+            StatusCode::CONFLICT,
+            format!(
+                "Tag {} is written by the heater control loop, change the heater settings instead",
+                name
+            ),
+        );
+    }
+
+    // Whole numbers arrive as integers in JSON
+    let value = match (tag.data_type, request.value) {
+        (TagType::Real, TagValue::Int(value)) => TagValue::Real(value as f32),
+        (_, value) => value,
+    };
+    let fits = match (tag.data_type, value) {
+        (TagType::Bool, TagValue::Bool(_)) => true,
+        (TagType::Bool, _) => false,
+        _ => tag.encode(value).is_some(),
+    };
+    if !fits {
+        return response(
+            StatusCode::BAD_REQUEST,
+            format!("Value {:?} does not fit tag {} ({:?})", value, name, tag.data_type),
+        );
+    }
+
+    println!("** Tag {} set to {:?} by {}", name, value, identity);
+    let old_value = tag_poller.value(&name);
+    let result = tag_poller.write(&plc, &name, value).await;
+    audit.record(
+        &identity,
+        AuditEvent::new(AuditAction::WriteTag, &address)
+            .target(&name)
+            .old_value(old_value)
+            .new_value(value),
+        &result,
+    );
+
+    match result {
+        Ok(_) => response(StatusCode::OK, format!("Tag {} written", name)),
+        Err(e) => response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Couldn't write tag {}. Reason: {:?}", name, e),
+        ),
+    }
+}
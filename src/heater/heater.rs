@@ -1,14 +1,19 @@
-use crate::routes::AppState;
+use crate::{
+    config::debug_logging,
+    plc::{PlcArea, PlcPool, Priority},
+    routes::AppState,
+};
 use pid::Pid;
-use snap7_rs::{AreaTable, WordLenTable};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
 
 #[derive(Debug, Clone)]
 pub struct Heater {
     pub target_temp:f32,
     pub enabled: Arc<Mutex<bool>>, // Shared state for enabled
+    pub task: Arc<Mutex<Option<JoinHandle<()>>>>, // The running heater loop
 }
 
 impl Heater {
@@ -18,13 +23,17 @@ impl Heater {
         // Start the background task
         let heater_clone = self.clone();
         let heater_clone_for_task = Arc::clone(&heater_clone.enabled);
-        let app_state_clone = Arc::clone(&app_state);
-        let pid_clone = Arc::clone(&pid);
-        tokio::spawn(async move {
+        let app_state_clone = Arc::clone(app_state);
+        let pid_clone = Arc::clone(pid);
+        let task = tokio::spawn(async move {
             heater_clone
                 .start_interval(heater_clone_for_task, app_state_clone, pid_clone)
                 .await;
         });
+        // Only one loop drives the output
+        if let Some(previous) = self.task.lock().await.replace(task) {
+            previous.abort();
+        }
     }
 
     pub async fn disable(&self) {
@@ -32,28 +41,45 @@ impl Heater {
         *enabled = false;
     }
 
-    fn water_present(&self, state: &AppState) -> bool {
-        let mut buffer: [u8; 1] = [0; 1];
-        state
-            .s7_client
-            .read_area(
-                AreaTable::S7AreaPE,
-                0,
-                0,
-                1,
-                WordLenTable::S7WLBit,
-                &mut buffer,
-            )
-            .unwrap();
-        buffer[0] == 1
+    /// Stops the heater loop without waiting for its window to end and switches the output off.
+    pub async fn stop(&self, plc: &PlcPool) -> Result<(), anyhow::Error> {
+        self.disable().await;
+        if let Some(task) = self.task.lock().await.take() {
+            task.abort();
+        }
+        self.set_output(plc, false).await
+    }
+
+    async fn water_present(&self, plc: &PlcPool) -> Result<bool, anyhow::Error> {
+        plc.read(Priority::Safety, |client| client.read_bit(PlcArea::Inputs, 0, 0))
+            .await
     }
 
-    pub fn get_temperature(&self, state: &AppState) -> f32 {
-        let mut buffer: [u8; 2] = [0; 2];
-        state.s7_client.db_read(1, 0, 2, &mut buffer).unwrap();
+    pub async fn get_temperature(&self, plc: &PlcPool) -> Result<f32, anyhow::Error> {
+        let buffer = plc
+            .read(Priority::Control, |client| {
+                let mut buffer: [u8; 2] = [0; 2];
+                client.read_area(PlcArea::Db(1), 0, &mut buffer)?;
+                Ok(buffer)
+            })
+            .await?;
 
         let raw_value = ((buffer[0] as u16) << 8) | (buffer[1] as u16);
-        self.scale_to_temperature(raw_value)
+        // Above the nominal range the reading is uncertain, don't control on it
+        if raw_value > 27648 {
+            anyhow::bail!("Temperature input is overrange (raw value {})", raw_value);
+        }
+        Ok(self.scale_to_temperature(raw_value))
+    }
+
+    /// Sets or clears the heater output bit (Q0.1).
+    /// Switching off is queued as a safety request so it is never held up behind other traffic.
+    async fn set_output(&self, plc: &PlcPool, on: bool) -> Result<(), anyhow::Error> {
+        let priority = if on { Priority::Control } else { Priority::Safety };
+        plc.write(priority, move |client| {
+            client.write_bit(PlcArea::Outputs, 0, 1, on)
+        })
+        .await
     }
 
     fn scale_to_temperature(&self, raw_value: u16) -> f32 {
@@ -81,75 +107,57 @@ impl Heater {
                 }
             }
 
-            let app_state = app_state.lock().await;
-            if !self.water_present(&*app_state) {
-                // If water is not present, skip this iteration
-                drop(app_state);
-                sleep(Duration::from_millis(100)).await;
-                continue;
+            // Only hold the state lock long enough to get the PLC handle
+            let plc = app_state.lock().await.plc.clone();
+            match self.water_present(&plc).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    // If water is not present, skip this iteration
+                    sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+                Err(e) => {
+                    println!("** Failed to read water present input: {:?}", e);
+                    sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
             }
 
-            let current_temperature = self.get_temperature(&*app_state);
-            println!("Target Temp.: {:.2}", self.target_temp);
-
-            println!("Temp.: {:.2}", current_temperature);
+            let current_temperature = match self.get_temperature(&plc).await {
+                Ok(temperature) => temperature,
+                Err(e) => {
+                    println!("** Failed to read temperature: {:?}", e);
+                    sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            if debug_logging() {
+                println!("Target Temp.: {:.2}", self.target_temp);
+                println!("Temp.: {:.2}", current_temperature);
+            }
 
             let mut pid = pid.lock().await;
             let output: pid::ControlOutput<f64> =
                 pid.next_control_output(current_temperature as f64);
-            println!("Power %: {:.2}", output.output);
-            let clamped_output = output.output.max(0.0).min(100.0);
+            if debug_logging() {
+                println!("Power %: {:.2}", output.output);
+            }
+            let clamped_output = output.output.clamp(0.0, 100.0);
             let on_duration = (clamped_output / 100.0 * 10000.0) as u64;
             let off_duration = 10000 - on_duration;
             drop(pid);
 
             // Turn on the heater
-            {
-                let mut byte_buff = [0u8; 1];
-                let _read_result = app_state.s7_client.read_area(
-                    snap7_rs::AreaTable::S7AreaPA,
-                    0,
-                    0,
-                    1, // Reading 1 byte
-                    WordLenTable::S7WLByte,
-                    &mut byte_buff,
-                );
-
-                byte_buff[0] |= 1 << 1;
-                let _write_result = app_state.s7_client.write_area(
-                    snap7_rs::AreaTable::S7AreaPA, // Adjust if needed
-                    0,                             // DB number - adjust if needed
-                    0,
-                    1, // Writing 1 byte
-                    WordLenTable::S7WLByte,
-                    &mut byte_buff, // Write the modified byte
-                );
+            if let Err(e) = self.set_output(&plc, true).await {
+                println!("** Failed to turn heater on: {:?}", e);
             }
 
             // Sleep for the on_duration
             sleep(Duration::from_millis(on_duration)).await;
 
             // Turn off the heater
-            {
-                let mut byte_buff = [0u8; 1];
-                let _read_result = app_state.s7_client.read_area(
-                    snap7_rs::AreaTable::S7AreaPA,
-                    0,
-                    0,
-                    1, // Reading 1 byte
-                    WordLenTable::S7WLByte,
-                    &mut byte_buff,
-                );
-
-                byte_buff[0] &= !(1 << 1);
-                let _write_result = app_state.s7_client.write_area(
-                    snap7_rs::AreaTable::S7AreaPA, // Adjust if needed
-                    0,                             // DB number - adjust if needed
-                    0,
-                    1, // Writing 1 byte
-                    WordLenTable::S7WLByte,
-                    &mut byte_buff, // Write the modified byte
-                );
+            if let Err(e) = self.set_output(&plc, false).await {
+                println!("** Failed to turn heater off: {:?}", e);
             }
 
             // Sleep for the off_duration
@@ -1,2 +1,3 @@
+#[allow(clippy::module_inception)]
 mod heater;
 pub use heater::Heater;
\ No newline at end of file
@@ -0,0 +1,22 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+/// The long running tasks of the application, aborted together on shutdown.
+#[derive(Debug, Clone, Default)]
+pub struct BackgroundTasks {
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl BackgroundTasks {
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let handle = tokio::spawn(task);
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    pub fn abort_all(&self) {
+        for handle in self.handles.lock().unwrap().drain(..) {
+            handle.abort();
+        }
+    }
+}
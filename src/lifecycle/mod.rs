@@ -0,0 +1,5 @@
+mod background_tasks;
+mod shutdown;
+
+pub use background_tasks::BackgroundTasks;
+pub use shutdown::{shutdown_signal, stop};
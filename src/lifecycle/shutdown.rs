@@ -0,0 +1,75 @@
+use crate::{
+    controllers::SharedState,
+    plc::{PlcArea, Priority},
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Resolves on Ctrl-C (SIGINT) or SIGTERM.
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => println!("** Received SIGINT, shutting down"),
+        _ = terminate => println!("** Received SIGTERM, shutting down"),
+    }
+}
+
+/// Leaves the plant in a safe state: stops the control loops and background tasks, switches
+/// the heater output and the heater_on tag off, saves the controller state and disconnects
+/// from the PLC. Every step is attempted, failures are reported together.
+pub async fn stop(shared_state: &Arc<Mutex<SharedState>>) -> Result<(), anyhow::Error> {
+    let full_state = shared_state.lock().await;
+    full_state.tasks.abort_all();
+    let plc = full_state.app_state.lock().await.plc.clone();
+    let mut errors = Vec::new();
+
+    if let Err(e) = full_state.heater.stop(&plc).await {
+        errors.push(format!("heater output: {:?}", e));
+    }
+
+    // Written even if the cache says it is off already
+    if let Some(tag) = full_state.tags.find("heater_on").cloned() {
+        let result = plc
+            .write(Priority::Safety, move |client| {
+                client.write_bit(PlcArea::Db(tag.db), tag.offset, tag.bit, false)
+            })
+            .await;
+        if let Err(e) = result {
+            errors.push(format!("heater_on: {:?}", e));
+        }
+    }
+
+    {
+        let pid = full_state.pid.lock().await;
+        if let Err(e) = full_state
+            .state_store
+            .save_pid(&pid, &full_state.config.heater)
+        {
+            errors.push(format!("runtime state: {:?}", e));
+        }
+    }
+
+    if let Err(e) = full_state.app_state.lock().await.disconnect().await {
+        errors.push(format!("disconnect: {:?}", e));
+    }
+
+    if !errors.is_empty() {
+        anyhow::bail!("Failed to stop cleanly: {}", errors.join(", "));
+    }
+    Ok(())
+}
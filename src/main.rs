@@ -2,11 +2,172 @@ mod routes;
 mod controllers;
 mod middlewares;
 mod heater;
+mod clock;
+mod blocks;
+mod tags;
+mod plc;
+mod simulator;
+mod config;
+mod persistence;
+mod lifecycle;
+mod auth;
+mod audit;
+#[cfg(test)]
+mod tests;
+
+use config::{ConfigSource, CONFIG_ENV};
+use plc::{FaultInjector, FaultScenario, Recording};
+use simulator::{PlcSimulator, ThermalConfig, ThermalModel};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_SIMULATOR_PORT: u16 = 1102;
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+}
+
+fn arg_values<'a>(args: &'a [String], name: &'a str) -> impl Iterator<Item = &'a String> {
+    args.windows(2)
+        .filter(move |pair| pair[0] == name)
+        .map(|pair| &pair[1])
+}
+
+/// Layers `--config <file.toml>` (or the `SNAP7_CONFIG` file), `SNAP7_*` environment variables,
+/// `--set <key>=<value>` and `--bind <address:port>` over the defaults.
+fn config_source(args: &[String]) -> ConfigSource {
+    let file = arg_value(args, "--config")
+        .cloned()
+        .or_else(|| std::env::var(CONFIG_ENV).ok());
+    let mut overrides: Vec<String> = arg_values(args, "--set").cloned().collect();
+    if let Some(bind) = arg_value(args, "--bind") {
+        overrides.push(format!("http.bind={}", bind));
+    }
+    if let Some(port) = simulator_port(args) {
+        overrides.push("plc.address=127.0.0.1".to_string());
+        overrides.push(format!("plc.port={}", port));
+    }
+    ConfigSource { file, overrides }
+}
+
+/// `--simulate [--simulator-port <port>] [--thermal-config <file.json>]` runs against an
+/// in-process PLC simulator with a thermal model of the tank instead of the CPU at 192.168.0.1.
+fn simulator_port(args: &[String]) -> Option<u16> {
+    if !args.iter().any(|arg| arg == "--simulate") {
+        return None;
+    }
+
+    let port = arg_value(args, "--simulator-port")
+        .map(|port| port.parse().expect("--simulator-port must be a port number"));
+    Some(port.unwrap_or(DEFAULT_SIMULATOR_PORT))
+}
 
 #[tokio::main]
 async fn main() {
-    let app = routes::create_routes().await;
+    let args: Vec<String> = std::env::args().collect();
+    let config_source = config_source(&args);
+    let config = match config_source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("** {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // `--check-config` validates the configuration and prints the effective values
+    if args.iter().any(|arg| arg == "--check-config") {
+        println!("{:#?}", config);
+        println!("** Configuration is valid");
+        return;
+    }
+    config.logging.apply();
+
+    // Kept alive for as long as the server runs
+    let _simulator = simulator_port(&args).map(|port| {
+        let simulator = Arc::new(
+            PlcSimulator::start("127.0.0.1", port).expect("failed to start PLC simulator"),
+        );
+        let thermal_config = match arg_value(&args, "--thermal-config") {
+            Some(path) => ThermalConfig::load(path).expect("failed to load thermal model config"),
+            None => ThermalConfig::default(),
+        };
+        tokio::spawn(ThermalModel::new(thermal_config).run(simulator.clone()));
+        simulator
+    });
+
+    // Debug mode: play a scripted fault scenario against every PLC request
+    let mut pool_config = config.pool.pool_config();
+    if let Some(path) = arg_value(&args, "--fault-scenario") {
+        let scenario = FaultScenario::load(path).expect("failed to load fault scenario");
+        println!("** Injecting PLC faults from {}", path);
+        pool_config.faults = Some(Arc::new(FaultInjector::new(scenario)));
+    }
+
+    // Capture PLC traffic to reproduce field issues later, or replay a capture without a PLC
+    if let Some(path) = arg_value(&args, "--replay") {
+        let recording = Recording::load(path).expect("failed to load PLC recording");
+        println!("** Replaying PLC traffic from {}", path);
+        pool_config.client = recording.player();
+    } else if let Some(path) = arg_value(&args, "--record") {
+        let recording = Recording::create(path).expect("failed to create PLC recording");
+        println!("** Recording PLC traffic to {}", path);
+        pool_config.client = recording.recorder(pool_config.client.clone());
+    }
+
+    let address = config.bind_address();
+    let tls = config.http.tls.clone();
+    let app = routes::create_routes(config, config_source, pool_config).await;
+
+    // On SIGINT/SIGTERM stop accepting connections and give open requests time to finish
+    let handle = Handle::new();
+    let shutdown_timeout = {
+        let (handle, state) = (handle.clone(), app.state.clone());
+        tokio::spawn(async move {
+            lifecycle::shutdown_signal().await;
+            let timeout = Duration::from_millis(state.lock().await.config.shutdown.timeout_ms);
+            handle.graceful_shutdown(Some(timeout));
+            timeout
+        })
+    };
+
+    let server = match tls {
+        Some(tls) => {
+            let rustls_config = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+                .await
+                .expect("failed to load TLS certificate");
+            println!("** Listening on https://{}", address);
+            axum_server::bind_rustls(address, rustls_config)
+                .handle(handle)
+                .serve(app.router.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+        None => {
+            println!("** Listening on http://{}", address);
+            axum_server::bind(address)
+                .handle(handle)
+                .serve(app.router.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+    };
+    if let Err(e) = server {
+        println!("** HTTP server failed: {:?}", e);
+    }
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Then leave the plant in a safe state before exiting. Without a signal the server failed
+    if !shutdown_timeout.is_finished() {
+        shutdown_timeout.abort();
+    }
+    let timeout = match shutdown_timeout.await {
+        Ok(timeout) => timeout,
+        Err(_) => Duration::from_millis(app.state.lock().await.config.shutdown.timeout_ms),
+    };
+    match tokio::time::timeout(timeout, lifecycle::stop(&app.state)).await {
+        Ok(Ok(())) => println!("** Heater off, disconnected from PLC"),
+        Ok(Err(e)) => println!("** {}", e),
+        Err(_) => println!("** Timed out putting the PLC in a safe state"),
+    }
 }
@@ -1,2 +1,6 @@
+mod require_authentication;
 mod require_plc_connection;
-pub use require_plc_connection::require_plc_connection;
\ No newline at end of file
+mod require_role;
+pub use require_authentication::require_authentication;
+pub use require_plc_connection::require_plc_connection;
+pub use require_role::{require_role, Access};
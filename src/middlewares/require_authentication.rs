@@ -0,0 +1,60 @@
+use crate::{
+    auth::{ApiKey, Authenticator},
+    config::debug_logging,
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Serialize;
+use std::net::SocketAddr;
+
+#[derive(Serialize)]
+struct AuthErrorResponse {
+    message: String,
+}
+
+/// Identifies the caller from `X-API-Key` or `Authorization: Bearer` and hands the
+/// `Identity` to the handlers as a request extension. Unknown callers get a 401.
+pub async fn require_authentication(
+    State(authenticator): State<Authenticator>,
+    api_key: Option<TypedHeader<ApiKey>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let api_key = api_key
+        .as_ref()
+        .map(|TypedHeader(ApiKey(key))| key.as_str());
+    let token = bearer.as_ref().map(|TypedHeader(bearer)| bearer.token());
+
+    match authenticator.authenticate(api_key, token) {
+        Ok(mut identity) => {
+            identity.address = connect_info.map(|ConnectInfo(address)| address.ip());
+            if debug_logging() {
+                println!("** {} {} by {}", request.method(), request.uri(), identity);
+            }
+            request.extensions_mut().insert(identity);
+            next.run(request).await
+        }
+        Err(e) => {
+            println!("** Rejected {} {}: {}", request.method(), request.uri(), e);
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                Json(AuthErrorResponse {
+                    message: format!("Authentication required: {}", e),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
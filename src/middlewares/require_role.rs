@@ -0,0 +1,78 @@
+use crate::{
+    auth::{Identity, Role},
+    routes::AppState,
+};
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Serialize)]
+struct AccessDeniedResponse {
+    message: String,
+}
+
+/// What a route group requires of the caller: a minimum role and, for groups that write
+/// to the PLC, the connected PLC in the caller's write scope.
+#[derive(Clone)]
+pub struct Access {
+    role: Role,
+    plc: Option<Arc<Mutex<AppState>>>,
+}
+
+impl Access {
+    pub fn new(role: Role) -> Self {
+        Access { role, plc: None }
+    }
+
+    pub fn writing_to(self, app_state: Arc<Mutex<AppState>>) -> Self {
+        Access {
+            plc: Some(app_state),
+            ..self
+        }
+    }
+}
+
+/// Rejects callers the authentication middleware identified with too little access.
+pub async fn require_role(
+    State(access): State<Access>,
+    Extension(identity): Extension<Identity>,
+    OriginalUri(uri): OriginalUri,
+    request: Request,
+    next: Next,
+) -> Response {
+    let denied = if identity.role < access.role {
+        Some(format!(
+            "{} has role {}, {} {} needs {}",
+            identity,
+            identity.role,
+            request.method(),
+            uri.path(),
+            access.role
+        ))
+    } else if let Some(app_state) = &access.plc {
+        let address = app_state.lock().await.address.clone();
+        (!identity.scope.allows_plc(&address))
+            .then(|| format!("{} may not write to the PLC at {}", identity, address))
+    } else {
+        None
+    };
+
+    match denied {
+        Some(message) => {
+            println!("** Denied: {}", message);
+            (
+                StatusCode::FORBIDDEN,
+                Json(AccessDeniedResponse { message }),
+            )
+                .into_response()
+        }
+        None => next.run(request).await,
+    }
+}
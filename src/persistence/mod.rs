@@ -0,0 +1,3 @@
+mod state_store;
+
+pub use state_store::{SavedConnection, StateStore};
@@ -0,0 +1,142 @@
+use crate::{config::HeaterConfig, plc::ConnectionParams, routes::PLCConfig};
+use chrono::{DateTime, Local};
+use pid::Pid;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const STATE_FILE: &str = "runtime_state.json";
+
+/// PLC connection settings without the session password, which is never written to disk.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedConnection {
+    pub address: String,
+    pub rack: i32,
+    pub slot: i32,
+    pub connection: ConnectionParams,
+}
+
+impl From<&PLCConfig> for SavedConnection {
+    fn from(config: &PLCConfig) -> Self {
+        SavedConnection {
+            address: config.address.clone(),
+            rack: config.rack,
+            slot: config.slot,
+            connection: config.connection.clone(),
+        }
+    }
+}
+
+/// Settings changed through the API at runtime. Each one remembers the configured value it
+/// replaced, so an edit of the config file made while the service was down still wins.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RuntimeState {
+    pub connection: Option<SavedChange<SavedConnection>>,
+    pub heater_enabled: Option<bool>,
+    pub pid: Option<SavedPid>,
+    pub saved_at: Option<DateTime<Local>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedChange<T> {
+    pub value: T,
+    pub configured: T, // The config value at the time of the change
+}
+
+/// Setpoint, tuning and integrator of the heater loop, with the heater settings it ran with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedPid {
+    pub pid: Pid<f64>,
+    pub configured: HeaterConfig,
+}
+
+/// Keeps the runtime state in `<dir>/runtime_state.json`, rewritten on every change.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+    state: Arc<Mutex<RuntimeState>>,
+}
+
+impl StateStore {
+    /// Opens the store. An unreadable state file is moved aside and the store starts empty,
+    /// so a corrupt file can never prevent a start.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Can't create state directory {:?}: {}", dir, e))?;
+        let path = dir.join(STATE_FILE);
+
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(state) => state,
+                Err(e) => {
+                    let corrupt = path.with_extension("json.corrupt");
+                    println!(
+                        "** Unreadable state file {:?} ({}), moved to {:?}",
+                        path, e, corrupt
+                    );
+                    fs::rename(&path, &corrupt)?;
+                    RuntimeState::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RuntimeState::default(),
+            Err(e) => anyhow::bail!("Can't read state file {:?}: {}", path, e),
+        };
+
+        Ok(StateStore {
+            path,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    pub fn state(&self) -> RuntimeState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Connection settings to use instead of `configured`, if they were changed at runtime
+    /// since the configuration last changed.
+    pub fn connection(&self, configured: &PLCConfig) -> Option<SavedConnection> {
+        let saved = self.state.lock().unwrap().connection.clone()?;
+        (saved.configured == SavedConnection::from(configured)).then_some(saved.value)
+    }
+
+    /// The saved controller, if it ran with the same heater settings as `configured`.
+    pub fn pid(&self, configured: &HeaterConfig) -> Option<Pid<f64>> {
+        let saved = self.state.lock().unwrap().pid.clone()?;
+        (saved.configured == *configured).then_some(saved.pid)
+    }
+
+    /// Applies `change` and writes the state file. The new file replaces the old one in a
+    /// single rename, a crash never leaves a half written state.
+    pub fn update(&self, change: impl FnOnce(&mut RuntimeState)) -> Result<(), anyhow::Error> {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+        state.saved_at = Some(Local::now());
+
+        let temporary = self.path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(&*state)?)?;
+        fs::rename(&temporary, &self.path)
+            .map_err(|e| anyhow::anyhow!("Can't write state file {:?}: {}", self.path, e))
+    }
+
+    pub fn save_connection(
+        &self,
+        value: &PLCConfig,
+        configured: &PLCConfig,
+    ) -> Result<(), anyhow::Error> {
+        let change = SavedChange {
+            value: SavedConnection::from(value),
+            configured: SavedConnection::from(configured),
+        };
+        self.update(|state| state.connection = Some(change))
+    }
+
+    pub fn save_pid(&self, pid: &Pid<f64>, configured: &HeaterConfig) -> Result<(), anyhow::Error> {
+        let saved = SavedPid {
+            pid: *pid,
+            configured: configured.clone(),
+        };
+        self.update(|state| state.pid = Some(saved))
+    }
+}
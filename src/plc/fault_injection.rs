@@ -0,0 +1,302 @@
+use super::plc_client::{AreaItem, ConnectTarget, PlcArea, PlcClient, Protection};
+use crate::blocks::{BlockDetails, BlockKind};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, anyhow::Error>;
+
+/// A failure the PLC transport can be told to simulate.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    Timeout { after_ms: u64 }, // Hang, then fail like an unanswered request
+    Disconnect,                // Drop the connection and fail the request
+    ItemError { message: Option<String> }, // Fail the whole request with an item error
+    // Fail only these items (by index) of a multi-variable read or write, the others
+    // succeed; other requests run normally
+    BadItems { items: Vec<usize>, message: Option<String> },
+    Latency { ms: u64 },           // Delay the request, then run it normally
+    Corrupt { value: Option<u8> }, // Overwrite read data, 0xFF by default
+}
+
+/// What an injected fault does to the result of a request that ran.
+enum Tamper {
+    Nothing,
+    Corrupt(u8),
+    BadItems(Vec<usize>, String),
+}
+
+impl Tamper {
+    fn corrupt(&self) -> Option<u8> {
+        match self {
+            Tamper::Corrupt(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Replaces the chosen items of a multi-variable result with an item error.
+    fn fail_items<T>(&self, results: &mut [std::result::Result<T, String>]) {
+        if let Tamper::BadItems(items, message) = self {
+            for index in items {
+                if let Some(result) = results.get_mut(*index) {
+                    *result = Err(message.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Injects `fault` into matching operations. Operations are named after the
+/// `PlcClient` method, e.g. `read_area` or `plc_stop`; an empty list matches all.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FaultRule {
+    pub fault: Fault,
+    #[serde(default)]
+    pub operations: Vec<String>,
+    #[serde(default)]
+    pub skip: u64, // Let this many matching requests through first
+    #[serde(default)]
+    pub count: Option<u64>, // Fire this many times, forever when omitted
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct FaultScenario {
+    pub rules: Vec<FaultRule>,
+}
+
+impl FaultScenario {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Plays a scenario against the requests of every connection it is attached to.
+#[derive(Debug)]
+pub struct FaultInjector {
+    scenario: FaultScenario,
+    matched: Mutex<Vec<u64>>, // Matching requests seen per rule
+}
+
+impl FaultInjector {
+    pub fn new(scenario: FaultScenario) -> Self {
+        let matched = Mutex::new(vec![0; scenario.rules.len()]);
+        FaultInjector { scenario, matched }
+    }
+
+    /// The fault to inject into this request, if any. The first firing rule wins.
+    pub fn next_fault(&self, operation: &str) -> Option<Fault> {
+        let mut matched = self.matched.lock().unwrap();
+        let mut fault = None;
+
+        for (rule, seen) in self.scenario.rules.iter().zip(matched.iter_mut()) {
+            let applies =
+                rule.operations.is_empty() || rule.operations.iter().any(|name| name == operation);
+            if !applies {
+                continue;
+            }
+
+            *seen += 1;
+            let fires =
+                *seen > rule.skip && rule.count.is_none_or(|count| *seen <= rule.skip + count);
+            if fires && fault.is_none() {
+                fault = Some(rule.fault.clone());
+            }
+        }
+        fault
+    }
+}
+
+/// Wraps a client and fails or delays its requests as the injector scripts.
+/// Connecting can be faulted, disconnecting and `is_connected` never are.
+pub struct FaultyClient {
+    inner: Box<dyn PlcClient>,
+    faults: Arc<FaultInjector>,
+}
+
+impl FaultyClient {
+    pub fn new(inner: Box<dyn PlcClient>, faults: Arc<FaultInjector>) -> Self {
+        FaultyClient { inner, faults }
+    }
+
+    /// Applies any fault scripted for `operation`. Returns what to do to its result.
+    fn inject(&self, operation: &str) -> Result<Tamper> {
+        let Some(fault) = self.faults.next_fault(operation) else {
+            return Ok(Tamper::Nothing);
+        };
+
+        match fault {
+            Fault::Timeout { after_ms } => {
+                thread::sleep(Duration::from_millis(after_ms));
+                anyhow::bail!("Injected fault: {} timed out", operation)
+            }
+            Fault::Disconnect => {
+                let _ = self.inner.disconnect();
+                anyhow::bail!("Injected fault: connection lost during {}", operation)
+            }
+            Fault::ItemError { message } => Err(anyhow::anyhow!(
+                message.unwrap_or_else(|| format!("Injected fault: item error in {}", operation))
+            )),
+            Fault::BadItems { items, message } => Ok(Tamper::BadItems(
+                items,
+                message.unwrap_or_else(|| format!("Injected fault: item error in {}", operation)),
+            )),
+            Fault::Latency { ms } => {
+                thread::sleep(Duration::from_millis(ms));
+                Ok(Tamper::Nothing)
+            }
+            Fault::Corrupt { value } => Ok(Tamper::Corrupt(value.unwrap_or(0xFF))),
+        }
+    }
+
+    /// Runs a read and overwrites its data when the injector asks for corruption.
+    fn read_data(&self, operation: &str, read: impl FnOnce() -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let corrupt = self.inject(operation)?.corrupt();
+        let mut data = read()?;
+        if let Some(value) = corrupt {
+            data.fill(value);
+        }
+        Ok(data)
+    }
+}
+
+impl PlcClient for FaultyClient {
+    fn connect(&self, target: &ConnectTarget) -> Result<()> {
+        self.inject("connect")?;
+        self.inner.connect(target)
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        self.inner.disconnect()
+    }
+
+    fn is_connected(&self) -> Result<bool> {
+        self.inner.is_connected()
+    }
+
+    fn set_session_password(&self, password: Option<&str>) -> Result<()> {
+        self.inject("set_session_password")?;
+        self.inner.set_session_password(password)
+    }
+
+    fn get_protection(&self) -> Result<Protection> {
+        self.inject("get_protection")?;
+        self.inner.get_protection()
+    }
+
+    fn get_plc_status(&self) -> Result<i32> {
+        self.inject("get_plc_status")?;
+        self.inner.get_plc_status()
+    }
+
+    fn plc_stop(&self) -> Result<()> {
+        self.inject("plc_stop")?;
+        self.inner.plc_stop()
+    }
+
+    fn plc_hot_start(&self) -> Result<()> {
+        self.inject("plc_hot_start")?;
+        self.inner.plc_hot_start()
+    }
+
+    fn plc_cold_start(&self) -> Result<()> {
+        self.inject("plc_cold_start")?;
+        self.inner.plc_cold_start()
+    }
+
+    fn get_plc_date_time(&self) -> Result<NaiveDateTime> {
+        self.inject("get_plc_date_time")?;
+        self.inner.get_plc_date_time()
+    }
+
+    fn set_plc_date_time(&self, value: NaiveDateTime) -> Result<()> {
+        self.inject("set_plc_date_time")?;
+        self.inner.set_plc_date_time(value)
+    }
+
+    fn read_area(&self, area: PlcArea, start: usize, buff: &mut [u8]) -> Result<()> {
+        let corrupt = self.inject("read_area")?.corrupt();
+        self.inner.read_area(area, start, buff)?;
+        if let Some(value) = corrupt {
+            buff.fill(value);
+        }
+        Ok(())
+    }
+
+    fn write_area(&self, area: PlcArea, start: usize, data: &[u8]) -> Result<()> {
+        self.inject("write_area")?;
+        self.inner.write_area(area, start, data)
+    }
+
+    fn read_bit(&self, area: PlcArea, byte: usize, bit: u8) -> Result<bool> {
+        let corrupt = self.inject("read_bit")?.corrupt();
+        let value = self.inner.read_bit(area, byte, bit)?;
+        Ok(corrupt.map_or(value, |corrupt| corrupt & 1 != 0))
+    }
+
+    fn write_bit(&self, area: PlcArea, byte: usize, bit: u8, value: bool) -> Result<()> {
+        self.inject("write_bit")?;
+        self.inner.write_bit(area, byte, bit, value)
+    }
+
+    fn read_multi_vars(
+        &self,
+        items: &[AreaItem],
+    ) -> Result<Vec<std::result::Result<Vec<u8>, String>>> {
+        let tamper = self.inject("read_multi_vars")?;
+        let mut results = self.inner.read_multi_vars(items)?;
+        if let Some(value) = tamper.corrupt() {
+            for data in results.iter_mut().flatten() {
+                data.fill(value);
+            }
+        }
+        tamper.fail_items(&mut results);
+        Ok(results)
+    }
+
+    fn write_multi_vars(
+        &self,
+        items: &[(AreaItem, Vec<u8>)],
+    ) -> Result<Vec<std::result::Result<(), String>>> {
+        let tamper = self.inject("write_multi_vars")?;
+        let mut results = self.inner.write_multi_vars(items)?;
+        tamper.fail_items(&mut results);
+        Ok(results)
+    }
+
+    fn list_blocks(&self) -> Result<Vec<(BlockKind, i32)>> {
+        self.inject("list_blocks")?;
+        self.inner.list_blocks()
+    }
+
+    fn list_blocks_of_type(&self, kind: BlockKind) -> Result<Vec<u16>> {
+        self.inject("list_blocks_of_type")?;
+        self.inner.list_blocks_of_type(kind)
+    }
+
+    fn get_block_info(&self, kind: BlockKind, number: i32) -> Result<BlockDetails> {
+        self.inject("get_block_info")?;
+        self.inner.get_block_info(kind, number)
+    }
+
+    fn full_upload(&self, kind: BlockKind, number: i32) -> Result<Vec<u8>> {
+        self.read_data("full_upload", || self.inner.full_upload(kind, number))
+    }
+
+    fn download(&self, data: &[u8]) -> Result<()> {
+        self.inject("download")?;
+        self.inner.download(data)
+    }
+
+    fn db_get(&self, number: i32, size: usize) -> Result<Vec<u8>> {
+        self.read_data("db_get", || self.inner.db_get(number, size))
+    }
+
+    fn db_fill(&self, number: i32, value: u8) -> Result<()> {
+        self.inject("db_fill")?;
+        self.inner.db_fill(number, value)
+    }
+}
@@ -0,0 +1,448 @@
+use super::plc_client::{AreaItem, ClientFactory, ConnectTarget, PlcArea, PlcClient, Protection};
+use crate::blocks::{BlockDetails, BlockKind};
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+type Result<T> = std::result::Result<T, anyhow::Error>;
+
+// CPU states as returned by get_plc_status
+const CPU_STATUS_STOP: i32 = 0x04;
+const CPU_STATUS_RUN: i32 = 0x08;
+
+// Error texts as snap7 reports them, so callers see the same messages
+const NOT_CONNECTED: &str = "CLI : Client not connected";
+const ADDRESS_OUT_OF_RANGE: &str = "CPU : Address out of range";
+const ITEM_NOT_AVAILABLE: &str = "CPU : Item not available";
+const NEED_PASSWORD: &str = "CPU : Function not authorized for current protection level";
+const INVALID_PASSWORD: &str = "CPU : Invalid password";
+
+/// Block type codes at byte 5 of an MC7 block header.
+fn mc7_block_kind(code: u8) -> Option<BlockKind> {
+    match code {
+        0x08 => Some(BlockKind::Ob),
+        0x0A => Some(BlockKind::Db),
+        0x0B => Some(BlockKind::Sdb),
+        0x0C => Some(BlockKind::Fc),
+        0x0D => Some(BlockKind::Sfc),
+        0x0E => Some(BlockKind::Fb),
+        0x0F => Some(BlockKind::Sfb),
+        _ => None,
+    }
+}
+
+struct MockMemory {
+    reachable: bool,
+    status: i32,
+    password: Option<String>, // Writes and control need it as session password when set
+    clock_offset: TimeDelta,
+    areas: HashMap<PlcArea, Vec<u8>>,
+    blocks: HashMap<(BlockKind, i32), Vec<u8>>, // Program blocks other than DBs
+}
+
+impl MockMemory {
+    fn area(&self, area: PlcArea, start: usize, size: usize) -> Result<&[u8]> {
+        self.areas
+            .get(&area)
+            .and_then(|data| data.get(start..start.checked_add(size)?))
+            .ok_or_else(|| anyhow::anyhow!(ADDRESS_OUT_OF_RANGE))
+    }
+
+    fn area_mut(&mut self, area: PlcArea, start: usize, size: usize) -> Result<&mut [u8]> {
+        self.areas
+            .get_mut(&area)
+            .and_then(|data| data.get_mut(start..start.checked_add(size)?))
+            .ok_or_else(|| anyhow::anyhow!(ADDRESS_OUT_OF_RANGE))
+    }
+
+    fn db(&self, number: i32) -> Result<&Vec<u8>> {
+        self.areas
+            .get(&PlcArea::Db(number))
+            .ok_or_else(|| anyhow::anyhow!(ITEM_NOT_AVAILABLE))
+    }
+}
+
+/// A PLC kept entirely in memory for unit tests. Clones
+/// are further connections to the same CPU.
+///
+/// Blocks other than DBs are stored as given; uploads of a DB return its data. Downloads
+/// take the block type and number from the MC7 header.
+pub struct MockPlc {
+    memory: Arc<Mutex<MockMemory>>,
+    connected: AtomicBool,
+    session_password: Mutex<Option<String>>,
+}
+
+impl Clone for MockPlc {
+    fn clone(&self) -> Self {
+        MockPlc {
+            memory: self.memory.clone(),
+            connected: AtomicBool::new(false),
+            session_password: Mutex::new(None),
+        }
+    }
+}
+
+impl MockPlc {
+    /// A running CPU with 8 bytes each of inputs, outputs and flags and no DBs.
+    pub fn new() -> Self {
+        let areas = [PlcArea::Inputs, PlcArea::Outputs, PlcArea::Flags]
+            .into_iter()
+            .map(|area| (area, vec![0u8; 8]))
+            .collect();
+
+        MockPlc {
+            memory: Arc::new(Mutex::new(MockMemory {
+                reachable: true,
+                status: CPU_STATUS_RUN,
+                password: None,
+                clock_offset: TimeDelta::zero(),
+                areas,
+                blocks: HashMap::new(),
+            })),
+            connected: AtomicBool::new(false),
+            session_password: Mutex::new(None),
+        }
+    }
+
+    /// Creates connections to this CPU for a pool.
+    pub fn factory(&self) -> ClientFactory {
+        let plc = self.clone();
+        Arc::new(move || Box::new(plc.clone()) as Box<dyn PlcClient>)
+    }
+
+    pub fn with_db(self, number: i32, size: usize) -> Self {
+        self.set_area(PlcArea::Db(number), vec![0u8; size]);
+        self
+    }
+
+    /// Replaces the contents (and size) of an area.
+    pub fn set_area(&self, area: PlcArea, data: Vec<u8>) {
+        self.memory.lock().unwrap().areas.insert(area, data);
+    }
+
+    pub fn area(&self, area: PlcArea) -> Option<Vec<u8>> {
+        self.memory.lock().unwrap().areas.get(&area).cloned()
+    }
+
+    pub fn set_bytes(&self, area: PlcArea, start: usize, data: &[u8]) {
+        let mut memory = self.memory.lock().unwrap();
+        if let Ok(bytes) = memory.area_mut(area, start, data.len()) {
+            bytes.copy_from_slice(data);
+        }
+    }
+
+    pub fn bit(&self, area: PlcArea, byte: usize, bit: u8) -> bool {
+        let memory = self.memory.lock().unwrap();
+        memory
+            .area(area, byte, 1)
+            .is_ok_and(|bytes| bytes[0] & (1 << bit) != 0)
+    }
+
+    pub fn add_block(&self, kind: BlockKind, number: i32, data: Vec<u8>) {
+        self.memory
+            .lock()
+            .unwrap()
+            .blocks
+            .insert((kind, number), data);
+    }
+
+    pub fn status(&self) -> i32 {
+        self.memory.lock().unwrap().status
+    }
+
+    /// Protects the CPU so writes and control need this session password.
+    pub fn set_password(&self, password: Option<&str>) {
+        self.memory.lock().unwrap().password = password.map(str::to_string);
+    }
+
+    /// An unreachable CPU refuses new connections and drops the open ones.
+    pub fn set_reachable(&self, reachable: bool) {
+        self.memory.lock().unwrap().reachable = reachable;
+    }
+
+    fn connected_memory(&self) -> Result<std::sync::MutexGuard<'_, MockMemory>> {
+        let memory = self.memory.lock().unwrap();
+        if !memory.reachable {
+            self.connected.store(false, Ordering::Relaxed);
+        }
+        if !self.connected.load(Ordering::Relaxed) {
+            anyhow::bail!(NOT_CONNECTED);
+        }
+        Ok(memory)
+    }
+
+    /// Like `connected_memory`, but also checks the session password of a protected CPU.
+    fn authorized_memory(&self) -> Result<std::sync::MutexGuard<'_, MockMemory>> {
+        let memory = self.connected_memory()?;
+        if let Some(password) = &memory.password {
+            if self.session_password.lock().unwrap().as_ref() != Some(password) {
+                anyhow::bail!(NEED_PASSWORD);
+            }
+        }
+        Ok(memory)
+    }
+
+    fn block_details(kind: BlockKind, number: i32, size: usize) -> BlockDetails {
+        BlockDetails {
+            block_type: Some(kind),
+            number,
+            language: 0,
+            flags: 0,
+            mc7_size: size as i32,
+            load_size: size as i32,
+            local_data: 0,
+            sbb_length: 0,
+            checksum: 0,
+            version: 0,
+            code_date: String::new(),
+            interface_date: String::new(),
+            author: String::new(),
+            family: String::new(),
+            header: String::new(),
+        }
+    }
+}
+
+impl PlcClient for MockPlc {
+    fn connect(&self, _target: &ConnectTarget) -> Result<()> {
+        self.connected.store(false, Ordering::Relaxed);
+        if !self.memory.lock().unwrap().reachable {
+            anyhow::bail!("TCP : Connection refused");
+        }
+        *self.session_password.lock().unwrap() = None;
+        self.connected.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        self.connected.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn is_connected(&self) -> Result<bool> {
+        Ok(self.connected_memory().is_ok())
+    }
+
+    fn set_session_password(&self, password: Option<&str>) -> Result<()> {
+        let memory = self.connected_memory()?;
+        if let (Some(password), Some(expected)) = (password, &memory.password) {
+            if password != expected {
+                anyhow::bail!(INVALID_PASSWORD);
+            }
+        }
+        *self.session_password.lock().unwrap() = password.map(str::to_string);
+        Ok(())
+    }
+
+    fn get_protection(&self) -> Result<Protection> {
+        let memory = self.connected_memory()?;
+        let protected = memory.password.is_some();
+        let unlocked =
+            protected && self.session_password.lock().unwrap().as_ref() == memory.password.as_ref();
+        Ok(Protection {
+            protection_level: if protected && !unlocked { 2 } else { 1 },
+            selector_level: 1,
+            password_level: if protected { 2 } else { 1 },
+            mode_selector: if memory.status == CPU_STATUS_RUN {
+                2
+            } else {
+                3
+            },
+            startup_switch: 2,
+        })
+    }
+
+    fn get_plc_status(&self) -> Result<i32> {
+        Ok(self.connected_memory()?.status)
+    }
+
+    fn plc_stop(&self) -> Result<()> {
+        self.authorized_memory()?.status = CPU_STATUS_STOP;
+        Ok(())
+    }
+
+    fn plc_hot_start(&self) -> Result<()> {
+        let mut memory = self.authorized_memory()?;
+        if memory.status == CPU_STATUS_RUN {
+            anyhow::bail!("CPU : Function not available in current mode");
+        }
+        memory.status = CPU_STATUS_RUN;
+        Ok(())
+    }
+
+    fn plc_cold_start(&self) -> Result<()> {
+        self.plc_hot_start()
+    }
+
+    fn get_plc_date_time(&self) -> Result<NaiveDateTime> {
+        let offset = self.connected_memory()?.clock_offset;
+        Ok(Local::now().naive_local() + offset)
+    }
+
+    fn set_plc_date_time(&self, value: NaiveDateTime) -> Result<()> {
+        self.authorized_memory()?.clock_offset = value - Local::now().naive_local();
+        Ok(())
+    }
+
+    fn read_area(&self, area: PlcArea, start: usize, buff: &mut [u8]) -> Result<()> {
+        let memory = self.connected_memory()?;
+        buff.copy_from_slice(memory.area(area, start, buff.len())?);
+        Ok(())
+    }
+
+    fn write_area(&self, area: PlcArea, start: usize, data: &[u8]) -> Result<()> {
+        let mut memory = self.authorized_memory()?;
+        memory
+            .area_mut(area, start, data.len())?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_bit(&self, area: PlcArea, byte: usize, bit: u8) -> Result<bool> {
+        let memory = self.connected_memory()?;
+        Ok(memory.area(area, byte, 1)?[0] & (1 << bit) != 0)
+    }
+
+    fn write_bit(&self, area: PlcArea, byte: usize, bit: u8, value: bool) -> Result<()> {
+        let mut memory = self.authorized_memory()?;
+        let bytes = memory.area_mut(area, byte, 1)?;
+        if value {
+            bytes[0] |= 1 << bit;
+        } else {
+            bytes[0] &= !(1 << bit);
+        }
+        Ok(())
+    }
+
+    fn read_multi_vars(
+        &self,
+        items: &[AreaItem],
+    ) -> Result<Vec<std::result::Result<Vec<u8>, String>>> {
+        let memory = self.connected_memory()?;
+        Ok(items
+            .iter()
+            .map(|item| {
+                memory
+                    .area(item.area, item.start, item.size)
+                    .map(|data| data.to_vec())
+                    .map_err(|e| e.to_string())
+            })
+            .collect())
+    }
+
+    fn write_multi_vars(
+        &self,
+        items: &[(AreaItem, Vec<u8>)],
+    ) -> Result<Vec<std::result::Result<(), String>>> {
+        let mut memory = self.authorized_memory()?;
+        Ok(items
+            .iter()
+            .map(|(item, data)| {
+                let size = item.size.min(data.len());
+                memory
+                    .area_mut(item.area, item.start, size)
+                    .map(|bytes| bytes.copy_from_slice(&data[..size]))
+                    .map_err(|e| e.to_string())
+            })
+            .collect())
+    }
+
+    fn list_blocks(&self) -> Result<Vec<(BlockKind, i32)>> {
+        let memory = self.connected_memory()?;
+        let dbs = memory
+            .areas
+            .keys()
+            .filter(|area| matches!(area, PlcArea::Db(_)))
+            .count();
+
+        Ok(BlockKind::PROGRAM
+            .into_iter()
+            .chain([BlockKind::Sfc, BlockKind::Sfb])
+            .map(|kind| {
+                let count = match kind {
+                    BlockKind::Db => dbs,
+                    kind => memory.blocks.keys().filter(|(k, _)| *k == kind).count(),
+                };
+                (kind, count as i32)
+            })
+            .collect())
+    }
+
+    fn list_blocks_of_type(&self, kind: BlockKind) -> Result<Vec<u16>> {
+        let memory = self.connected_memory()?;
+        let mut numbers: Vec<u16> = match kind {
+            BlockKind::Db => memory
+                .areas
+                .keys()
+                .filter_map(|area| match area {
+                    PlcArea::Db(number) => Some(*number as u16),
+                    _ => None,
+                })
+                .collect(),
+            kind => memory
+                .blocks
+                .keys()
+                .filter(|(k, _)| *k == kind)
+                .map(|(_, number)| *number as u16)
+                .collect(),
+        };
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+
+    fn get_block_info(&self, kind: BlockKind, number: i32) -> Result<BlockDetails> {
+        let memory = self.connected_memory()?;
+        let size = match kind {
+            BlockKind::Db => memory.db(number)?.len(),
+            kind => memory
+                .blocks
+                .get(&(kind, number))
+                .ok_or_else(|| anyhow::anyhow!(ITEM_NOT_AVAILABLE))?
+                .len(),
+        };
+        Ok(Self::block_details(kind, number, size))
+    }
+
+    fn full_upload(&self, kind: BlockKind, number: i32) -> Result<Vec<u8>> {
+        let memory = self.connected_memory()?;
+        match kind {
+            BlockKind::Db => memory.db(number).cloned(),
+            kind => memory
+                .blocks
+                .get(&(kind, number))
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!(ITEM_NOT_AVAILABLE)),
+        }
+    }
+
+    fn download(&self, data: &[u8]) -> Result<()> {
+        let mut memory = self.authorized_memory()?;
+        let (Some(kind), Some(number)) = (
+            data.get(5).and_then(|code| mc7_block_kind(*code)),
+            data.get(6..8)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as i32),
+        ) else {
+            anyhow::bail!("CPU : Invalid block");
+        };
+        memory.blocks.insert((kind, number), data.to_vec());
+        Ok(())
+    }
+
+    fn db_get(&self, number: i32, size: usize) -> Result<Vec<u8>> {
+        let memory = self.connected_memory()?;
+        let data = memory.db(number)?;
+        Ok(data[..size.min(data.len())].to_vec())
+    }
+
+    fn db_fill(&self, number: i32, value: u8) -> Result<()> {
+        let mut memory = self.authorized_memory()?;
+        memory
+            .areas
+            .get_mut(&PlcArea::Db(number))
+            .ok_or_else(|| anyhow::anyhow!(ITEM_NOT_AVAILABLE))?
+            .fill(value);
+        Ok(())
+    }
+}
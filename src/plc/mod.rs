@@ -0,0 +1,16 @@
+mod fault_injection;
+#[cfg(test)]
+mod mock_client;
+mod plc_client;
+mod plc_pool;
+mod plc_scheduler;
+mod plc_worker;
+mod recording_client;
+mod snap7_client;
+pub use fault_injection::{FaultInjector, FaultScenario};
+#[cfg(test)]
+pub use mock_client::MockPlc;
+pub use plc_client::{AreaItem, ConnectTarget, ConnectionParams, PlcArea, PlcClient, Protection};
+pub use plc_pool::{ConnectionStatus, PlcPool, PoolConfig};
+pub use plc_scheduler::Priority;
+pub use recording_client::Recording;
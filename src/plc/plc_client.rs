@@ -0,0 +1,148 @@
+use crate::blocks::{BlockDetails, BlockKind};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+type Result<T> = std::result::Result<T, anyhow::Error>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionType {
+    #[default]
+    Pg,
+    Op,
+    S7Basic,
+}
+
+/// Low level connection settings. Explicit TSAPs (e.g. for LOGO! or S7-200) replace rack and slot.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Validate)]
+#[validate(schema(function = "validate_tsaps"))]
+pub struct ConnectionParams {
+    #[serde(default)]
+    pub connection_type: ConnectionType,
+    pub local_tsap: Option<u16>,
+    pub remote_tsap: Option<u16>,
+    #[serde(default = "default_port")]
+    #[validate(range(min = 1))]
+    pub port: u16,
+    #[validate(range(min = 1, max = 60000))]
+    pub connect_timeout_ms: Option<i32>,
+    #[validate(range(min = 1, max = 60000))]
+    pub recv_timeout_ms: Option<i32>,
+    #[validate(range(min = 1, max = 60000))]
+    pub send_timeout_ms: Option<i32>,
+}
+
+fn default_port() -> u16 {
+    102
+}
+
+fn validate_tsaps(params: &ConnectionParams) -> std::result::Result<(), ValidationError> {
+    if params.local_tsap.is_some() != params.remote_tsap.is_some() {
+        let mut error = ValidationError::new("tsaps");
+        error.message = Some("local_tsap and remote_tsap must be set together".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+impl Default for ConnectionParams {
+    fn default() -> Self {
+        ConnectionParams {
+            connection_type: ConnectionType::default(),
+            local_tsap: None,
+            remote_tsap: None,
+            port: default_port(),
+            connect_timeout_ms: None,
+            recv_timeout_ms: None,
+            send_timeout_ms: None,
+        }
+    }
+}
+
+/// Everything a backend needs to open a connection to one CPU.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConnectTarget {
+    pub address: String,
+    pub rack: i32,
+    pub slot: i32,
+    pub params: ConnectionParams,
+}
+
+/// A memory area of the CPU. Addresses within an area are byte offsets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PlcArea {
+    Inputs,
+    Outputs,
+    Flags,
+    Db(i32),
+}
+
+/// One variable of a multi-variable request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AreaItem {
+    pub area: PlcArea,
+    pub start: usize,
+    pub size: usize,
+}
+
+/// CPU protection as reported by the PLC, using the S7 level codes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Protection {
+    pub protection_level: u16, // Effective level: 1 none, 2 write, 3 read/write
+    pub selector_level: u16,
+    pub password_level: u16,
+    pub mode_selector: u16,  // 1 RUN, 2 RUN-P, 3 STOP, 4 MRES
+    pub startup_switch: u16, // 1 CRST, 2 WRST
+}
+
+/// A connection to one PLC. Jobs queued on the pool receive it on the connection's
+/// worker thread, so implementations may block. Operation names used for fault
+/// injection and recordings are the method names.
+pub trait PlcClient: Send {
+    /// Opens the connection, closing any previous one first.
+    fn connect(&self, target: &ConnectTarget) -> Result<()>;
+    fn disconnect(&self) -> Result<()>;
+    fn is_connected(&self) -> Result<bool>;
+    /// Sets or clears the password of the current session.
+    fn set_session_password(&self, password: Option<&str>) -> Result<()>;
+
+    fn get_protection(&self) -> Result<Protection>;
+    fn get_plc_status(&self) -> Result<i32>;
+    fn plc_stop(&self) -> Result<()>;
+    fn plc_hot_start(&self) -> Result<()>;
+    fn plc_cold_start(&self) -> Result<()>;
+    /// The PLC keeps local wall-clock time without a zone.
+    fn get_plc_date_time(&self) -> Result<NaiveDateTime>;
+    fn set_plc_date_time(&self, value: NaiveDateTime) -> Result<()>;
+
+    fn read_area(&self, area: PlcArea, start: usize, buff: &mut [u8]) -> Result<()>;
+    fn write_area(&self, area: PlcArea, start: usize, data: &[u8]) -> Result<()>;
+    fn read_bit(&self, area: PlcArea, byte: usize, bit: u8) -> Result<bool>;
+    fn write_bit(&self, area: PlcArea, byte: usize, bit: u8, value: bool) -> Result<()>;
+    /// Reads several variables in one request. The outer error fails the whole request,
+    /// the inner ones report single items.
+    fn read_multi_vars(
+        &self,
+        items: &[AreaItem],
+    ) -> Result<Vec<std::result::Result<Vec<u8>, String>>>;
+    fn write_multi_vars(
+        &self,
+        items: &[(AreaItem, Vec<u8>)],
+    ) -> Result<Vec<std::result::Result<(), String>>>;
+
+    fn list_blocks(&self) -> Result<Vec<(BlockKind, i32)>>;
+    fn list_blocks_of_type(&self, kind: BlockKind) -> Result<Vec<u16>>;
+    fn get_block_info(&self, kind: BlockKind, number: i32) -> Result<BlockDetails>;
+    /// The complete block (header, body and footer) as it can be downloaded again.
+    fn full_upload(&self, kind: BlockKind, number: i32) -> Result<Vec<u8>>;
+    /// Downloads a block, keeping the block number stored in the block itself.
+    fn download(&self, data: &[u8]) -> Result<()>;
+    /// Reads up to `size` bytes of a DB from its start.
+    fn db_get(&self, number: i32, size: usize) -> Result<Vec<u8>>;
+    fn db_fill(&self, number: i32, value: u8) -> Result<()>;
+}
+
+/// Creates the client of every pooled connection, including replacements.
+pub type ClientFactory = std::sync::Arc<dyn Fn() -> Box<dyn PlcClient> + Send + Sync>;
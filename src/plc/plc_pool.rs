@@ -0,0 +1,284 @@
+use super::plc_scheduler::{Priority, SchedulerConfig, SchedulerMetrics};
+use super::plc_worker::PlcWorker;
+use serde::Serialize;
+use super::fault_injection::{FaultInjector, FaultyClient};
+use super::plc_client::{ClientFactory, PlcClient};
+use super::snap7_client::Snap7Client;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::time::Duration;
+
+/// What a pooled connection is used for. Polling reads and program uploads go to
+/// readers so they never queue behind writes and control commands.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionRole {
+    Read,
+    Write,
+}
+
+impl fmt::Display for ConnectionRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionRole::Read => write!(f, "read"),
+            ConnectionRole::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PoolConfig {
+    pub read_connections: usize,
+    pub write_connections: usize,
+    pub request_timeout: Duration,
+    pub health_check_timeout: Duration,
+    pub scheduler: SchedulerConfig,
+    pub client: ClientFactory, // The backend, snap7 by default
+    pub faults: Option<Arc<FaultInjector>>, // Shared by every connection in the pool
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            read_connections: 2,
+            write_connections: 1,
+            request_timeout: Duration::from_secs(10),
+            health_check_timeout: Duration::from_secs(2),
+            scheduler: SchedulerConfig::default(),
+            client: Arc::new(|| Box::new(Snap7Client::new()) as Box<dyn PlcClient>),
+            faults: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectionStatus {
+    pub index: usize,
+    pub role: ConnectionRole,
+    pub healthy: bool,
+    pub replacements: u64,
+    pub scheduler: SchedulerMetrics,
+}
+
+struct PooledConnection {
+    role: ConnectionRole,
+    worker: PlcWorker,
+    healthy: bool,
+    replacements: u64,
+}
+
+/// Several connections to the same CPU, each on its own worker thread.
+#[derive(Clone)]
+pub struct PlcPool {
+    config: PoolConfig,
+    connections: Arc<RwLock<Vec<PooledConnection>>>,
+    next_connection: Arc<AtomicUsize>,
+}
+
+impl PlcPool {
+    pub fn new(config: PoolConfig) -> Self {
+        // Writes always need a connection, reads fall back to the writers
+        let write_connections = config.write_connections.max(1);
+        let roles = std::iter::repeat_n(ConnectionRole::Write, write_connections).chain(
+            std::iter::repeat_n(ConnectionRole::Read, config.read_connections),
+        );
+
+        let connections = roles
+            .enumerate()
+            .map(|(index, role)| PooledConnection {
+                role,
+                worker: Self::spawn_worker(&config, role, index),
+                healthy: false,
+                replacements: 0,
+            })
+            .collect();
+
+        PlcPool {
+            config,
+            connections: Arc::new(RwLock::new(connections)),
+            next_connection: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn spawn_worker(config: &PoolConfig, role: ConnectionRole, index: usize) -> PlcWorker {
+        let mut client = (config.client)();
+        if let Some(faults) = &config.faults {
+            client = Box::new(FaultyClient::new(client, faults.clone()));
+        }
+        PlcWorker::spawn(
+            &format!("{}-{}", role, index),
+            client,
+            config.request_timeout,
+            config.scheduler.clone(),
+        )
+    }
+
+    /// Picks a connection for `role` round robin, preferring healthy ones.
+    fn select(&self, role: ConnectionRole) -> PlcWorker {
+        let connections = self.connections.read().unwrap();
+        let all = candidates_or_writers(&connections, role);
+        let healthy: Vec<&PooledConnection> = all
+            .iter()
+            .copied()
+            .filter(|connection| connection.healthy)
+            .collect();
+        let candidates = if healthy.is_empty() { all } else { healthy };
+
+        let next = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        candidates[next % candidates.len()].worker.clone()
+    }
+
+    pub async fn read<R, F>(&self, priority: Priority, request: F) -> Result<R, anyhow::Error>
+    where
+        F: FnOnce(&dyn PlcClient) -> Result<R, anyhow::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.select(ConnectionRole::Read)
+            .call(priority, request)
+            .await
+    }
+
+    pub async fn write<R, F>(&self, priority: Priority, request: F) -> Result<R, anyhow::Error>
+    where
+        F: FnOnce(&dyn PlcClient) -> Result<R, anyhow::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.select(ConnectionRole::Write)
+            .call(priority, request)
+            .await
+    }
+
+    /// Runs `request` on every connection, e.g. to connect or set the session password.
+    /// Every connection is attempted; the first error is returned.
+    pub async fn broadcast<F>(&self, priority: Priority, request: F) -> Result<(), anyhow::Error>
+    where
+        F: Fn(&dyn PlcClient) -> Result<(), anyhow::Error> + Clone + Send + 'static,
+    {
+        let workers: Vec<PlcWorker> = {
+            let connections = self.connections.read().unwrap();
+            connections
+                .iter()
+                .map(|connection| connection.worker.clone())
+                .collect()
+        };
+
+        let mut first_error = None;
+        for (index, worker) in workers.into_iter().enumerate() {
+            let result = worker.call(priority, request.clone()).await;
+            self.set_healthy(index, result.is_ok());
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn set_healthy(&self, index: usize, healthy: bool) {
+        if let Some(connection) = self.connections.write().unwrap().get_mut(index) {
+            connection.healthy = healthy;
+        }
+    }
+
+    /// Whether the worker is executing a request that is not overdue yet. Such a
+    /// connection is working, a probe would only queue behind e.g. a long upload.
+    fn in_progress(&self, worker: &PlcWorker) -> bool {
+        worker
+            .busy_for()
+            .is_some_and(|busy| busy < self.config.request_timeout)
+    }
+
+    /// Probes every idle connection and replaces those that are disconnected or hung
+    /// with a fresh client set up by `connect`. Connections busy with a request are left
+    /// alone unless that request has been running for longer than the request timeout.
+    pub async fn check_health<F>(&self, connect: F)
+    where
+        F: Fn(&dyn PlcClient) -> Result<(), anyhow::Error> + Clone + Send + 'static,
+    {
+        let workers: Vec<(ConnectionRole, PlcWorker)> = {
+            let connections = self.connections.read().unwrap();
+            connections
+                .iter()
+                .map(|connection| (connection.role, connection.worker.clone()))
+                .collect()
+        };
+
+        for (index, (role, worker)) in workers.into_iter().enumerate() {
+            if self.in_progress(&worker) {
+                continue;
+            }
+
+            let probe = worker
+                .call_with_timeout(Priority::Hmi, self.config.health_check_timeout, |client| {
+                    if !client.is_connected()? {
+                        anyhow::bail!("not connected");
+                    }
+                    client.get_plc_status().map(|_| ())
+                })
+                .await;
+
+            let Err(e) = probe else {
+                self.set_healthy(index, true);
+                continue;
+            };
+            // The probe may have queued behind a request that started after the check
+            if self.in_progress(&worker) {
+                continue;
+            }
+
+            println!(
+                "** PLC connection {} ({}) is unhealthy, replacing it: {:?}",
+                index, role, e
+            );
+            let replacement = Self::spawn_worker(&self.config, role, index);
+            let connected = replacement.call(Priority::Hmi, connect.clone()).await;
+            if let Err(e) = &connected {
+                println!("** Failed to reconnect PLC connection {}: {:?}", index, e);
+            }
+
+            if let Some(connection) = self.connections.write().unwrap().get_mut(index) {
+                connection.worker = replacement;
+                connection.healthy = connected.is_ok();
+                connection.replacements += 1;
+            }
+        }
+    }
+
+    pub fn status(&self) -> Vec<ConnectionStatus> {
+        let connections = self.connections.read().unwrap();
+        connections
+            .iter()
+            .enumerate()
+            .map(|(index, connection)| ConnectionStatus {
+                index,
+                role: connection.role,
+                healthy: connection.healthy,
+                replacements: connection.replacements,
+                scheduler: connection.worker.metrics(),
+            })
+            .collect()
+    }
+}
+
+/// Connections for `role` regardless of health, or the writers when the pool has
+/// no connection of that role.
+fn candidates_or_writers(
+    connections: &[PooledConnection],
+    role: ConnectionRole,
+) -> Vec<&PooledConnection> {
+    let for_role: Vec<&PooledConnection> = connections
+        .iter()
+        .filter(|connection| connection.role == role)
+        .collect();
+    if !for_role.is_empty() {
+        return for_role;
+    }
+    connections
+        .iter()
+        .filter(|connection| connection.role == ConnectionRole::Write)
+        .collect()
+}
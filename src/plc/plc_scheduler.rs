@@ -0,0 +1,243 @@
+use serde::Serialize;
+use super::plc_client::PlcClient;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+pub type Job = Box<dyn FnOnce(&dyn PlcClient) -> JobOutcome + Send>;
+
+/// Whether a job actually talked to the PLC or was skipped because its caller gave up.
+/// An executed job hands back the delivery of its result, which the scheduler runs once
+/// the job is no longer counted as executing.
+pub enum JobOutcome {
+    Executed(Box<dyn FnOnce() + Send>),
+    Cancelled,
+}
+
+/// Request classes, highest priority first.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Safety,
+    Control,
+    Hmi,
+    Bulk,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Safety,
+        Priority::Control,
+        Priority::Hmi,
+        Priority::Bulk,
+    ];
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ClassMetrics {
+    pub queue_depth: usize,
+    pub completed: u64,
+    pub cancelled: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: f64,
+    pub avg_exec_ms: f64,
+    pub max_exec_ms: f64,
+    #[serde(skip)]
+    total_wait: Duration,
+    #[serde(skip)]
+    total_exec: Duration,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SchedulerMetrics {
+    pub safety: ClassMetrics,
+    pub control: ClassMetrics,
+    pub hmi: ClassMetrics,
+    pub bulk: ClassMetrics,
+}
+
+struct QueuedJob {
+    job: Job,
+    enqueued_at: Instant,
+}
+
+struct Queues {
+    classes: [VecDeque<QueuedJob>; 4],
+    metrics: [ClassMetrics; 4],
+    last_bulk_started: Option<Instant>,
+    executing_since: Option<Instant>, // Start of the job running right now
+    closed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub bulk_min_interval: Duration, // Minimum gap between two bulk requests
+    pub aging_threshold: Duration,   // Jobs waiting longer than this are served first
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            bulk_min_interval: Duration::from_millis(50),
+            aging_threshold: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Priority queues feeding one PLC connection. Higher classes are always served
+/// first, except that jobs waiting past the aging threshold are promoted so bulk
+/// work cannot starve forever, and bulk jobs are rate limited.
+pub struct PlcScheduler {
+    config: SchedulerConfig,
+    queues: Mutex<Queues>,
+    available: Condvar,
+}
+
+impl PlcScheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        PlcScheduler {
+            config,
+            queues: Mutex::new(Queues {
+                classes: Default::default(),
+                metrics: Default::default(),
+                last_bulk_started: None,
+                executing_since: None,
+                closed: false,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn push(&self, priority: Priority, job: Job) -> Result<(), anyhow::Error> {
+        let mut queues = self.queues.lock().unwrap();
+        if queues.closed {
+            anyhow::bail!("PLC worker is not running");
+        }
+        queues.classes[priority.index()].push_back(QueuedJob {
+            job,
+            enqueued_at: Instant::now(),
+        });
+        self.available.notify_one();
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.queues.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+
+    /// Blocks until a job may run. Returns `None` once the scheduler is closed.
+    fn next(&self) -> Option<(Priority, QueuedJob)> {
+        let mut queues = self.queues.lock().unwrap();
+        loop {
+            if queues.closed {
+                return None;
+            }
+
+            let now = Instant::now();
+            let bulk_ready_at = queues
+                .last_bulk_started
+                .map(|started| started + self.config.bulk_min_interval);
+            let bulk_allowed = bulk_ready_at.is_none_or(|ready_at| now >= ready_at);
+
+            let runnable = |priority: &Priority| priority != &Priority::Bulk || bulk_allowed;
+            let aged = Priority::ALL.into_iter().filter(runnable).find(|priority| {
+                queues.classes[priority.index()]
+                    .front()
+                    .is_some_and(|job| now - job.enqueued_at > self.config.aging_threshold)
+            });
+            let picked = aged.or_else(|| {
+                Priority::ALL
+                    .into_iter()
+                    .filter(runnable)
+                    .find(|priority| !queues.classes[priority.index()].is_empty())
+            });
+
+            if let Some(priority) = picked {
+                let job = queues.classes[priority.index()].pop_front()?;
+                if priority == Priority::Bulk {
+                    queues.last_bulk_started = Some(now);
+                }
+                queues.executing_since = Some(now);
+                return Some((priority, job));
+            }
+
+            // Only rate limited bulk work is waiting, sleep until it may run
+            queues = match bulk_ready_at {
+                Some(ready_at) if !queues.classes[Priority::Bulk.index()].is_empty() => {
+                    self.available
+                        .wait_timeout(queues, ready_at.saturating_duration_since(now))
+                        .unwrap()
+                        .0
+                }
+                _ => self.available.wait(queues).unwrap(),
+            };
+        }
+    }
+
+    /// Executes jobs until the scheduler is closed. Runs on the worker thread.
+    pub fn run(&self, client: &dyn PlcClient) {
+        while let Some((priority, queued)) = self.next() {
+            let started = Instant::now();
+            let outcome = (queued.job)(client);
+            let finished = Instant::now();
+
+            let mut queues = self.queues.lock().unwrap();
+            queues.executing_since = None;
+            let metrics = &mut queues.metrics[priority.index()];
+            let deliver = match outcome {
+                JobOutcome::Executed(deliver) => {
+                    let wait = started - queued.enqueued_at;
+                    let exec = finished - started;
+                    metrics.completed += 1;
+                    metrics.total_wait += wait;
+                    metrics.total_exec += exec;
+                    metrics.max_wait_ms = metrics.max_wait_ms.max(wait.as_secs_f64() * 1000.0);
+                    metrics.max_exec_ms = metrics.max_exec_ms.max(exec.as_secs_f64() * 1000.0);
+                    Some(deliver)
+                }
+                JobOutcome::Cancelled => {
+                    metrics.cancelled += 1;
+                    None
+                }
+            };
+            drop(queues);
+
+            if let Some(deliver) = deliver {
+                deliver();
+            }
+        }
+    }
+
+    /// How long the job being executed has been running, `None` when idle.
+    pub fn executing_for(&self) -> Option<Duration> {
+        let queues = self.queues.lock().unwrap();
+        queues.executing_since.map(|since| since.elapsed())
+    }
+
+    pub fn metrics(&self) -> SchedulerMetrics {
+        let queues = self.queues.lock().unwrap();
+        let class = |priority: Priority| {
+            let mut metrics = queues.metrics[priority.index()].clone();
+            metrics.queue_depth = queues.classes[priority.index()].len();
+            if metrics.completed > 0 {
+                metrics.avg_wait_ms =
+                    metrics.total_wait.as_secs_f64() * 1000.0 / metrics.completed as f64;
+                metrics.avg_exec_ms =
+                    metrics.total_exec.as_secs_f64() * 1000.0 / metrics.completed as f64;
+            }
+            metrics
+        };
+
+        SchedulerMetrics {
+            safety: class(Priority::Safety),
+            control: class(Priority::Control),
+            hmi: class(Priority::Hmi),
+            bulk: class(Priority::Bulk),
+        }
+    }
+}
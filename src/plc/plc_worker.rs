@@ -0,0 +1,102 @@
+use super::plc_scheduler::{
+    Job, JobOutcome, PlcScheduler, Priority, SchedulerConfig, SchedulerMetrics,
+};
+use super::plc_client::PlcClient;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+/// Closes the scheduler once the last `PlcWorker` handle is dropped, which ends the thread.
+struct WorkerHandle {
+    scheduler: Arc<PlcScheduler>,
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        self.scheduler.close();
+    }
+}
+
+/// Owns a `PlcClient` on a dedicated thread so its blocking network calls never run
+/// on the Tokio workers. Requests are queued by priority and executed one at a time.
+#[derive(Clone)]
+pub struct PlcWorker {
+    handle: Arc<WorkerHandle>,
+    pub default_timeout: Duration,
+}
+
+impl PlcWorker {
+    pub fn spawn(
+        name: &str,
+        client: Box<dyn PlcClient>,
+        default_timeout: Duration,
+        config: SchedulerConfig,
+    ) -> Self {
+        let scheduler = Arc::new(PlcScheduler::new(config));
+        let thread_scheduler = scheduler.clone();
+
+        thread::Builder::new()
+            .name(format!("plc-{}", name))
+            .spawn(move || thread_scheduler.run(client.as_ref()))
+            .expect("failed to spawn PLC worker thread");
+
+        PlcWorker {
+            handle: Arc::new(WorkerHandle { scheduler }),
+            default_timeout,
+        }
+    }
+
+    pub fn metrics(&self) -> SchedulerMetrics {
+        self.handle.scheduler.metrics()
+    }
+
+    /// How long the worker has been executing its current request, `None` when idle.
+    pub fn busy_for(&self) -> Option<Duration> {
+        self.handle.scheduler.executing_for()
+    }
+
+    pub async fn call<R, F>(&self, priority: Priority, request: F) -> Result<R, anyhow::Error>
+    where
+        F: FnOnce(&dyn PlcClient) -> Result<R, anyhow::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.call_with_timeout(priority, self.default_timeout, request)
+            .await
+    }
+
+    /// Runs `request` on the worker thread. When the timeout expires or the returned
+    /// future is dropped, a request that has not started yet is skipped.
+    pub async fn call_with_timeout<R, F>(
+        &self,
+        priority: Priority,
+        timeout: Duration,
+        request: F,
+    ) -> Result<R, anyhow::Error>
+    where
+        F: FnOnce(&dyn PlcClient) -> Result<R, anyhow::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let job: Job = Box::new(move |client| {
+            if result_sender.is_closed() {
+                return JobOutcome::Cancelled;
+            }
+            let result = request(client);
+            JobOutcome::Executed(Box::new(move || {
+                let _ = result_sender.send(result);
+            }))
+        });
+
+        self.handle.scheduler.push(priority, job)?;
+
+        match tokio::time::timeout(timeout, result_receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(anyhow::anyhow!("PLC worker dropped the request")),
+            Err(_) => Err(anyhow::anyhow!(
+                "PLC request timed out after {} ms",
+                timeout.as_millis()
+            )),
+        }
+    }
+}
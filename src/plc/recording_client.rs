@@ -0,0 +1,370 @@
+use super::plc_client::{AreaItem, ClientFactory, ConnectTarget, PlcArea, PlcClient, Protection};
+use crate::blocks::{BlockDetails, BlockKind};
+use chrono::NaiveDateTime;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, anyhow::Error>;
+
+/// One request to the PLC and what it answered. `at_ms` is when the request was issued,
+/// counted from the start of the recording.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlcExchange {
+    pub connection: usize,
+    pub at_ms: u64,
+    pub duration_ms: u64,
+    pub operation: String,
+    pub request: Value,
+    pub response: std::result::Result<Value, String>,
+}
+
+/// Exchanges recorded from, or to be replayed to, the connections of a pool. A recording
+/// made with `create` goes straight to a JSON Lines file instead of memory, so it can run
+/// for as long as the service does and survives a crash.
+#[derive(Clone)]
+pub struct Recording {
+    exchanges: Arc<Mutex<Vec<PlcExchange>>>,
+    replayed: Arc<Mutex<Vec<bool>>>,
+    sink: Option<Arc<Mutex<LineWriter<File>>>>,
+    started: Instant,
+    next_connection: Arc<AtomicUsize>,
+}
+
+impl Recording {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::from_exchanges(Vec::new())
+    }
+
+    pub fn from_exchanges(exchanges: Vec<PlcExchange>) -> Self {
+        let replayed = vec![false; exchanges.len()];
+        Recording {
+            exchanges: Arc::new(Mutex::new(exchanges)),
+            replayed: Arc::new(Mutex::new(replayed)),
+            sink: None,
+            started: Instant::now(),
+            next_connection: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Records to `path`, replacing any previous recording there.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)?;
+        let mut recording = Self::from_exchanges(Vec::new());
+        recording.sink = Some(Arc::new(Mutex::new(LineWriter::new(file))));
+        Ok(recording)
+    }
+
+    /// Loads a recording written by `create` for replay.
+    pub fn load(path: &str) -> Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        let mut exchanges = Vec::new();
+        for (index, line) in file.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange = serde_json::from_str(&line)
+                .map_err(|e| anyhow::anyhow!("{} line {}: {}", path, index + 1, e))?;
+            exchanges.push(exchange);
+        }
+        Ok(Self::from_exchanges(exchanges))
+    }
+
+    #[cfg(test)]
+    pub fn exchanges(&self) -> Vec<PlcExchange> {
+        self.exchanges.lock().unwrap().clone()
+    }
+
+    /// Creates recording clients around the clients of `backend` for a pool.
+    pub fn recorder(&self, backend: ClientFactory) -> ClientFactory {
+        let recording = self.clone();
+        Arc::new(move || Box::new(recording.record(backend())) as Box<dyn PlcClient>)
+    }
+
+    /// Creates replaying clients for a pool.
+    pub fn player(&self) -> ClientFactory {
+        let recording = self.clone();
+        Arc::new(move || Box::new(recording.replay()) as Box<dyn PlcClient>)
+    }
+
+    /// A client that passes requests to `inner` and records them here.
+    pub fn record(&self, inner: Box<dyn PlcClient>) -> RecordingClient {
+        RecordingClient {
+            recording: self.clone(),
+            connection: self.next_connection.fetch_add(1, Ordering::Relaxed),
+            inner: Some(inner),
+        }
+    }
+
+    /// A client that answers from the recorded exchanges without any PLC.
+    pub fn replay(&self) -> RecordingClient {
+        RecordingClient {
+            recording: self.clone(),
+            connection: self.next_connection.fetch_add(1, Ordering::Relaxed),
+            inner: None,
+        }
+    }
+
+    fn push(&self, exchange: PlcExchange) {
+        let Some(sink) = &self.sink else {
+            self.exchanges.lock().unwrap().push(exchange);
+            self.replayed.lock().unwrap().push(false);
+            return;
+        };
+
+        let written = serde_json::to_string(&exchange)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(sink.lock().unwrap(), "{}", line)?));
+        if let Err(e) = written {
+            println!("** Failed to record PLC exchange: {:?}", e);
+        }
+    }
+
+    /// The first exchange not replayed yet with the same operation and request. Matching on
+    /// the request rather than the order keeps replay deterministic while pooled connections
+    /// interleave their requests differently from the recording.
+    ///
+    /// Requests whose answer carries no data (connects, writes) fall back to the next one of
+    /// the same operation, so e.g. a setpoint written with a slightly different value does not
+    /// end the replay. The divergence is logged.
+    fn take(&self, operation: &str, request: &Value) -> Option<PlcExchange> {
+        let exchanges = self.exchanges.lock().unwrap();
+        let mut replayed = self.replayed.lock().unwrap();
+        let unused = || {
+            exchanges
+                .iter()
+                .zip(replayed.iter())
+                .enumerate()
+                .filter(|(_, (exchange, used))| !**used && exchange.operation == operation)
+        };
+
+        let exact = unused()
+            .find(|(_, (exchange, _))| exchange.request == *request)
+            .map(|(index, _)| index);
+        let index = match exact {
+            Some(index) => index,
+            None => {
+                let (index, (exchange, _)) = unused().find(|(_, (exchange, _))| {
+                    matches!(exchange.response, Ok(Value::Null) | Err(_))
+                })?;
+                println!(
+                    "** Replay diverged: {} {} was recorded as {}",
+                    operation, request, exchange.request
+                );
+                index
+            }
+        };
+        replayed[index] = true;
+        Some(exchanges[index].clone())
+    }
+}
+
+/// Records the traffic of the wrapped client, or replays a recording when there is none.
+/// Replayed requests take as long as they did when recorded.
+pub struct RecordingClient {
+    recording: Recording,
+    connection: usize,
+    inner: Option<Box<dyn PlcClient>>,
+}
+
+impl RecordingClient {
+    fn exchange<T: Serialize + DeserializeOwned>(
+        &self,
+        operation: &str,
+        request: Value,
+        call: impl FnOnce(&dyn PlcClient) -> Result<T>,
+    ) -> Result<T> {
+        let Some(inner) = &self.inner else {
+            let exchange = self.recording.take(operation, &request).ok_or_else(|| {
+                anyhow::anyhow!("No recorded response for {} {}", operation, request)
+            })?;
+            thread::sleep(Duration::from_millis(exchange.duration_ms));
+            return match exchange.response {
+                Ok(value) => Ok(serde_json::from_value(value)?),
+                Err(e) => Err(anyhow::anyhow!(e)),
+            };
+        };
+
+        let at = self.recording.started.elapsed();
+        let result = call(inner.as_ref());
+        let duration = self.recording.started.elapsed() - at;
+        let response = match &result {
+            Ok(value) => Ok(serde_json::to_value(value)?),
+            Err(e) => Err(e.to_string()),
+        };
+        self.recording.push(PlcExchange {
+            connection: self.connection,
+            at_ms: at.as_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            operation: operation.to_string(),
+            request,
+            response,
+        });
+        result
+    }
+}
+
+impl PlcClient for RecordingClient {
+    fn connect(&self, target: &ConnectTarget) -> Result<()> {
+        self.exchange("connect", json!(target), |client| client.connect(target))
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        self.exchange("disconnect", Value::Null, |client| client.disconnect())
+    }
+
+    fn is_connected(&self) -> Result<bool> {
+        self.exchange("is_connected", Value::Null, |client| client.is_connected())
+    }
+
+    fn set_session_password(&self, password: Option<&str>) -> Result<()> {
+        // Only whether a password was set, recordings must not leak it
+        self.exchange(
+            "set_session_password",
+            json!(password.is_some()),
+            |client| client.set_session_password(password),
+        )
+    }
+
+    fn get_protection(&self) -> Result<Protection> {
+        self.exchange("get_protection", Value::Null, |client| {
+            client.get_protection()
+        })
+    }
+
+    fn get_plc_status(&self) -> Result<i32> {
+        self.exchange("get_plc_status", Value::Null, |client| {
+            client.get_plc_status()
+        })
+    }
+
+    fn plc_stop(&self) -> Result<()> {
+        self.exchange("plc_stop", Value::Null, |client| client.plc_stop())
+    }
+
+    fn plc_hot_start(&self) -> Result<()> {
+        self.exchange("plc_hot_start", Value::Null, |client| {
+            client.plc_hot_start()
+        })
+    }
+
+    fn plc_cold_start(&self) -> Result<()> {
+        self.exchange("plc_cold_start", Value::Null, |client| {
+            client.plc_cold_start()
+        })
+    }
+
+    fn get_plc_date_time(&self) -> Result<NaiveDateTime> {
+        self.exchange("get_plc_date_time", Value::Null, |client| {
+            client.get_plc_date_time()
+        })
+    }
+
+    fn set_plc_date_time(&self, value: NaiveDateTime) -> Result<()> {
+        self.exchange("set_plc_date_time", json!(value), |client| {
+            client.set_plc_date_time(value)
+        })
+    }
+
+    fn read_area(&self, area: PlcArea, start: usize, buff: &mut [u8]) -> Result<()> {
+        let request = json!({ "area": area, "start": start, "size": buff.len() });
+        let data: Vec<u8> = self.exchange("read_area", request, |client| {
+            let mut data = vec![0u8; buff.len()];
+            client.read_area(area, start, &mut data)?;
+            Ok(data)
+        })?;
+        if data.len() != buff.len() {
+            anyhow::bail!(
+                "Recorded read_area returned {} bytes, expected {}",
+                data.len(),
+                buff.len()
+            );
+        }
+        buff.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn write_area(&self, area: PlcArea, start: usize, data: &[u8]) -> Result<()> {
+        let request = json!({ "area": area, "start": start, "data": data });
+        self.exchange("write_area", request, |client| {
+            client.write_area(area, start, data)
+        })
+    }
+
+    fn read_bit(&self, area: PlcArea, byte: usize, bit: u8) -> Result<bool> {
+        let request = json!({ "area": area, "byte": byte, "bit": bit });
+        self.exchange("read_bit", request, |client| {
+            client.read_bit(area, byte, bit)
+        })
+    }
+
+    fn write_bit(&self, area: PlcArea, byte: usize, bit: u8, value: bool) -> Result<()> {
+        let request = json!({ "area": area, "byte": byte, "bit": bit, "value": value });
+        self.exchange("write_bit", request, |client| {
+            client.write_bit(area, byte, bit, value)
+        })
+    }
+
+    fn read_multi_vars(
+        &self,
+        items: &[AreaItem],
+    ) -> Result<Vec<std::result::Result<Vec<u8>, String>>> {
+        self.exchange("read_multi_vars", json!(items), |client| {
+            client.read_multi_vars(items)
+        })
+    }
+
+    fn write_multi_vars(
+        &self,
+        items: &[(AreaItem, Vec<u8>)],
+    ) -> Result<Vec<std::result::Result<(), String>>> {
+        self.exchange("write_multi_vars", json!(items), |client| {
+            client.write_multi_vars(items)
+        })
+    }
+
+    fn list_blocks(&self) -> Result<Vec<(BlockKind, i32)>> {
+        self.exchange("list_blocks", Value::Null, |client| client.list_blocks())
+    }
+
+    fn list_blocks_of_type(&self, kind: BlockKind) -> Result<Vec<u16>> {
+        self.exchange("list_blocks_of_type", json!(kind), |client| {
+            client.list_blocks_of_type(kind)
+        })
+    }
+
+    fn get_block_info(&self, kind: BlockKind, number: i32) -> Result<BlockDetails> {
+        let request = json!({ "kind": kind, "number": number });
+        self.exchange("get_block_info", request, |client| {
+            client.get_block_info(kind, number)
+        })
+    }
+
+    fn full_upload(&self, kind: BlockKind, number: i32) -> Result<Vec<u8>> {
+        let request = json!({ "kind": kind, "number": number });
+        self.exchange("full_upload", request, |client| {
+            client.full_upload(kind, number)
+        })
+    }
+
+    fn download(&self, data: &[u8]) -> Result<()> {
+        self.exchange("download", json!(data), |client| client.download(data))
+    }
+
+    fn db_get(&self, number: i32, size: usize) -> Result<Vec<u8>> {
+        let request = json!({ "number": number, "size": size });
+        self.exchange("db_get", request, |client| client.db_get(number, size))
+    }
+
+    fn db_fill(&self, number: i32, value: u8) -> Result<()> {
+        let request = json!({ "number": number, "value": value });
+        self.exchange("db_fill", request, |client| client.db_fill(number, value))
+    }
+}
@@ -0,0 +1,356 @@
+use super::plc_client::{AreaItem, ConnectTarget, ConnectionType, PlcArea, PlcClient, Protection};
+use crate::blocks::{BlockDetails, BlockKind};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use snap7_rs::{
+    AreaTable, ConnType, DateTime, InternalParam, InternalParamValue, S7Client, TS7BlockInfo,
+    TS7BlocksList, TS7BlocksOfType, TS7DataItem, TS7Protection, WordLenTable,
+};
+use std::os::raw::c_void;
+
+type Result<T> = std::result::Result<T, anyhow::Error>;
+
+impl ConnectionType {
+    fn conn_type(&self) -> ConnType {
+        match self {
+            ConnectionType::Pg => ConnType::PG,
+            ConnectionType::Op => ConnType::OP,
+            ConnectionType::S7Basic => ConnType::S7Basic(0x03),
+        }
+    }
+}
+
+impl PlcArea {
+    fn area_table(&self) -> (AreaTable, i32) {
+        match self {
+            PlcArea::Inputs => (AreaTable::S7AreaPE, 0),
+            PlcArea::Outputs => (AreaTable::S7AreaPA, 0),
+            PlcArea::Flags => (AreaTable::S7AreaMK, 0),
+            PlcArea::Db(number) => (AreaTable::S7AreaDB, *number),
+        }
+    }
+}
+
+fn data_item(item: &AreaItem, data: &mut [u8]) -> TS7DataItem {
+    let (area, db_number) = item.area.area_table();
+    TS7DataItem {
+        Area: area as i32,
+        WordLen: WordLenTable::S7WLByte as i32,
+        Result: 0,
+        DBNumber: db_number,
+        Start: item.start as i32,
+        Amount: item.size as i32,
+        pdata: data.as_mut_ptr() as *mut c_void,
+    }
+}
+
+fn item_result(code: i32) -> std::result::Result<(), String> {
+    match code {
+        0 => Ok(()),
+        code => Err(S7Client::error_text(code)),
+    }
+}
+
+/// The S7 protocol through snap7.
+pub struct Snap7Client {
+    client: S7Client,
+}
+
+impl Snap7Client {
+    pub fn new() -> Self {
+        Snap7Client {
+            client: S7Client::create(),
+        }
+    }
+}
+
+impl PlcClient for Snap7Client {
+    fn connect(&self, target: &ConnectTarget) -> Result<()> {
+        // Connection parameters can only be changed while disconnected
+        if self.is_connected()? {
+            self.client.disconnect()?;
+        }
+
+        let params = &target.params;
+        self.client
+            .set_connection_type(params.connection_type.conn_type())?;
+        self.client.set_param(
+            InternalParam::RemotePort,
+            InternalParamValue::U16(params.port),
+        )?;
+
+        let timeouts = [
+            (InternalParam::PingTimeout, params.connect_timeout_ms),
+            (InternalParam::RecvTimeout, params.recv_timeout_ms),
+            (InternalParam::SendTimeout, params.send_timeout_ms),
+        ];
+        for (param, timeout) in timeouts {
+            if let Some(timeout) = timeout {
+                self.client
+                    .set_param(param, InternalParamValue::I32(timeout))?;
+            }
+        }
+
+        match (params.local_tsap, params.remote_tsap) {
+            (Some(local_tsap), Some(remote_tsap)) => {
+                self.client
+                    .set_connection_params(&target.address, local_tsap, remote_tsap)?;
+                self.client.connect()
+            }
+            _ => self
+                .client
+                .connect_to(&target.address, target.rack, target.slot),
+        }
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        self.client.disconnect()
+    }
+
+    fn is_connected(&self) -> Result<bool> {
+        let mut connected = 0;
+        self.client.get_connected(&mut connected)?;
+        Ok(connected != 0)
+    }
+
+    fn set_session_password(&self, password: Option<&str>) -> Result<()> {
+        match password {
+            Some(password) => self.client.set_session_password(password),
+            None => self.client.clear_session_password(),
+        }
+    }
+
+    fn get_protection(&self) -> Result<Protection> {
+        let mut protection = TS7Protection {
+            sch_schal: 0,
+            sch_par: 0,
+            sch_rel: 0,
+            bart_sch: 0,
+            anl_sch: 0,
+        };
+        self.client.get_protection(&mut protection)?;
+        Ok(Protection {
+            protection_level: protection.sch_rel,
+            selector_level: protection.sch_schal,
+            password_level: protection.sch_par,
+            mode_selector: protection.bart_sch,
+            startup_switch: protection.anl_sch,
+        })
+    }
+
+    fn get_plc_status(&self) -> Result<i32> {
+        let mut status = 0;
+        self.client.get_plc_status(&mut status)?;
+        Ok(status)
+    }
+
+    fn plc_stop(&self) -> Result<()> {
+        self.client.plc_stop()
+    }
+
+    fn plc_hot_start(&self) -> Result<()> {
+        self.client.plc_hot_start()
+    }
+
+    fn plc_cold_start(&self) -> Result<()> {
+        self.client.plc_cold_start()
+    }
+
+    fn get_plc_date_time(&self) -> Result<NaiveDateTime> {
+        let mut date_time = DateTime::default();
+        self.client.get_plc_date_time(&mut date_time)?;
+
+        let (year, month, day) = (date_time.tm_year, date_time.tm_mon, date_time.tm_mday);
+        let (hour, minute, second) = (date_time.tm_hour, date_time.tm_min, date_time.tm_sec);
+        NaiveDate::from_ymd_opt(year + 1900, (month + 1) as u32, day as u32)
+            .and_then(|date| date.and_hms_opt(hour as u32, minute as u32, second as u32))
+            .ok_or_else(|| anyhow::anyhow!("PLC returned an invalid date/time"))
+    }
+
+    fn set_plc_date_time(&self, value: NaiveDateTime) -> Result<()> {
+        let mut date_time = DateTime {
+            tm_sec: value.second() as i32,
+            tm_min: value.minute() as i32,
+            tm_hour: value.hour() as i32,
+            tm_mday: value.day() as i32,
+            tm_mon: value.month0() as i32,
+            tm_year: value.year() - 1900,
+            tm_wday: value.weekday().num_days_from_sunday() as i32,
+            tm_yday: value.ordinal0() as i32,
+            tm_isdst: -1,
+        };
+        self.client.set_plc_date_time(&mut date_time)
+    }
+
+    fn read_area(&self, area: PlcArea, start: usize, buff: &mut [u8]) -> Result<()> {
+        let (area, db_number) = area.area_table();
+        let size = buff.len() as i32;
+        self.client.read_area(
+            area,
+            db_number,
+            start as i32,
+            size,
+            WordLenTable::S7WLByte,
+            buff,
+        )
+    }
+
+    fn write_area(&self, area: PlcArea, start: usize, data: &[u8]) -> Result<()> {
+        let (area, db_number) = area.area_table();
+        let mut buffer = data.to_vec();
+        self.client.write_area(
+            area,
+            db_number,
+            start as i32,
+            buffer.len() as i32,
+            WordLenTable::S7WLByte,
+            &mut buffer,
+        )
+    }
+
+    fn read_bit(&self, area: PlcArea, byte: usize, bit: u8) -> Result<bool> {
+        let (area, db_number) = area.area_table();
+        let mut buffer = [0u8; 1];
+        // Bit accesses are addressed in bits
+        let bit_address = (byte * 8) as i32 + bit as i32;
+        self.client.read_area(
+            area,
+            db_number,
+            bit_address,
+            1,
+            WordLenTable::S7WLBit,
+            &mut buffer,
+        )?;
+        Ok(buffer[0] != 0)
+    }
+
+    fn write_bit(&self, area: PlcArea, byte: usize, bit: u8, value: bool) -> Result<()> {
+        let (area, db_number) = area.area_table();
+        let mut buffer = [value as u8];
+        let bit_address = (byte * 8) as i32 + bit as i32;
+        self.client.write_area(
+            area,
+            db_number,
+            bit_address,
+            1,
+            WordLenTable::S7WLBit,
+            &mut buffer,
+        )
+    }
+
+    fn read_multi_vars(
+        &self,
+        items: &[AreaItem],
+    ) -> Result<Vec<std::result::Result<Vec<u8>, String>>> {
+        if items.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut buffers: Vec<Vec<u8>> = items.iter().map(|item| vec![0u8; item.size]).collect();
+        let mut data_items: Vec<TS7DataItem> = items
+            .iter()
+            .zip(buffers.iter_mut())
+            .map(|(item, buffer)| data_item(item, buffer))
+            .collect();
+
+        self.client
+            .read_multi_vars(&mut data_items, items.len() as i32)?;
+
+        Ok(data_items
+            .iter()
+            .zip(buffers)
+            .map(|(item, buffer)| item_result(item.Result).map(|_| buffer))
+            .collect())
+    }
+
+    fn write_multi_vars(
+        &self,
+        items: &[(AreaItem, Vec<u8>)],
+    ) -> Result<Vec<std::result::Result<(), String>>> {
+        if items.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut buffers: Vec<Vec<u8>> = items.iter().map(|(_, data)| data.clone()).collect();
+        let mut data_items: Vec<TS7DataItem> = items
+            .iter()
+            .zip(buffers.iter_mut())
+            .map(|((item, _), buffer)| data_item(item, buffer))
+            .collect();
+
+        self.client
+            .write_multi_vars(&mut data_items, items.len() as i32)?;
+
+        Ok(data_items
+            .iter()
+            .map(|item| item_result(item.Result))
+            .collect())
+    }
+
+    fn list_blocks(&self) -> Result<Vec<(BlockKind, i32)>> {
+        let mut list = TS7BlocksList {
+            OBCount: 0,
+            FBCount: 0,
+            FCCount: 0,
+            SFBCount: 0,
+            SFCCount: 0,
+            DBCount: 0,
+            SDBCount: 0,
+        };
+        self.client.list_blocks(&mut list)?;
+
+        Ok(vec![
+            (BlockKind::Ob, list.OBCount),
+            (BlockKind::Fb, list.FBCount),
+            (BlockKind::Fc, list.FCCount),
+            (BlockKind::Db, list.DBCount),
+            (BlockKind::Sdb, list.SDBCount),
+            (BlockKind::Sfc, list.SFCCount),
+            (BlockKind::Sfb, list.SFBCount),
+        ])
+    }
+
+    fn list_blocks_of_type(&self, kind: BlockKind) -> Result<Vec<u16>> {
+        let mut buffer: Box<TS7BlocksOfType> = Box::new([0; 8192]);
+        let mut items_count = buffer.len() as i32;
+        self.client
+            .list_blocks_of_type(kind.block_type(), &mut buffer, &mut items_count)?;
+
+        Ok(buffer[..items_count.max(0) as usize].to_vec())
+    }
+
+    fn get_block_info(&self, kind: BlockKind, number: i32) -> Result<BlockDetails> {
+        // TS7BlockInfo is a plain C struct, all zeroes is a valid value
+        let mut info: TS7BlockInfo = unsafe { std::mem::zeroed() };
+        self.client
+            .get_ag_block_info(kind.block_type(), number, &mut info)?;
+        Ok(BlockDetails::from(&info))
+    }
+
+    fn full_upload(&self, kind: BlockKind, number: i32) -> Result<Vec<u8>> {
+        let mut buffer = vec![0u8; 65536];
+        let mut size = buffer.len() as i32;
+        self.client
+            .full_upload(kind.block_type(), number, &mut buffer, &mut size)?;
+
+        buffer.truncate(size.max(0) as usize);
+        Ok(buffer)
+    }
+
+    fn download(&self, data: &[u8]) -> Result<()> {
+        let mut buffer = data.to_vec();
+        let size = buffer.len() as i32;
+        // -1 keeps the block number stored in the block itself
+        self.client.download(-1, &mut buffer, size)
+    }
+
+    fn db_get(&self, number: i32, size: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0u8; size.max(1)];
+        let mut size = buffer.len() as i32;
+        self.client.db_get(number, &mut buffer, &mut size)?;
+
+        buffer.truncate(size.max(0) as usize);
+        Ok(buffer)
+    }
+
+    fn db_fill(&self, number: i32, value: u8) -> Result<()> {
+        self.client.db_fill(number, value as i32)
+    }
+}
@@ -1,33 +1,91 @@
 use crate::{
+    audit::AuditLog,
+    auth::{Authenticator, Role},
+    blocks::{
+        BlockArchive, BlockBackup, BlockDetails, BlockKind, BlockWatch, ConfirmationTokens,
+        DbSnapshots,
+    },
+    clock::ClockSync,
+    config::{debug_logging, watch_config_file, AppConfig, ConfigSource, StartPolicy},
     controllers::{self, SharedState},
     heater::Heater,
-    middlewares::require_plc_connection,
+    lifecycle::BackgroundTasks,
+    middlewares::{require_authentication, require_plc_connection, require_role, Access},
+    persistence::StateStore,
+    plc::{
+        ConnectTarget, ConnectionParams, PlcArea, PlcClient, PlcPool, PoolConfig, Priority,
+        Protection,
+    },
+    tags::{TagPoller, TagValue},
 };
 use axum::{
     middleware,
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
+use chrono::NaiveDateTime;
 use pid::Pid;
 use serde::Deserialize;
-use snap7_rs::S7Client;
 use std::sync::Arc;
 use std::{fmt, time::Duration};
-use tokio::{sync::Mutex, time::sleep};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, Instant},
+};
+use validator::Validate;
+
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+// The integrator moves on every update, shutdown saves it too so this only bounds the loss on a crash
+const PID_SAVE_INTERVAL: Duration = Duration::from_secs(60);
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, PartialEq, Validate)]
 pub struct PLCConfig {
+    #[validate(length(min = 1, message = "address is required"))]
     pub address: String,
+    #[validate(range(min = 0, max = 7))]
     pub rack: i32,
+    #[validate(range(min = 0, max = 31))]
     pub slot: i32,
+    // Session password for protected CPUs, applied after every connect
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(flatten)]
+    #[validate(nested)]
+    pub connection: ConnectionParams,
+}
+
+impl Default for PLCConfig {
+    fn default() -> Self {
+        PLCConfig {
+            address: "192.168.0.1".to_string(),
+            rack: 0,
+            slot: 2,
+            password: None,
+            connection: ConnectionParams::default(),
+        }
+    }
+}
+
+impl fmt::Debug for PLCConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PLCConfig")
+            .field("address", &self.address)
+            .field("rack", &self.rack)
+            .field("slot", &self.slot)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("connection", &self.connection)
+            .finish()
+    }
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub s7_client: Arc<S7Client>,
+    pub plc: PlcPool,
     pub address: String,
     pub rack: i32,
     pub slot: i32,
+    pub password: Option<String>,
+    pub connection: ConnectionParams,
 }
 
 impl fmt::Debug for AppState {
@@ -36,43 +94,236 @@ impl fmt::Debug for AppState {
             .field("address", &self.address)
             .field("rack", &self.rack)
             .field("slot", &self.slot)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("connection", &self.connection)
             .finish()
     }
 }
 
 impl AppState {
-    pub fn connect_to_plc(&self) -> Result<(), anyhow::Error> {
-        self.s7_client
-            .connect_to(&self.address, self.rack, self.slot)
+    pub fn new(plc: PlcPool, config: PLCConfig) -> Self {
+        AppState {
+            plc,
+            address: config.address,
+            rack: config.rack,
+            slot: config.slot,
+            password: config.password,
+            connection: config.connection,
+        }
+    }
+
+    /// Builds the request that sets up one connection from the current settings. It is
+    /// run on every pooled connection and again when a broken one is replaced.
+    pub fn connect_job(
+        &self,
+    ) -> impl Fn(&dyn PlcClient) -> Result<(), anyhow::Error> + Clone + Send + 'static {
+        let target = ConnectTarget {
+            address: self.address.clone(),
+            rack: self.rack,
+            slot: self.slot,
+            params: self.connection.clone(),
+        };
+        let password = self.password.clone();
+
+        move |client: &dyn PlcClient| {
+            client.connect(&target)?;
+            // The session password only lives as long as the connection
+            match &password {
+                Some(password) => client.set_session_password(Some(password)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    pub async fn connect_to_plc(&self) -> Result<(), anyhow::Error> {
+        self.plc.broadcast(Priority::Hmi, self.connect_job()).await
+    }
+
+    pub async fn disconnect(&self) -> Result<(), anyhow::Error> {
+        self.plc
+            .broadcast(Priority::Hmi, |client: &dyn PlcClient| client.disconnect())
+            .await
+    }
+
+    /// Sets or clears the session password on the open connection.
+    pub async fn set_session_password(&mut self, password: Option<String>) -> Result<(), anyhow::Error> {
+        let new_password = password.clone();
+        self.plc
+            .broadcast(Priority::Hmi, move |client: &dyn PlcClient| {
+                client.set_session_password(new_password.as_deref())
+            })
+            .await?;
+        self.password = password;
+        Ok(())
+    }
+
+    pub async fn get_protection(&self) -> Result<Protection, anyhow::Error> {
+        self.plc
+            .read(Priority::Hmi, |client| client.get_protection())
+            .await
+    }
+
+    pub async fn get_plc_status(&self) -> Result<i32, anyhow::Error> {
+        self.plc
+            .read(Priority::Hmi, |client| client.get_plc_status())
+            .await
+    }
+
+    pub async fn stop_plc(&self) -> Result<(), anyhow::Error> {
+        self.plc.write(Priority::Safety, |client| client.plc_stop()).await
+    }
+
+    pub async fn hot_start_plc(&self) -> Result<(), anyhow::Error> {
+        self.plc.write(Priority::Hmi, |client| client.plc_hot_start()).await
+    }
+
+    pub async fn cold_start_plc(&self) -> Result<(), anyhow::Error> {
+        self.plc.write(Priority::Hmi, |client| client.plc_cold_start()).await
     }
 
-    pub fn get_plc_status(&self) -> Result<i32, anyhow::Error> {
-        let mut status = 0;
-        self.s7_client.get_plc_status(&mut status)?;
-        Ok(status)
+    /// Reads the CPU clock. The PLC keeps local wall-clock time without a zone.
+    pub async fn get_plc_clock(&self) -> Result<NaiveDateTime, anyhow::Error> {
+        self.plc
+            .read(Priority::Hmi, |client| client.get_plc_date_time())
+            .await
     }
 
+    pub async fn set_plc_clock(&self, value: NaiveDateTime) -> Result<(), anyhow::Error> {
+        self.plc
+            .write(Priority::Hmi, move |client| client.set_plc_date_time(value))
+            .await
+    }
+
+    pub async fn list_blocks(&self) -> Result<Vec<(BlockKind, i32)>, anyhow::Error> {
+        self.plc
+            .read(Priority::Bulk, |client| client.list_blocks())
+            .await
+    }
+
+    pub async fn list_blocks_of_type(&self, kind: BlockKind) -> Result<Vec<u16>, anyhow::Error> {
+        self.plc
+            .read(Priority::Bulk, move |client| client.list_blocks_of_type(kind))
+            .await
+    }
+
+    pub async fn get_block_info(
+        &self,
+        kind: BlockKind,
+        number: i32,
+    ) -> Result<BlockDetails, anyhow::Error> {
+        self.plc
+            .read(Priority::Bulk, move |client| client.get_block_info(kind, number))
+            .await
+    }
+
+    /// Uploads the complete block (header, body and footer) so it can be downloaded again later.
+    pub async fn upload_block(&self, kind: BlockKind, number: i32) -> Result<Vec<u8>, anyhow::Error> {
+        self.plc
+            .read(Priority::Bulk, move |client| client.full_upload(kind, number))
+            .await
+    }
+
+    pub async fn download_block(&self, data: &[u8]) -> Result<(), anyhow::Error> {
+        let data = data.to_vec();
+        self.plc
+            .write(Priority::Bulk, move |client| client.download(&data))
+            .await
+    }
+
+    /// Reads the complete contents of a DB, sized from its block info.
+    pub async fn read_db(&self, number: i32) -> Result<Vec<u8>, anyhow::Error> {
+        let info = self.get_block_info(BlockKind::Db, number).await?;
+        self.plc
+            .read(Priority::Bulk, move |client| {
+                client.db_get(number, info.mc7_size.max(1) as usize)
+            })
+            .await
+    }
+
+    pub async fn fill_db(&self, number: i32, value: u8) -> Result<(), anyhow::Error> {
+        self.plc
+            .write(Priority::Bulk, move |client| client.db_fill(number, value))
+            .await
+    }
+
+    pub async fn write_db(&self, number: i32, start: i32, data: &[u8]) -> Result<(), anyhow::Error> {
+        let data = data.to_vec();
+        self.plc
+            .write(Priority::Bulk, move |client| {
+                client.write_area(PlcArea::Db(number), start as usize, &data)
+            })
+            .await
+    }
+
+    /// Identifies the PLC in on-disk archives.
+    pub fn plc_id(&self) -> String {
+        format!("{}_r{}_s{}", self.address, self.rack, self.slot)
+    }
+
+    /// Switches to new connection settings. The session password is kept when they leave it
+    /// out, `set_session_password` clears it.
     pub fn update_config(&mut self, new_config: PLCConfig) {
         self.address = new_config.address;
         self.rack = new_config.rack;
         self.slot = new_config.slot;
+        if let Some(password) = new_config.password {
+            self.password = Some(password);
+        }
+        self.connection = new_config.connection;
     }
 }
 
-pub async fn create_routes() -> Router {
-    let s7_client = Arc::new(S7Client::create());
+/// The router and the state its handlers and background tasks share.
+pub struct App {
+    pub router: Router,
+    pub state: Arc<Mutex<SharedState>>,
+}
 
-    let app_state = Arc::new(Mutex::new(AppState {
-        address: "192.168.0.1".to_string(),
-        rack: 0,
-        slot: 2,
-        s7_client,
-    }));
+#[cfg(test)]
+pub async fn create_app(
+    config: AppConfig,
+    config_source: ConfigSource,
+    pool_config: PoolConfig,
+) -> Router {
+    create_routes(config, config_source, pool_config).await.router
+}
+
+/// Connects to the PLC, starts the background tasks and builds the router.
+pub async fn create_routes(
+    config: AppConfig,
+    config_source: ConfigSource,
+    pool_config: PoolConfig,
+) -> App {
+    let plc = PlcPool::new(pool_config);
+    let tasks = BackgroundTasks::default();
+
+    // Settings changed through the API before the last shutdown
+    let state_store = StateStore::open(&config.state.dir).expect("failed to open state store");
+    let restore = config.state.start == StartPolicy::Restore;
+    let mut plc_config = config.plc.clone();
+    if let Some(saved) = state_store.connection(&config.plc) {
+        println!("** Restoring PLC connection settings saved at runtime");
+        plc_config.address = saved.address;
+        plc_config.rack = saved.rack;
+        plc_config.slot = saved.slot;
+        plc_config.connection = saved.connection;
+    }
+
+    let app_state = Arc::new(Mutex::new(AppState::new(plc, plc_config)));
+
+    let authenticator =
+        Authenticator::new(&config.auth).expect("failed to load authentication keys");
+    let audit = AuditLog::open(&config.audit.file).expect("failed to open audit log");
+    if authenticator.allows_anonymous() {
+        println!("** WARNING: Authentication is disabled and auth.allow_anonymous is set, anyone on the network can control the PLC");
+    } else if !authenticator.enabled() {
+        println!("** WARNING: Authentication is disabled, callers without credentials may only read. Set auth.allow_anonymous to let them do everything");
+    }
 
     {
         let app_state = app_state.clone();
         let app_state = app_state.lock().await;
-        let connection_result = app_state.connect_to_plc();
+        let connection_result = app_state.connect_to_plc().await;
 
         match connection_result {
             Ok(_) => println!(
@@ -83,49 +334,132 @@ pub async fn create_routes() -> Router {
         };
     }
 
-    const TARGET_TEMP: f32 = 30.0;
+    // Probe every pooled connection periodically and replace broken ones
+    tasks.spawn(monitor_plc_connections(
+        app_state.clone(),
+        Duration::from_millis(config.pool.health_check_interval_ms),
+    ));
 
+    let heater_config = &config.heater;
     let heater = Heater {
-        target_temp: TARGET_TEMP,
-        enabled: Arc::new(tokio::sync::Mutex::new(true)),
+        target_temp: heater_config.target_temp,
+        enabled: Arc::new(tokio::sync::Mutex::new(heater_config.enabled)),
+        task: Arc::new(tokio::sync::Mutex::new(None)),
     };
 
-    let pid = Arc::new(tokio::sync::Mutex::new(Pid::new(TARGET_TEMP, 100.0)));
+    let mut pid = Pid::new(heater_config.target_temp, heater_config.output_limit);
+    // Set proportional, integral, and derivative gains
+    let limit = heater_config.output_limit;
+    pid.p(heater_config.kp, limit); // Proportional gain with limit
+    pid.i(heater_config.ki, limit); // Integral gain with limit
+    pid.d(heater_config.kd, limit); // Derivative gain with limit
+
+    // Continue with the integrator where the loop stopped, unless the heater settings changed since
+    if restore {
+        if let Some(saved) = state_store.pid(heater_config) {
+            println!("** Restoring heater controller state");
+            pid = saved;
+        }
+    }
+    let pid = Arc::new(tokio::sync::Mutex::new(pid));
 
     let app_state_clone = app_state.clone();
 
+    // Compare the PLC clock with the host periodically, re-sync when it drifted too far
+    let clock_sync = ClockSync::new(
+        Duration::from_secs(config.clock.check_interval_s),
+        config.clock.max_drift_ms,
+    );
+    tasks.spawn(clock_sync.clone().run(app_state.clone()));
+
+    // Back up the full program every night, keeping the configured number of versions per PLC
+    let storage = &config.storage;
+    let block_backup = BlockBackup {
+        archive: BlockArchive::new(&storage.backups_dir, storage.backups_keep),
+        run_at: storage.backup_time(),
+    };
+    tasks.spawn(block_backup.clone().run(app_state.clone()));
+
+    // Snapshot block checksums every 5 minutes to detect program changes outside change control
+    let block_watch = BlockWatch::new(&storage.block_watch_dir, Duration::from_secs(300));
+    tasks.spawn(block_watch.clone().run(app_state.clone()));
+
+    // Poll the tag table at the base scan rate (or at each tag's own scan rate) into the tag cache
+    let tags = config.tags.table();
+    let tag_poller = TagPoller::new(tags.clone(), Duration::from_millis(config.tags.scan_rate_ms));
+    tasks.spawn(tag_poller.clone().run(app_state.clone()));
+
     let shared_state = Arc::new(Mutex::new(SharedState {
-        app_state,
+        app_state: app_state.clone(),
         heater,
         pid,
+        clock_sync,
+        block_backup,
+        block_watch,
+        confirmations: ConfirmationTokens::new(Duration::from_secs(300)),
+        db_snapshots: DbSnapshots::new(&storage.snapshots_dir),
+        tags,
+        tag_poller,
+        config: config.clone(),
+        config_source,
+        state_store: state_store.clone(),
+        tasks: tasks.clone(),
+        authenticator: authenticator.clone(),
+        audit,
     }));
 
-    {
-        let shared_state_guard = shared_state.lock().await;
-        let mut pid_clone = shared_state_guard.pid.lock().await;
-
-        // Set proportional, integral, and derivative gains
-        pid_clone.p(1.0, 100.0); // Proportional gain with limit
-        pid_clone.i(0.1, 100.0); // Integral gain with limit
-        pid_clone.d(0.01, 100.0); // Derivative gain with limit
+    // Apply changes to the config file without a restart
+    tasks.spawn(watch_config_file(shared_state.clone(), CONFIG_WATCH_INTERVAL));
+
+    // Safe start unless configured otherwise: the heater only runs again if it is enabled
+    match state_store.state().heater_enabled {
+        Some(enabled) if restore => {
+            println!("** Restoring heater enabled: {}", enabled);
+            let shared_state_guard = shared_state.lock().await;
+            if enabled {
+                shared_state_guard
+                    .heater
+                    .enable(&shared_state_guard.app_state, &shared_state_guard.pid)
+                    .await;
+            } else {
+                shared_state_guard.heater.disable().await;
+            }
+        }
+        _ => (),
     }
 
-    tokio::spawn(perform_periodic_task(
-        app_state_clone,
-        shared_state.clone(),
-        TARGET_TEMP,
-    ));
+    tasks.spawn(perform_periodic_task(app_state_clone, shared_state.clone()));
 
-    tokio::signal::ctrl_c()
-        .await
-        .expect("failed to listen for event");
+    // Route groups by the role they need. Groups that write to the PLC also need the
+    // connected PLC in the caller's write scope.
+    let viewer = Access::new(Role::Viewer);
+    let operator = Access::new(Role::Operator).writing_to(app_state.clone());
+    let engineer = Access::new(Role::Engineer).writing_to(app_state.clone());
+    let admin = Access::new(Role::Admin);
 
     let health_check_router = Router::new()
-        .route("/server", get(controllers::server_health_check))
-        .route("/plc", get(controllers::plc_connection_check));
+        .route("/plc", get(controllers::plc_connection_check))
+        .route_layer(middleware::from_fn_with_state(viewer.clone(), require_role));
 
-    let plc_router = Router::new()
+    let plc_read_router = Router::new()
         .route("/", get(controllers::get_plc_operating_mode))
+        .route("/scheduler", get(controllers::get_scheduler_metrics))
+        .route("/clock", get(controllers::get_plc_clock))
+        .route("/protection", get(controllers::get_protection))
+        .route("/clock/drift", get(controllers::get_clock_drift_history))
+        .route("/blocks", get(controllers::list_blocks))
+        .route("/blocks/changes", get(controllers::list_block_changes))
+        .route("/blocks/changes/stream", get(controllers::stream_block_changes))
+        .route("/blocks/:block_type", get(controllers::list_blocks_of_type))
+        .route("/blocks/:block_type/:number", get(controllers::get_block_info))
+        .route("/db/:number/snapshot", get(controllers::get_db_snapshot))
+        .route("/db/:number/snapshots", get(controllers::list_db_snapshots))
+        .route("/db/:number/compare", post(controllers::compare_db))
+        .route("/backups", get(controllers::list_backups))
+        .route_layer(middleware::from_fn_with_state(viewer.clone(), require_role))
+        .layer(middleware::from_fn(require_plc_connection));
+
+    let plc_control_router = Router::new()
         .route(
             "/configure_connection",
             post(controllers::change_plc_connection_settings),
@@ -133,190 +467,189 @@ pub async fn create_routes() -> Router {
         .route("/stop", get(controllers::stop_plc))
         .route("/hot_start", get(controllers::hot_start))
         .route("/cold_start", get(controllers::cold_start))
+        .route("/clock", put(controllers::set_plc_clock))
+        .route("/session_password", put(controllers::set_session_password))
+        .route("/blocks", post(controllers::download_block))
+        .route("/blocks/changes/check", post(controllers::check_block_changes))
+        .route("/blocks/baseline", post(controllers::reset_block_baseline))
+        .route(
+            "/blocks/:block_type/:number/upload",
+            post(controllers::upload_block),
+        )
+        .route("/db/:number/restore", post(controllers::restore_db))
+        .route("/db/:number/snapshot", post(controllers::save_db_snapshot))
+        .route("/db/:number/fill", post(controllers::fill_db))
+        .route("/backups", post(controllers::backup_program))
+        .route_layer(middleware::from_fn_with_state(engineer, require_role))
         .layer(middleware::from_fn(require_plc_connection));
 
+    let tag_router = Router::new()
+        .route("/", get(controllers::get_tag_values))
+        .route("/stream", get(controllers::stream_tag_changes))
+        .route_layer(middleware::from_fn_with_state(viewer, require_role));
+
+    let tag_write_router = Router::new()
+        .route("/:name", put(controllers::write_tag))
+        .route_layer(middleware::from_fn_with_state(operator.clone(), require_role));
+
+    let admin_router = Router::new()
+        .route("/reload", post(controllers::reload_config))
+        .route_layer(middleware::from_fn_with_state(admin.clone(), require_role));
+
+    let audit_router = Router::new()
+        .route("/", get(controllers::get_audit_entries))
+        .route("/verify", get(controllers::verify_audit_log))
+        .route_layer(middleware::from_fn_with_state(admin, require_role));
+
     let heater_router = Router::new()
         .route("/enable", get(controllers::enable_heater))
         .route("/disable", get(controllers::disable_heater))
+        .route_layer(middleware::from_fn_with_state(operator, require_role))
         .with_state(shared_state.clone());
 
-    Router::new()
+    let router = Router::new()
         .nest("/health_check", health_check_router)
-        .nest("/plc", plc_router)
+        .nest("/plc", plc_read_router)
+        .nest("/plc", plc_control_router)
+        .nest("/tags", tag_router)
+        .nest("/tags", tag_write_router)
         .nest("/heater", heater_router)
-        .with_state(shared_state)
+        .nest("/admin", admin_router)
+        .nest("/audit", audit_router)
+        .route_layer(middleware::from_fn_with_state(
+            authenticator,
+            require_authentication,
+        ))
+        // Open to load balancers and monitoring without credentials
+        .route("/health_check/server", get(controllers::server_health_check))
+        .with_state(shared_state.clone());
+
+    App {
+        router,
+        state: shared_state,
+    }
+}
+
+async fn monitor_plc_connections(app_state: Arc<Mutex<AppState>>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+
+        let (plc, connect) = {
+            let app_state = app_state.lock().await;
+            (app_state.plc.clone(), app_state.connect_job())
+        };
+        plc.check_health(connect).await;
+    }
 }
 
 async fn perform_periodic_task(
     app_state_clone: Arc<Mutex<AppState>>,
     shared_state: Arc<Mutex<SharedState>>,
-    target_temp: f32,
 ) {
-    let mut update_interval = tokio::time::interval(Duration::from_millis(100));
-    let mut heater_interval = tokio::time::interval(Duration::from_secs(10));
+    let mut heater_config = shared_state.lock().await.config.heater.clone();
+    let mut update_interval =
+        tokio::time::interval(Duration::from_millis(heater_config.update_interval_ms));
+    let mut heater_interval = tokio::time::interval(Duration::from_millis(heater_config.cycle_ms));
+    let mut pid_saved_at: Option<Instant> = None;
+    let tag_poller = shared_state.lock().await.tag_poller.clone();
 
     loop {
         tokio::select! {
             _ = update_interval.tick() => {
-                // Update values
-                let app_state = app_state_clone.lock().await;
-                let s7_client = &app_state.s7_client;
-
-                let shared_state_guard = shared_state.lock().await;
-                let mut pid_clone = shared_state_guard.pid.lock().await;
-
-                let mut read_result = read_plc_db(s7_client).await;
-                let temp = scale_to_temperature(read_result.temp_ai);
-                read_result.current_temp = temp;
-                read_result.target_temp = target_temp;
-                read_result.heater_enabled = true;
-
-                let output: pid::ControlOutput<f64> =
-                    pid_clone.next_control_output(temp as f64);
-                let power_percentage = output.output as f32;
-                read_result.power_percentage = if power_percentage < 0.0 {
-                    read_result.heater_enabled = false;
-                    0.0
-                } else if power_percentage > 100.0 {
-                    100.0
-                } else {
-                    power_percentage
+                // Pick up reloaded heater settings
+                let current = shared_state.lock().await.config.heater.clone();
+                if current.update_interval_ms != heater_config.update_interval_ms {
+                    update_interval = tokio::time::interval(Duration::from_millis(current.update_interval_ms));
+                }
+                if current.cycle_ms != heater_config.cycle_ms {
+                    heater_interval = tokio::time::interval(Duration::from_millis(current.cycle_ms));
+                }
+                heater_config = current;
+
+                // Update values, the poller only writes the ones that changed
+                let s7_client = &app_state_clone.lock().await.plc.clone();
+
+                // Refuse to control on anything but a good temperature reading
+                let Some(TagValue::Int(temp_ai)) = tag_poller.value("temp_ai") else {
+                    if let Some(reading) = tag_poller.reading("temp_ai") {
+                        println!("** temp_ai quality is {:?}, skipping control update", reading.quality);
+                    }
+                    continue;
                 };
+                let temp = scale_to_temperature(temp_ai as u16);
 
-                println!("Update task executed");
-
-                write_plc_db(s7_client, &read_result).await;
+                let output = {
+                    let shared_state_guard = shared_state.lock().await;
+                    let mut pid_clone = shared_state_guard.pid.lock().await;
+                    pid_clone.next_control_output(temp as f64)
+                };
+                let power_percentage = output.output as f32;
+                let heater_enabled = power_percentage >= 0.0;
+
+                let updates = [
+                    ("current_temp", TagValue::Real(temp)),
+                    ("target_temp", TagValue::Real(heater_config.target_temp)),
+                    ("heater_enabled", TagValue::Bool(heater_enabled)),
+                    ("power_percentage", TagValue::Real(power_percentage.clamp(0.0, 100.0))),
+                ];
+                if let Err(e) = tag_poller.write_all(s7_client, &updates).await {
+                    eprintln!("Error writing tags: {:?}", e);
+                }
+
+                if debug_logging() {
+                    println!("Update task executed");
+                }
             }
             _ = heater_interval.tick() => {
-                // Control heater
-                let app_state = app_state_clone.lock().await;
-                let s7_client = &app_state.s7_client;
-
-                let mut read_result = read_plc_db(s7_client).await;
+                // Control heater, without holding the state lock while sleeping
+                let s7_client = &app_state_clone.lock().await.plc.clone();
+
+                // Keep the heater off for this window unless the power setpoint is good
+                let power_percentage = match tag_poller.value("power_percentage") {
+                    Some(TagValue::Real(power_percentage)) => power_percentage,
+                    _ => {
+                        println!("** power_percentage is not good, keeping heater off");
+                        0.0
+                    }
+                };
 
-                let total_duration = Duration::from_secs(10);
-                let on_duration = (read_result.power_percentage / 100.0
+                let total_duration = Duration::from_millis(heater_config.cycle_ms);
+                let on_duration = (power_percentage / 100.0
                     * total_duration.as_millis() as f32)
                     as u64;
                 let off_duration = total_duration.as_millis() as u64 - on_duration;
 
                 // Set heater on for calculated duration
-                read_result.heater_on = true;
-                write_plc_db(s7_client, &read_result).await;
+                if let Err(e) = tag_poller.write(s7_client, "heater_on", TagValue::Bool(true)).await {
+                    eprintln!("Error writing heater_on: {:?}", e);
+                }
 
                 // Sleep for the on_duration
                 sleep(Duration::from_millis(on_duration)).await;
 
                 // Set heater off
-                read_result.heater_on = false;
-                write_plc_db(s7_client, &read_result).await;
+                if let Err(e) = tag_poller.write(s7_client, "heater_on", TagValue::Bool(false)).await {
+                    eprintln!("Error writing heater_on: {:?}", e);
+                }
 
                 // Sleep for the off_duration
                 sleep(Duration::from_millis(off_duration)).await;
+
+                // Keep the integrator for a restart
+                if pid_saved_at.is_some_and(|saved_at| saved_at.elapsed() < PID_SAVE_INTERVAL) {
+                    continue;
+                }
+                let shared_state_guard = shared_state.lock().await;
+                let pid = shared_state_guard.pid.lock().await;
+                match shared_state_guard.state_store.save_pid(&pid, &shared_state_guard.config.heater) {
+                    Ok(()) => pid_saved_at = Some(Instant::now()),
+                    Err(e) => println!("** Failed to save runtime state: {:?}", e),
+                }
             }
         }
     }
 }
 
-#[derive(Debug)]
-struct PlcData {
-    temp_ai: u16,
-    power_percentage: f32,
-    target_temp: f32,
-    current_temp: f32,
-    water_present: bool,
-    heater_enabled: bool,
-    heater_on: bool,
-}
-impl PlcData {
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-
-        bytes.extend_from_slice(&self.temp_ai.to_be_bytes());
-        bytes.extend_from_slice(&self.power_percentage.to_be_bytes());
-        bytes.extend_from_slice(&self.target_temp.to_be_bytes());
-        bytes.extend_from_slice(&self.current_temp.to_be_bytes());
-
-        // Combine water_present, heater_enabled, and heater_on into one byte
-        let mut status_byte = 0u8;
-        status_byte |= (self.water_present as u8) << 0;
-        status_byte |= (self.heater_enabled as u8) << 1;
-        status_byte |= (self.heater_on as u8) << 2;
-        bytes.push(status_byte);
-
-        bytes
-    }
-}
-
-async fn read_plc_db(s7_client: &Arc<S7Client>) -> PlcData {
-    let mut temp_ai = [0u8; 2];
-    let mut power_percentage = [0u8; 4];
-    let mut target_temp = [0u8; 4];
-    let mut current_temp = [0u8; 4];
-    let mut water_present = [0u8; 1];
-    let mut heater_enabled = [0u8; 1];
-    let mut heater_on = [0u8; 1];
-
-    // Reading Temperature AI
-    let temp_ai_value = if s7_client.db_read(1, 0, 2, &mut temp_ai).is_ok() {
-        u16::from_be_bytes(temp_ai)
-    } else {
-        0
-    };
-
-    // Reading Power Percentage
-    let power_percentage_value = if s7_client.db_read(1, 4, 4, &mut power_percentage).is_ok() {
-        f32::from_bits(u32::from_be_bytes(power_percentage))
-    } else {
-        0.0
-    };
-
-    // Reading Target Temperature
-    let target_temp_value = if s7_client.db_read(1, 8, 4, &mut target_temp).is_ok() {
-        f32::from_bits(u32::from_be_bytes(target_temp))
-    } else {
-        0.0
-    };
-
-    // Reading Current Temperature
-    let current_temp_value = if s7_client.db_read(1, 12, 4, &mut current_temp).is_ok() {
-        f32::from_bits(u32::from_be_bytes(current_temp))
-    } else {
-        0.0
-    };
-
-    // Reading Water Present
-    let water_present_value = if s7_client.db_read(1, 2, 1, &mut water_present).is_ok() {
-        (water_present[0] >> 0 & 1) == 1
-    } else {
-        false
-    };
-
-    // Reading Heater Enabled
-    let heater_enabled_value = if s7_client.db_read(1, 2, 1, &mut heater_enabled).is_ok() {
-        (heater_enabled[0] >> 1 & 1) == 1
-    } else {
-        false
-    };
-
-    // Reading Heater On
-    let heater_on_value = if s7_client.db_read(1, 2, 1, &mut heater_on).is_ok() {
-        (heater_on[0] >> 2 & 1) == 1
-    } else {
-        false
-    };
-
-    PlcData {
-        temp_ai: temp_ai_value,
-        power_percentage: power_percentage_value,
-        target_temp: target_temp_value,
-        current_temp: current_temp_value,
-        water_present: water_present_value,
-        heater_enabled: heater_enabled_value,
-        heater_on: heater_on_value,
-    }
-}
-
 fn scale_to_temperature(raw_value: u16) -> f32 {
     let min_raw_value = 0;
     let max_raw_value = 27648;
@@ -327,32 +660,3 @@ fn scale_to_temperature(raw_value: u16) -> f32 {
         + (raw_value as f32 - min_raw_value as f32) * (max_temp - min_temp)
             / (max_raw_value as f32 - min_raw_value as f32)
 }
-
-async fn write_plc_db(s7_client: &Arc<S7Client>, data: &PlcData) {
-    let mut data_bytes = data.to_bytes();
-
-    // Write temperature AI (first 2 bytes)
-    if let Err(err) = s7_client.db_write(1, 0, 2, &mut data_bytes[0..2]) {
-        eprintln!("Error writing temp_ai: {:?}", err);
-    }
-
-    // Write power percentage (next 4 bytes)
-    if let Err(err) = s7_client.db_write(1, 4, 4, &mut data_bytes[2..6]) {
-        eprintln!("Error writing power_percentage: {:?}", err);
-    }
-
-    // Write target temperature (next 4 bytes)
-    if let Err(err) = s7_client.db_write(1, 8, 4, &mut data_bytes[6..10]) {
-        eprintln!("Error writing target_temp: {:?}", err);
-    }
-
-    // Write current temperature (next 4 bytes)
-    if let Err(err) = s7_client.db_write(1, 12, 4, &mut data_bytes[10..14]) {
-        eprintln!("Error writing current_temp: {:?}", err);
-    }
-
-    // Write status byte (next 1 byte)
-    if let Err(err) = s7_client.db_write(1, 2, 1, &mut data_bytes[14..15]) {
-        eprintln!("Error writing status byte: {:?}", err);
-    }
-}
@@ -1,5 +1,7 @@
 mod main_router;
 
 pub use main_router::create_routes;
+#[cfg(test)]
+pub use main_router::create_app;
 pub use main_router::AppState;
 pub use main_router::PLCConfig;
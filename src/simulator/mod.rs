@@ -0,0 +1,6 @@
+mod plc_simulator;
+mod thermal_model;
+pub use plc_simulator::PlcSimulator;
+#[cfg(test)]
+pub use plc_simulator::SimulatedArea;
+pub use thermal_model::{ThermalConfig, ThermalModel};
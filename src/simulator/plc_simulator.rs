@@ -0,0 +1,130 @@
+use snap7_rs::{AreaCode, InternalParam, InternalParamValue, S7Server};
+use std::sync::Mutex;
+
+const DB1_SIZE: usize = 64;
+const IO_SIZE: usize = 8;
+const CPU_STATUS_RUN: i32 = 0x08;
+const AMBIENT_RAW: u16 = 11849; // 20 °C on the -40..100 °C input scale
+
+/// Memory areas hosted by the simulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedArea {
+    Db1,
+    Inputs,  // PE
+    Outputs, // PA
+}
+
+impl SimulatedArea {
+    fn code(&self) -> AreaCode {
+        match self {
+            SimulatedArea::Db1 => AreaCode::S7AreaDB,
+            SimulatedArea::Inputs => AreaCode::S7AreaPE,
+            SimulatedArea::Outputs => AreaCode::S7AreaPA,
+        }
+    }
+
+    /// DB number for DBs, ignored by snap7 for the other areas.
+    fn index(&self) -> u16 {
+        match self {
+            SimulatedArea::Db1 => 1,
+            SimulatedArea::Inputs | SimulatedArea::Outputs => 0,
+        }
+    }
+}
+
+/// A snap7 `S7Server` hosting DB1, PE and PA in memory, so the service can run
+/// without a physical CPU. The server runs on its own threads until dropped.
+pub struct PlcSimulator {
+    // Declared first so it is destroyed before the memory it points into
+    server: S7Server,
+    db1: Mutex<Box<[u8]>>,
+    inputs: Mutex<Box<[u8]>>,
+    outputs: Mutex<Box<[u8]>>,
+}
+
+impl PlcSimulator {
+    pub fn start(address: &str, port: u16) -> Result<Self, anyhow::Error> {
+        let simulator = PlcSimulator {
+            server: S7Server::create(),
+            db1: Mutex::new(vec![0u8; DB1_SIZE].into_boxed_slice()),
+            inputs: Mutex::new(vec![0u8; IO_SIZE].into_boxed_slice()),
+            outputs: Mutex::new(vec![0u8; IO_SIZE].into_boxed_slice()),
+        };
+
+        // The boxed buffers never move, so the server can keep pointers to them
+        for area in [
+            SimulatedArea::Db1,
+            SimulatedArea::Inputs,
+            SimulatedArea::Outputs,
+        ] {
+            let mut memory = simulator.memory(area).lock().unwrap();
+            simulator
+                .server
+                .register_area(area.code(), area.index(), &mut memory[..])?;
+        }
+
+        simulator
+            .server
+            .set_param(InternalParam::LocalPort, InternalParamValue::U16(port))?;
+        simulator.server.set_cpu_status(CPU_STATUS_RUN)?;
+
+        // Start with water in the tank at ambient temperature
+        simulator.set_bit(SimulatedArea::Inputs, 0, 0, true);
+        simulator.write(SimulatedArea::Db1, 0, &AMBIENT_RAW.to_be_bytes());
+        simulator.server.start_to(address)?;
+
+        println!("** PLC simulator listening on {}:{}", address, port);
+        Ok(simulator)
+    }
+
+    fn memory(&self, area: SimulatedArea) -> &Mutex<Box<[u8]>> {
+        match area {
+            SimulatedArea::Db1 => &self.db1,
+            SimulatedArea::Inputs => &self.inputs,
+            SimulatedArea::Outputs => &self.outputs,
+        }
+    }
+
+    /// Runs `f` on an area's memory while the server is kept from serving it.
+    fn with_area<R>(&self, area: SimulatedArea, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        let mut memory = self.memory(area).lock().unwrap();
+        // Only fails for unregistered areas, which cannot happen here
+        let _ = self.server.lock_area(area.code(), area.index());
+        let result = f(&mut memory);
+        let _ = self.server.unlock_area(area.code(), area.index());
+        result
+    }
+
+    pub fn write(&self, area: SimulatedArea, offset: usize, data: &[u8]) {
+        self.with_area(area, |memory| {
+            memory[offset..offset + data.len()].copy_from_slice(data)
+        })
+    }
+
+    pub fn bit(&self, area: SimulatedArea, offset: usize, bit: u8) -> bool {
+        self.with_area(area, |memory| (memory[offset] >> bit) & 1 == 1)
+    }
+
+    pub fn set_bit(&self, area: SimulatedArea, offset: usize, bit: u8, on: bool) {
+        self.with_area(area, |memory| {
+            if on {
+                memory[offset] |= 1 << bit;
+            } else {
+                memory[offset] &= !(1 << bit);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+impl PlcSimulator {
+    /// CPU status as reported to clients (0x08 run, 0x04 stop). Clients change it
+    /// with the stop and start commands.
+    pub fn cpu_status(&self) -> i32 {
+        let (mut server_status, mut cpu_status, mut client_count) = (0, 0, 0);
+        self.server
+            .get_status(&mut server_status, &mut cpu_status, &mut client_count)
+            .expect("failed to read simulator status");
+        cpu_status
+    }
+}
@@ -0,0 +1,172 @@
+use super::plc_simulator::{PlcSimulator, SimulatedArea};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+
+// Analog input scaling used by the heater: 0..27648 maps to -40..100 °C
+const MIN_TEMP: f64 = -40.0;
+const MAX_TEMP: f64 = 100.0;
+const NOMINAL_RAW: f64 = 27648.0;
+const MAX_RAW: f64 = 32511.0; // Top of the S7 overrange region
+
+/// Heating element as a separate node that heats the water through a finite
+/// heat transfer coefficient, instead of heating the water directly.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ElementConfig {
+    pub thermal_mass_j_per_k: f64,
+    pub transfer_w_per_k: f64,
+}
+
+/// Alternates the water-present input to exercise the heater interlock.
+#[derive(Deserialize, Debug, Clone)]
+pub struct WaterToggle {
+    pub present_secs: u64,
+    pub absent_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ThermalConfig {
+    pub heater_power_w: f64,
+    pub thermal_mass_j_per_k: f64, // Of the water, 4186 J/K per litre
+    pub ambient_temp_c: f64,
+    pub ambient_loss_w_per_k: f64,
+    pub dead_time_ms: u64,
+    pub noise_c: f64, // Peak measurement noise
+    pub step_ms: u64,
+    pub element: Option<ElementConfig>,
+    pub water_toggle: Option<WaterToggle>,
+}
+
+impl Default for ThermalConfig {
+    fn default() -> Self {
+        ThermalConfig {
+            heater_power_w: 2000.0,
+            thermal_mass_j_per_k: 10.0 * 4186.0,
+            ambient_temp_c: 20.0,
+            ambient_loss_w_per_k: 15.0,
+            dead_time_ms: 5000,
+            noise_c: 0.1,
+            step_ms: 100,
+            element: None,
+            water_toggle: None,
+        }
+    }
+}
+
+impl ThermalConfig {
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// First-order-plus-dead-time model of the water tank, optionally with a second node
+/// for the heating element. Reads the heater output Q0.1 and drives DB1.DBW0 and I0.0.
+pub struct ThermalModel {
+    config: ThermalConfig,
+    water_temp: f64,
+    element_temp: f64,
+    water_present: bool,
+    heater_history: VecDeque<bool>, // Heater output over the dead time, oldest first
+    noise_state: u64,
+}
+
+impl ThermalModel {
+    pub fn new(config: ThermalConfig) -> Self {
+        let delay_steps = (config.dead_time_ms / config.step_ms.max(1)) as usize;
+        ThermalModel {
+            water_temp: config.ambient_temp_c,
+            element_temp: config.ambient_temp_c,
+            water_present: true,
+            heater_history: VecDeque::from(vec![false; delay_steps]),
+            noise_state: 0x2545_f491_4f6c_dd1d,
+            config,
+        }
+    }
+
+    /// Advances the model by one step with the current heater output.
+    pub fn step(&mut self, heater_on: bool) {
+        let dt = self.config.step_ms as f64 / 1000.0;
+
+        self.heater_history.push_back(heater_on);
+        let delayed_on = self.heater_history.pop_front().unwrap_or(heater_on);
+        let heater_power = if delayed_on {
+            self.config.heater_power_w
+        } else {
+            0.0
+        };
+        let ambient_loss =
+            self.config.ambient_loss_w_per_k * (self.water_temp - self.config.ambient_temp_c);
+
+        if heater_on && !self.water_present {
+            println!("** Simulator: heater is on without water present");
+        }
+
+        match &self.config.element {
+            Some(element) => {
+                // Without water the element has nothing to transfer heat into
+                let transfer = if self.water_present {
+                    element.transfer_w_per_k * (self.element_temp - self.water_temp)
+                } else {
+                    0.0
+                };
+                self.element_temp += (heater_power - transfer) / element.thermal_mass_j_per_k * dt;
+                self.water_temp +=
+                    (transfer - ambient_loss) / self.config.thermal_mass_j_per_k * dt;
+            }
+            None => {
+                let heat_in = if self.water_present {
+                    heater_power
+                } else {
+                    0.0
+                };
+                self.water_temp += (heat_in - ambient_loss) / self.config.thermal_mass_j_per_k * dt;
+            }
+        }
+    }
+
+    /// Water-present state for the given time since start.
+    fn water_present_at(&self, elapsed: Duration) -> bool {
+        let Some(toggle) = &self.config.water_toggle else {
+            return true;
+        };
+        let period = toggle.present_secs + toggle.absent_secs;
+        period == 0 || elapsed.as_secs() % period < toggle.present_secs
+    }
+
+    /// Uniform noise in -noise_c..noise_c from a xorshift generator, enough for a simulation.
+    fn noise(&mut self) -> f64 {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 7;
+        self.noise_state ^= self.noise_state << 17;
+        let unit = (self.noise_state >> 11) as f64 / (1u64 << 53) as f64;
+        (unit * 2.0 - 1.0) * self.config.noise_c
+    }
+
+    /// Raw analog input for the measured temperature, clamped to the S7 input range.
+    pub fn raw_value(&mut self) -> u16 {
+        let measured = self.water_temp + self.noise();
+        let raw = (measured - MIN_TEMP) / (MAX_TEMP - MIN_TEMP) * NOMINAL_RAW;
+        raw.clamp(0.0, MAX_RAW).round() as u16
+    }
+
+    pub async fn run(mut self, simulator: Arc<PlcSimulator>) {
+        let mut interval = tokio::time::interval(Duration::from_millis(self.config.step_ms.max(1)));
+        let started = Instant::now();
+
+        loop {
+            interval.tick().await;
+
+            self.water_present = self.water_present_at(started.elapsed());
+            simulator.set_bit(SimulatedArea::Inputs, 0, 0, self.water_present);
+
+            let heater_on = simulator.bit(SimulatedArea::Outputs, 0, 1);
+            self.step(heater_on);
+
+            let raw = self.raw_value();
+            simulator.write(SimulatedArea::Db1, 0, &raw.to_be_bytes());
+        }
+    }
+}
@@ -0,0 +1,6 @@
+mod tag_poller;
+mod tag_quality;
+mod tag_table;
+pub use tag_poller::TagPoller;
+pub use tag_quality::Quality;
+pub use tag_table::{Tag, TagTable, TagType, TagValue};
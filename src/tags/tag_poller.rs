@@ -0,0 +1,410 @@
+use super::tag_quality::Quality;
+use super::tag_table::{Tag, TagTable, TagType, TagValue};
+use crate::{
+    plc::{AreaItem, PlcArea, PlcPool, Priority},
+    routes::AppState,
+};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{Duration, Instant};
+
+const CHANGE_CHANNEL_CAPACITY: usize = 1024;
+const STALE_AFTER_SCANS: u32 = 3;
+const MAX_VARS_PER_REQUEST: usize = 20; // S7 limit for multi-variable requests
+
+/// The last reported value of a tag. `source_timestamp` is when the value was sampled,
+/// `server_timestamp` when the poller last refreshed the entry (including failed reads).
+#[derive(Serialize, Debug, Clone)]
+pub struct TagReading {
+    pub value: Option<TagValue>, // None until the first successful read
+    #[serde(skip)]
+    latest: Option<TagValue>, // Last read or written, even when inside the deadband
+    pub quality: Quality,
+    pub source_timestamp: Option<DateTime<Local>>,
+    pub server_timestamp: DateTime<Local>,
+}
+
+/// Published to subscribers whenever a tag moves past its deadband, changes quality
+/// or is written.
+#[derive(Serialize, Debug, Clone)]
+pub struct TagChange {
+    pub name: String,
+    pub previous: Option<TagValue>,
+    #[serde(flatten)]
+    pub reading: TagReading,
+}
+
+/// Polls the tag table at each tag's scan rate, keeps the last reported value of every
+/// tag and notifies subscribers of changes. The table can be swapped while polling.
+#[derive(Clone)]
+pub struct TagPoller {
+    table: Arc<RwLock<TagTable>>,
+    base_scan_rate: Arc<RwLock<Duration>>,
+    cache: Arc<RwLock<HashMap<String, TagReading>>>,
+    changes: broadcast::Sender<TagChange>,
+}
+
+impl TagPoller {
+    pub fn new(table: TagTable, base_scan_rate: Duration) -> Self {
+        let (changes, _) = broadcast::channel(CHANGE_CHANNEL_CAPACITY);
+        TagPoller {
+            table: Arc::new(RwLock::new(table)),
+            base_scan_rate: Arc::new(RwLock::new(base_scan_rate)),
+            cache: Arc::new(RwLock::new(HashMap::new())),
+            changes,
+        }
+    }
+
+    pub fn table(&self) -> TagTable {
+        self.table.read().unwrap().clone()
+    }
+
+    fn base_scan_rate(&self) -> Duration {
+        *self.base_scan_rate.read().unwrap()
+    }
+
+    /// Switches to a new tag table and base scan rate. Readings of tags that were removed
+    /// or redefined are dropped, so nothing is reported from the old address.
+    pub fn reconfigure(&self, table: TagTable, base_scan_rate: Duration) {
+        let mut current = self.table.write().unwrap();
+        self.cache.write().unwrap().retain(|name, _| {
+            let new_tag = table.find(name);
+            new_tag.is_some() && new_tag == current.find(name)
+        });
+        *current = table;
+        *self.base_scan_rate.write().unwrap() = base_scan_rate;
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TagChange> {
+        self.changes.subscribe()
+    }
+
+    /// The cached reading, marked stale when the poller has not refreshed it in time.
+    pub fn reading(&self, name: &str) -> Option<TagReading> {
+        let tag = self.table.read().unwrap().find(name)?.clone();
+        let reading = self.cache.read().unwrap().get(name)?.clone();
+        Some(self.check_stale(&tag, reading))
+    }
+
+    /// The cached value, only if its quality is good. Control logic must not act on anything else.
+    pub fn value(&self, name: &str) -> Option<TagValue> {
+        self.reading(name)
+            .filter(|reading| reading.quality.is_good())
+            .and_then(|reading| reading.value)
+    }
+
+    /// The value the PLC held at the last read or write, only if its quality is good. Unlike
+    /// `value` it is not held back by the deadband.
+    fn latest_value(&self, name: &str) -> Option<TagValue> {
+        self.reading(name)
+            .filter(|reading| reading.quality.is_good())
+            .and_then(|reading| reading.latest)
+    }
+
+    pub fn values(&self) -> HashMap<String, TagReading> {
+        self.table()
+            .tags
+            .iter()
+            .filter_map(|tag| Some((tag.name.clone(), self.reading(&tag.name)?)))
+            .collect()
+    }
+
+    fn scan_rate(&self, tag: &Tag) -> Duration {
+        tag.scan_rate_ms
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| self.base_scan_rate())
+    }
+
+    fn check_stale(&self, tag: &Tag, mut reading: TagReading) -> TagReading {
+        let age = (Local::now() - reading.server_timestamp)
+            .to_std()
+            .unwrap_or_default();
+        let fresh_quality = matches!(reading.quality, Quality::Good | Quality::UncertainOverrange);
+        if fresh_quality && age > self.scan_rate(tag) * STALE_AFTER_SCANS {
+            reading.quality = Quality::Stale;
+        }
+        reading
+    }
+
+    /// Records the outcome of a read or write and notifies subscribers when the value
+    /// moved past the tag's deadband or the quality changed. `force` always stores the value.
+    fn update(&self, tag: &Tag, outcome: Result<TagValue, Quality>, force: bool) {
+        let now = Local::now();
+        let mut cache = self.cache.write().unwrap();
+        let previous = cache.get(&tag.name).cloned();
+
+        let mut reading = previous.clone().unwrap_or(TagReading {
+            value: None,
+            latest: None,
+            quality: Quality::BadCommFailure,
+            source_timestamp: None,
+            server_timestamp: now,
+        });
+        reading.server_timestamp = now;
+        match outcome {
+            Ok(value) => {
+                reading.latest = Some(value);
+                reading.quality = tag.assess(Some(value));
+                let value_changed = match reading.value {
+                    Some(previous) => force || tag.changed(previous, value),
+                    None => true,
+                };
+                if value_changed {
+                    reading.value = Some(value);
+                    reading.source_timestamp = Some(now);
+                }
+            }
+            // Keep the last value so clients can still see it, flagged with the bad quality
+            Err(quality) => reading.quality = quality,
+        }
+
+        let changed = match &previous {
+            Some(previous) => {
+                previous.value != reading.value || previous.quality != reading.quality
+            }
+            None => true,
+        };
+        cache.insert(tag.name.clone(), reading.clone());
+        drop(cache);
+
+        if changed {
+            // Nobody listening is fine
+            let _ = self.changes.send(TagChange {
+                name: tag.name.clone(),
+                previous: previous.and_then(|previous| previous.value),
+                reading,
+            });
+        }
+    }
+
+    /// Reads the given tags with one multi-variable request, each item covering the due
+    /// tags of one DB.
+    pub async fn poll(&self, plc: &PlcPool, due: &[&Tag]) {
+        let mut by_db: BTreeMap<i32, Vec<&Tag>> = BTreeMap::new();
+        for tag in due {
+            by_db.entry(tag.db).or_default().push(tag);
+        }
+
+        let spans: Vec<(i32, usize, usize)> = by_db
+            .iter()
+            .map(|(db, tags)| {
+                let start = tags.iter().map(|tag| tag.offset).min().unwrap_or(0);
+                let end = tags
+                    .iter()
+                    .map(|tag| tag.offset + tag.data_type.size())
+                    .max()
+                    .unwrap_or(0);
+                (*db, start, end)
+            })
+            .collect();
+        let items: Vec<AreaItem> = spans
+            .iter()
+            .map(|(db, start, end)| AreaItem {
+                area: PlcArea::Db(*db),
+                start: *start,
+                size: end - start,
+            })
+            .collect();
+
+        let result = plc
+            .read(Priority::Control, move |client| {
+                let mut results = Vec::with_capacity(items.len());
+                for chunk in items.chunks(MAX_VARS_PER_REQUEST) {
+                    results.extend(client.read_multi_vars(chunk)?);
+                }
+                Ok(results)
+            })
+            .await;
+
+        let results = match result {
+            Ok(results) => results,
+            Err(e) => {
+                println!("** Failed to poll tags: {:?}", e);
+                for tag in due {
+                    self.update(tag, Err(Quality::BadCommFailure), false);
+                }
+                return;
+            }
+        };
+
+        for ((db, start, end), result) in spans.into_iter().zip(results) {
+            let data = match result {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("** Failed to poll DB{}: {}", db, e);
+                    for tag in &by_db[&db] {
+                        self.update(tag, Err(Quality::BadCommFailure), false);
+                    }
+                    continue;
+                }
+            };
+
+            // Decoding expects the DB from byte 0, leave the unread prefix zeroed
+            let mut buffer = vec![0u8; end];
+            let size = data.len().min(end - start);
+            buffer[start..start + size].copy_from_slice(&data[..size]);
+            for tag in &by_db[&db] {
+                let outcome = tag.decode(&buffer).ok_or(Quality::BadConfigError);
+                self.update(tag, outcome, false);
+            }
+        }
+    }
+
+    /// Writes a tag unless the PLC already holds the value. Returns whether a write was issued.
+    pub async fn write(
+        &self,
+        plc: &PlcPool,
+        name: &str,
+        value: TagValue,
+    ) -> Result<bool, anyhow::Error> {
+        let tag = self
+            .table
+            .read()
+            .unwrap()
+            .find(name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown tag {}", name))?
+            .clone();
+
+        if self.latest_value(name) == Some(value) {
+            return Ok(false);
+        }
+
+        match (tag.data_type, value) {
+            (TagType::Bool, TagValue::Bool(on)) => {
+                let (db, offset, bit) = (tag.db, tag.offset, tag.bit);
+                plc.write(Priority::Control, move |client| {
+                    client.write_bit(PlcArea::Db(db), offset, bit, on)
+                })
+                .await?;
+            }
+            _ => {
+                let bytes = tag.encode(value).ok_or_else(|| {
+                    anyhow::anyhow!("Value {:?} does not fit tag {}", value, name)
+                })?;
+                let (db, offset) = (tag.db, tag.offset);
+                plc.write(Priority::Control, move |client| {
+                    client.write_area(PlcArea::Db(db), offset, &bytes)
+                })
+                .await?;
+            }
+        }
+
+        self.update(&tag, Ok(value), true);
+        Ok(true)
+    }
+
+    /// Writes several tags in one multi-variable request, skipping those the PLC already
+    /// holds the value of. Bool tags are written bit by bit in the same job. Tags the PLC
+    /// rejected are named in the error, the others are still written.
+    pub async fn write_all(
+        &self,
+        plc: &PlcPool,
+        updates: &[(&str, TagValue)],
+    ) -> Result<(), anyhow::Error> {
+        let table = self.table();
+        let mut errors = Vec::new();
+        let mut pending: Vec<(Tag, TagValue, Option<Vec<u8>>)> = Vec::new();
+        for (name, value) in updates {
+            let Some(tag) = table.find(name) else {
+                errors.push(format!("{}: unknown tag", name));
+                continue;
+            };
+            if self.latest_value(name) == Some(*value) {
+                continue;
+            }
+            match (tag.data_type, value) {
+                (TagType::Bool, TagValue::Bool(_)) => pending.push((tag.clone(), *value, None)),
+                _ => match tag.encode(*value) {
+                    Some(bytes) => pending.push((tag.clone(), *value, Some(bytes))),
+                    None => errors.push(format!("{}: value {:?} does not fit", name, value)),
+                },
+            }
+        }
+
+        if !pending.is_empty() {
+            let requests = pending.clone();
+            let results = plc
+                .write(Priority::Control, move |client| {
+                    let mut results = vec![Ok(()); requests.len()];
+                    let mut items = Vec::new();
+                    for (index, (tag, value, bytes)) in requests.iter().enumerate() {
+                        let area = PlcArea::Db(tag.db);
+                        match (bytes, value) {
+                            (Some(bytes), _) => {
+                                let item = AreaItem {
+                                    area,
+                                    start: tag.offset,
+                                    size: bytes.len(),
+                                };
+                                items.push((index, (item, bytes.clone())));
+                            }
+                            (None, TagValue::Bool(on)) => {
+                                results[index] = client
+                                    .write_bit(area, tag.offset, tag.bit, *on)
+                                    .map_err(|e| e.to_string());
+                            }
+                            (None, _) => {}
+                        }
+                    }
+
+                    for chunk in items.chunks(MAX_VARS_PER_REQUEST) {
+                        let batch: Vec<(AreaItem, Vec<u8>)> =
+                            chunk.iter().map(|(_, item)| item.clone()).collect();
+                        let written = client.write_multi_vars(&batch)?;
+                        for ((index, _), result) in chunk.iter().zip(written) {
+                            results[*index] = result;
+                        }
+                    }
+                    Ok(results)
+                })
+                .await?;
+
+            for ((tag, value, _), result) in pending.iter().zip(results) {
+                match result {
+                    Ok(()) => self.update(tag, Ok(*value), true),
+                    Err(e) => errors.push(format!("{}: {}", tag.name, e)),
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("Failed to write {}", errors.join(", "));
+        }
+        Ok(())
+    }
+
+    pub async fn run(self, app_state: Arc<Mutex<AppState>>) {
+        let mut base_scan_rate = self.base_scan_rate();
+        let mut interval = tokio::time::interval(base_scan_rate);
+        let mut next_due: HashMap<String, Instant> = HashMap::new();
+
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+
+            if self.base_scan_rate() != base_scan_rate {
+                base_scan_rate = self.base_scan_rate();
+                interval = tokio::time::interval(base_scan_rate);
+            }
+
+            let table = self.table();
+            let due: Vec<&Tag> = table
+                .tags
+                .iter()
+                .filter(|tag| next_due.get(&tag.name).is_none_or(|due_at| now >= *due_at))
+                .collect();
+            if due.is_empty() {
+                continue;
+            }
+            for tag in &due {
+                next_due.insert(tag.name.clone(), now + self.scan_rate(tag));
+            }
+
+            let plc = app_state.lock().await.plc.clone();
+            self.poll(&plc, &due).await;
+        }
+    }
+}
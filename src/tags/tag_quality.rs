@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// How far a tag value can be trusted, loosely following OPC UA status codes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    Good,
+    BadCommFailure,     // The last read from the PLC failed
+    BadConfigError,     // The tag does not fit the data that was read
+    UncertainOverrange, // Read fine, but outside the tag's valid range
+    Stale,              // Not refreshed within its scan rate
+}
+
+impl Quality {
+    pub fn is_good(&self) -> bool {
+        *self == Quality::Good
+    }
+}
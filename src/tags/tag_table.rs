@@ -0,0 +1,216 @@
+use super::tag_quality::Quality;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TagType {
+    Bool,
+    Byte,
+    Word,
+    Int,
+    DWord,
+    Real,
+}
+
+impl TagType {
+    pub fn size(&self) -> usize {
+        match self {
+            TagType::Bool | TagType::Byte => 1,
+            TagType::Word | TagType::Int => 2,
+            TagType::DWord | TagType::Real => 4,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum TagValue {
+    Bool(bool),
+    Int(i64),
+    Real(f32),
+}
+
+/// How far a numeric value has to move before it counts as a change.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Deadband {
+    Absolute(f64),
+    Percent(f64), // Of the last reported value
+}
+
+impl Deadband {
+    pub fn exceeded(&self, previous: f64, current: f64) -> bool {
+        let delta = (current - previous).abs();
+        match self {
+            Deadband::Absolute(limit) => delta > *limit,
+            Deadband::Percent(percent) => delta > previous.abs() * percent / 100.0,
+        }
+    }
+}
+
+/// Valid engineering range of a tag, e.g. 0..27648 for an S7 analog input.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TagRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub db: i32,
+    pub offset: usize,
+    #[serde(default)]
+    pub bit: u8, // Only used by bool tags
+    pub data_type: TagType,
+    #[serde(default)]
+    pub scan_rate_ms: Option<u64>, // Falls back to the poller's base rate
+    #[serde(default)]
+    pub deadband: Option<Deadband>,
+    #[serde(default)]
+    pub range: Option<TagRange>,
+}
+
+impl Tag {
+    pub fn new(name: &str, db: i32, offset: usize, data_type: TagType) -> Self {
+        Tag {
+            name: name.to_string(),
+            db,
+            offset,
+            bit: 0,
+            data_type,
+            scan_rate_ms: None,
+            deadband: None,
+            range: None,
+        }
+    }
+
+    pub fn new_bool(name: &str, db: i32, offset: usize, bit: u8) -> Self {
+        Tag {
+            bit,
+            ..Tag::new(name, db, offset, TagType::Bool)
+        }
+    }
+
+    pub fn with_scan_rate(self, scan_rate_ms: u64) -> Self {
+        Tag {
+            scan_rate_ms: Some(scan_rate_ms),
+            ..self
+        }
+    }
+
+    pub fn with_deadband(self, deadband: Deadband) -> Self {
+        Tag {
+            deadband: Some(deadband),
+            ..self
+        }
+    }
+
+    pub fn with_range(self, min: f64, max: f64) -> Self {
+        Tag {
+            range: Some(TagRange { min, max }),
+            ..self
+        }
+    }
+
+    /// Quality of a decoded value: missing means the tag did not fit the data.
+    pub fn assess(&self, value: Option<TagValue>) -> Quality {
+        let Some(value) = value else {
+            return Quality::BadConfigError;
+        };
+        match (self.range, value.as_f64()) {
+            (Some(range), Some(value)) if value < range.min || value > range.max => {
+                Quality::UncertainOverrange
+            }
+            _ => Quality::Good,
+        }
+    }
+
+    /// Whether the tag lies (at least partly) in `offset..offset + length`.
+    pub fn overlaps(&self, offset: usize, length: usize) -> bool {
+        self.offset < offset + length && offset < self.offset + self.data_type.size()
+    }
+
+    /// Decodes the tag from a buffer holding the DB starting at byte 0. S7 data is big endian.
+    pub fn decode(&self, data: &[u8]) -> Option<TagValue> {
+        let bytes = data.get(self.offset..self.offset + self.data_type.size())?;
+        Some(match self.data_type {
+            TagType::Bool => TagValue::Bool((bytes[0] >> self.bit) & 1 == 1),
+            TagType::Byte => TagValue::Int(bytes[0] as i64),
+            TagType::Word => TagValue::Int(u16::from_be_bytes([bytes[0], bytes[1]]) as i64),
+            TagType::Int => TagValue::Int(i16::from_be_bytes([bytes[0], bytes[1]]) as i64),
+            TagType::DWord => TagValue::Int(u32::from_be_bytes(bytes.try_into().ok()?) as i64),
+            TagType::Real => TagValue::Real(f32::from_be_bytes(bytes.try_into().ok()?)),
+        })
+    }
+
+    /// Encodes a value for a non-bool tag. Bool tags are written as single bits instead.
+    pub fn encode(&self, value: TagValue) -> Option<Vec<u8>> {
+        Some(match (self.data_type, value) {
+            (TagType::Byte, TagValue::Int(value)) => vec![u8::try_from(value).ok()?],
+            (TagType::Word, TagValue::Int(value)) => {
+                u16::try_from(value).ok()?.to_be_bytes().to_vec()
+            }
+            (TagType::Int, TagValue::Int(value)) => {
+                i16::try_from(value).ok()?.to_be_bytes().to_vec()
+            }
+            (TagType::DWord, TagValue::Int(value)) => {
+                u32::try_from(value).ok()?.to_be_bytes().to_vec()
+            }
+            (TagType::Real, TagValue::Real(value)) => value.to_be_bytes().to_vec(),
+            _ => return None,
+        })
+    }
+
+    /// Whether `current` differs enough from the last reported value to be a change.
+    pub fn changed(&self, previous: TagValue, current: TagValue) -> bool {
+        match (self.deadband, previous.as_f64(), current.as_f64()) {
+            (Some(deadband), Some(previous), Some(current)) => deadband.exceeded(previous, current),
+            _ => previous != current,
+        }
+    }
+}
+
+impl TagValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            TagValue::Bool(_) => None,
+            TagValue::Int(value) => Some(*value as f64),
+            TagValue::Real(value) => Some(*value as f64),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TagTable {
+    pub tags: Vec<Tag>,
+}
+
+impl TagTable {
+    /// Layout of the heater data block DB1.
+    pub fn heater_db1() -> Self {
+        TagTable {
+            tags: vec![
+                Tag::new("temp_ai", 1, 0, TagType::Word).with_range(0.0, 27648.0),
+                Tag::new_bool("water_present", 1, 2, 0),
+                Tag::new_bool("heater_enabled", 1, 2, 1),
+                Tag::new_bool("heater_on", 1, 2, 2),
+                Tag::new("power_percentage", 1, 4, TagType::Real)
+                    .with_deadband(Deadband::Absolute(0.5)),
+                Tag::new("target_temp", 1, 8, TagType::Real).with_scan_rate(1000),
+                Tag::new("current_temp", 1, 12, TagType::Real)
+                    .with_deadband(Deadband::Absolute(0.1)),
+            ],
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    pub fn tags_in(&self, db: i32, offset: usize, length: usize) -> impl Iterator<Item = &Tag> {
+        self.tags
+            .iter()
+            .filter(move |tag| tag.db == db && tag.overlaps(offset, length))
+    }
+}
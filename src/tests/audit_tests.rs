@@ -0,0 +1,208 @@
+use super::harness::{send_with_headers, state_dir, test_config};
+use crate::{
+    audit::{AuditAction, AuditEvent, AuditLog},
+    auth::Identity,
+    config::{AppConfig, AuthConfig, ConfigSource},
+    plc::{MockPlc, PlcArea, PoolConfig},
+    routes::create_app,
+};
+use axum::{extract::connect_info::MockConnectInfo, http::StatusCode, Router};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use tokio::time::{sleep, Duration, Instant};
+
+fn auth_config() -> AuthConfig {
+    let api_keys: Vec<Value> = [
+        ("operator", "operator"),
+        ("engineer", "engineer"),
+        ("admin", "admin"),
+    ]
+    .into_iter()
+    .map(|(name, role)| {
+        json!({
+            "name": name,
+            "sha256": hex::encode(Sha256::digest(name.as_bytes())),
+            "role": role,
+        })
+    })
+    .collect();
+    serde_json::from_value(json!({ "enabled": true, "api_keys": api_keys })).unwrap()
+}
+
+async fn start(config: AppConfig, mock: &MockPlc) -> Router {
+    let router = create_app(
+        config,
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await;
+    router.layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 7], 50000))))
+}
+
+async fn call(
+    router: &Router,
+    caller: &str,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    send_with_headers(router, method, uri, &[("x-api-key", caller)], body).await
+}
+
+#[tokio::test]
+async fn writes_are_recorded_with_caller_and_values() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Db(1), 0, &11849u16.to_be_bytes());
+    let config = AppConfig {
+        auth: auth_config(),
+        ..test_config()
+    };
+    let audit_file = config.audit.file.clone();
+    let router = start(config, &mock).await;
+
+    let (status, _) = call(&router, "operator", "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+    // Wait for the first poll, so the tag write has an old value. The control loop only
+    // reads temp_ai, nothing but the write below changes it
+    let deadline = Instant::now() + Duration::from_secs(3);
+    let mut old_value = Value::Null;
+    while old_value.is_null() && Instant::now() < deadline {
+        let (_, tags) = call(&router, "operator", "GET", "/tags", None).await;
+        old_value = tags["temp_ai"]["value"].clone();
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(old_value, json!(11849));
+    let reading = json!({ "value": 13824 });
+    let (status, _) = call(&router, "operator", "PUT", "/tags/temp_ai", Some(reading)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&router, "engineer", "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A failed write is recorded too
+    mock.set_reachable(false);
+    let (status, _) = call(&router, "engineer", "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    mock.set_reachable(true);
+
+    let (status, _) = call(&router, "engineer", "GET", "/audit", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, entries) = call(&router, "admin", "GET", "/audit", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let actions: Vec<&str> = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        ["enable_heater", "write_tag", "stop_plc", "stop_plc"]
+    );
+
+    let tag_write = &entries[1];
+    assert_eq!(tag_write["who"], "operator");
+    assert_eq!(tag_write["role"], "operator");
+    assert_eq!(tag_write["ip"], "10.0.0.7");
+    assert_eq!(tag_write["plc"], "192.168.0.1");
+    assert_eq!(tag_write["target"], "temp_ai");
+    assert_eq!(tag_write["old_value"], old_value);
+    assert_eq!(tag_write["new_value"], json!(13824));
+    assert_eq!(tag_write["result"], "ok");
+    assert_eq!(entries[2]["old_value"], "Running");
+    assert_eq!(entries[2]["new_value"], "Stopped");
+    assert_eq!(entries[3]["result"], "failed");
+    assert!(entries[3]["error"].is_string());
+
+    let (_, filtered) = call(
+        &router,
+        "admin",
+        "GET",
+        "/audit?who=engineer&result=ok",
+        None,
+    )
+    .await;
+    assert_eq!(filtered.as_array().unwrap().len(), 1);
+    assert_eq!(filtered[0]["action"], "stop_plc");
+    let (_, filtered) = call(&router, "admin", "GET", "/audit?action=write_tag", None).await;
+    assert_eq!(filtered.as_array().unwrap().len(), 1);
+    let (_, latest) = call(&router, "admin", "GET", "/audit?limit=1", None).await;
+    assert_eq!(latest[0]["seq"], 4);
+
+    let (_, verification) = call(&router, "admin", "GET", "/audit/verify", None).await;
+    assert_eq!(verification["valid"], true);
+    assert_eq!(verification["entries"], 4);
+    assert_eq!(
+        std::fs::read_to_string(audit_file).unwrap().lines().count(),
+        4
+    );
+}
+
+#[tokio::test]
+async fn session_passwords_are_not_recorded() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let config = test_config();
+    let audit_file = config.audit.file.clone();
+    let router = start(config, &mock).await;
+
+    let password = json!({ "password": "secret" });
+    let (status, _) = call(
+        &router,
+        "anonymous",
+        "PUT",
+        "/plc/session_password",
+        Some(password),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let contents = std::fs::read_to_string(audit_file).unwrap();
+    assert!(!contents.contains("secret"));
+    let entry: Value = serde_json::from_str(contents.trim()).unwrap();
+    assert_eq!(entry["action"], "set_session_password");
+    assert_eq!(entry["who"], "anonymous");
+    assert_eq!(entry["new_value"], true);
+}
+
+fn record_stop(log: &AuditLog, who: &str) {
+    let identity = Identity {
+        name: who.to_string(),
+        ..Identity::anonymous()
+    };
+    log.record(
+        &identity,
+        AuditEvent::new(AuditAction::StopPlc, "192.168.0.1"),
+        &Ok::<_, anyhow::Error>(()),
+    );
+}
+
+#[test]
+fn chain_survives_restart_and_reveals_tampering() {
+    let path = state_dir().join("audit.jsonl");
+    let log = AuditLog::open(&path).unwrap();
+    record_stop(&log, "alice");
+    record_stop(&log, "bob");
+
+    // Continues the chain after a restart
+    let log = AuditLog::open(&path).unwrap();
+    record_stop(&log, "carol");
+    let verification = log.verify().unwrap();
+    assert!(verification.valid);
+    assert_eq!(verification.entries, 3);
+
+    let original = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = original.lines().collect();
+
+    std::fs::write(&path, original.replace("\"bob\"", "\"mallory\"")).unwrap();
+    let verification = log.verify().unwrap();
+    assert!(!verification.valid);
+    assert_eq!(verification.first_invalid, Some(2));
+
+    std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+    let verification = log.verify().unwrap();
+    assert!(!verification.valid);
+    assert_eq!(verification.first_invalid, Some(2));
+}
@@ -0,0 +1,269 @@
+use super::harness::{send_with_headers, test_config};
+use crate::{
+    auth::Identity,
+    blocks::ConfirmationTokens,
+    config::{AppConfig, AuthConfig, ConfigSource, TagSettings},
+    plc::{MockPlc, PlcArea, PoolConfig},
+    routes::create_app,
+    tags::{Tag, TagTable, TagType},
+};
+use axum::{http::StatusCode, Router};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+// One API key per caller, the key being the caller's name
+const KEYS: &str = r#"
+enabled = true
+api_keys = [
+    { name = "viewer", sha256 = "{viewer}" },
+    { name = "operator", sha256 = "{operator}", role = "operator" },
+    { name = "engineer", sha256 = "{engineer}", role = "engineer" },
+    { name = "admin", sha256 = "{admin}", role = "admin" },
+    { name = "line2", sha256 = "{line2}", role = "engineer", plcs = ["10.0.2.1"] },
+    { name = "setpoints", sha256 = "{setpoints}", role = "operator", tags = ["target_*"] },
+]
+"#;
+
+fn auth_config() -> AuthConfig {
+    let mut keys = KEYS.to_string();
+    for name in [
+        "viewer",
+        "operator",
+        "engineer",
+        "admin",
+        "line2",
+        "setpoints",
+    ] {
+        let sha256 = hex::encode(Sha256::digest(name.as_bytes()));
+        keys = keys.replace(&format!("{{{}}}", name), &sha256);
+    }
+    toml::from_str(&keys).unwrap()
+}
+
+/// The heater DB1 layout plus a setpoint the heater loop does not write.
+fn tag_settings() -> TagSettings {
+    let mut table = TagTable::heater_db1().tags;
+    table.push(Tag::new("target_level", 1, 16, TagType::Real));
+    TagSettings {
+        table,
+        ..Default::default()
+    }
+}
+
+async fn start(mock: &MockPlc) -> Router {
+    create_app(
+        AppConfig {
+            auth: auth_config(),
+            tags: tag_settings(),
+            ..test_config()
+        },
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn call(
+    router: &Router,
+    caller: &str,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    send_with_headers(router, method, uri, &[("x-api-key", caller)], body).await
+}
+
+#[tokio::test]
+async fn roles_grant_route_groups() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&mock).await;
+
+    for caller in ["viewer", "operator", "engineer", "admin"] {
+        let (status, _) = call(&router, caller, "GET", "/health_check/plc", None).await;
+        assert_eq!(status, StatusCode::OK, "{}", caller);
+        let (status, _) = call(&router, caller, "GET", "/tags", None).await;
+        assert_eq!(status, StatusCode::OK, "{}", caller);
+    }
+
+    let (status, body) = call(&router, "viewer", "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body["message"].as_str().unwrap().contains("needs operator"));
+    let (status, _) = call(&router, "operator", "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = call(&router, "operator", "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&router, "engineer", "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Reading the clock is open to viewers, setting it is not
+    let (status, _) = call(&router, "viewer", "GET", "/plc/clock", None).await;
+    assert_ne!(status, StatusCode::FORBIDDEN);
+    let clock = json!({});
+    let (status, _) = call(&router, "operator", "PUT", "/plc/clock", Some(clock)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = call(&router, "engineer", "POST", "/admin/reload", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&router, "admin", "POST", "/admin/reload", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn writes_are_limited_to_the_plcs_in_scope() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&mock).await;
+
+    // Connected to 192.168.0.1, line2 may only write to 10.0.2.1
+    let (status, body) = call(&router, "line2", "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body["message"].as_str().unwrap().contains("192.168.0.1"));
+    let (status, _) = call(&router, "line2", "GET", "/plc", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let elsewhere = json!({ "address": "10.0.3.1", "rack": 0, "slot": 2 });
+    let (status, _) = call(
+        &router,
+        "line2",
+        "POST",
+        "/plc/configure_connection",
+        Some(elsewhere),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let line2 = json!({ "address": "10.0.2.1", "rack": 0, "slot": 2 });
+    let (status, _) = call(
+        &router,
+        "engineer",
+        "POST",
+        "/plc/configure_connection",
+        Some(line2),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&router, "line2", "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn tag_writes_are_limited_to_the_tags_in_scope() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&mock).await;
+
+    let setpoint = json!({ "value": 42.5 });
+    let (status, body) = call(
+        &router,
+        "setpoints",
+        "PUT",
+        "/tags/target_level",
+        Some(setpoint),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        mock.area(PlcArea::Db(1)).unwrap()[16..20],
+        42.5f32.to_be_bytes()
+    );
+
+    let present = json!({ "value": true });
+    let (status, _) = call(
+        &router,
+        "setpoints",
+        "PUT",
+        "/tags/water_present",
+        Some(present.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(!mock.bit(PlcArea::Db(1), 2, 0));
+
+    let (status, _) = call(
+        &router,
+        "viewer",
+        "PUT",
+        "/tags/water_present",
+        Some(present.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(
+        &router,
+        "operator",
+        "PUT",
+        "/tags/water_present",
+        Some(present),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(mock.bit(PlcArea::Db(1), 2, 0));
+
+    let (status, _) = call(
+        &router,
+        "operator",
+        "PUT",
+        "/tags/temp_ai",
+        Some(json!({ "value": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn tags_written_by_the_heater_loop_are_refused() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&mock).await;
+
+    let (status, body) = call(
+        &router,
+        "setpoints",
+        "PUT",
+        "/tags/target_temp",
+        Some(json!({ "value": 42.5 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body["message"].as_str().unwrap().contains("heater"));
+    assert_ne!(
+        mock.area(PlcArea::Db(1)).unwrap()[8..12],
+        42.5f32.to_be_bytes()
+    );
+
+    let (status, _) = call(
+        &router,
+        "admin",
+        "PUT",
+        "/tags/heater_on",
+        Some(json!({ "value": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn confirmation_tokens_only_work_for_the_caller_who_ran_the_dry_run() {
+    let tokens = ConfirmationTokens::new(Duration::from_secs(60));
+    let engineer = Identity {
+        name: "engineer".to_string(),
+        ..Identity::anonymous()
+    };
+    let line2 = Identity {
+        name: "line2".to_string(),
+        ..Identity::anonymous()
+    };
+    let operation = "db_restore:10.0.2.1_r0_s2:v1:DB1";
+
+    let token = tokens.issue(&engineer, operation).await.unwrap();
+    assert_eq!(token.len(), 64);
+    assert_ne!(token, tokens.issue(&engineer, operation).await.unwrap());
+
+    // Another caller can neither use nor burn the token
+    assert!(!tokens.consume(&token, &line2, operation).await);
+    assert!(!tokens.consume(&token, &engineer, "db_restore:10.0.2.1_r0_s2:v1:DB2").await);
+    assert!(tokens.consume(&token, &engineer, operation).await);
+    assert!(!tokens.consume(&token, &engineer, operation).await);
+}
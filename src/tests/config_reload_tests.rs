@@ -0,0 +1,256 @@
+use super::harness::{send, state_dir};
+use crate::{
+    config::ConfigSource,
+    plc::{FaultInjector, FaultScenario, MockPlc, PlcArea, PoolConfig},
+    routes::create_app,
+};
+use axum::{http::StatusCode, Router};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::time::{sleep, Duration, Instant};
+
+// A short heater window, the control loop does not update tags while the heater window runs.
+// Whether a window or an update runs next is picked at random, so allow for several windows.
+const CONFIG: &str = r#"
+[heater]
+target_temp = 30.0
+cycle_ms = 1000
+"#;
+
+fn config_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "snap7-rust-reload-{}-{}.toml",
+        name,
+        std::process::id()
+    ));
+    path.to_string_lossy().to_string()
+}
+
+async fn start(path: &str, mock: &MockPlc) -> Router {
+    start_with_faults(path, mock, None).await
+}
+
+async fn start_with_faults(
+    path: &str,
+    mock: &MockPlc,
+    faults: Option<Arc<FaultInjector>>,
+) -> Router {
+    // A good temp_ai reading (20 °C) so the control loop writes its tags
+    mock.set_bytes(PlcArea::Db(1), 0, &11849u16.to_be_bytes());
+    std::fs::write(path, CONFIG).unwrap();
+    let dir = state_dir();
+    let source = ConfigSource {
+        file: Some(path.to_string()),
+        overrides: vec![
+            format!("state.dir={}", dir.display()),
+            format!("audit.file={}", dir.join("audit.jsonl").display()),
+            format!("storage.backups_dir={}", dir.join("backups").display()),
+            format!("storage.snapshots_dir={}", dir.join("snapshots").display()),
+            format!("storage.block_watch_dir={}", dir.join("block_watch").display()),
+            "auth.allow_anonymous=true".to_string(),
+        ],
+    };
+    let config = source.load().unwrap();
+    create_app(
+        config,
+        source,
+        PoolConfig {
+            client: mock.factory(),
+            faults,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Waits for the control loop to write `target_temp` (DB1.DBD8) to the mock.
+async fn wait_for_target_temp(mock: &MockPlc, expected: f32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        let db1 = mock.area(PlcArea::Db(1)).unwrap();
+        if db1[8..12] == expected.to_be_bytes() {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn reload_applies_changes_and_reports_restart_only_sections() {
+    let path = config_path("apply");
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&path, &mock).await;
+    assert!(wait_for_target_temp(&mock, 30.0, Duration::from_secs(10)).await);
+
+    std::fs::write(
+        &path,
+        r#"
+[http]
+bind = "127.0.0.1:9000"
+
+[tags]
+scan_rate_ms = 200
+
+[heater]
+target_temp = 45.0
+kp = 2.0
+cycle_ms = 1000
+"#,
+    )
+    .unwrap();
+    let (status, body) = send(&router, "POST", "/admin/reload", None).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["applied"], json!(["tags", "heater"]));
+    assert_eq!(body["restart_required"], json!(["http"]));
+    assert!(wait_for_target_temp(&mock, 45.0, Duration::from_secs(10)).await);
+
+    // Setpoint and tuning changes are audited
+    let (_, entries) = send(&router, "GET", "/audit?action=change_heater_settings", None).await;
+    assert_eq!(entries[0]["old_value"]["target_temp"], 30.0);
+    assert_eq!(entries[0]["new_value"]["target_temp"], 45.0);
+    assert_eq!(entries[0]["new_value"]["kp"], 2.0);
+    let (_, entries) = send(&router, "GET", "/audit?action=reload_config", None).await;
+    assert_eq!(entries[0]["target"], "tags");
+    assert_eq!(entries[0]["old_value"]["scan_rate_ms"], 100);
+    assert_eq!(entries[0]["new_value"]["scan_rate_ms"], 200);
+}
+
+#[tokio::test]
+async fn auth_changes_are_audited_without_key_hashes() {
+    let path = config_path("auth");
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&path, &mock).await;
+
+    let sha256 = hex::encode(Sha256::digest(b"ops"));
+    std::fs::write(
+        &path,
+        format!(
+            "{}\n[[auth.api_keys]]\nname = \"ops\"\nsha256 = \"{}\"\nrole = \"operator\"\n",
+            CONFIG, sha256
+        ),
+    )
+    .unwrap();
+    let (status, body) = send(&router, "POST", "/admin/reload", None).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["applied"], json!(["auth"]));
+    let (_, entries) = send(&router, "GET", "/audit?action=reload_config", None).await;
+    assert_eq!(entries[0]["target"], "auth");
+    assert_eq!(entries[0]["old_value"]["api_keys"], json!([]));
+    assert_eq!(entries[0]["new_value"]["api_keys"][0]["name"], "ops");
+    assert_eq!(entries[0]["new_value"]["api_keys"][0]["role"], "operator");
+    assert!(!entries.to_string().contains(&sha256));
+}
+
+#[tokio::test]
+async fn invalid_config_is_not_applied() {
+    let path = config_path("invalid");
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&path, &mock).await;
+
+    std::fs::write(&path, "[heater]\ntarget_temp = 400.0\ncycle_ms = 1000\n").unwrap();
+    let (status, body) = send(&router, "POST", "/admin/reload", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["message"].as_str().unwrap().contains("target_temp"));
+
+    // Still running on the previous configuration, so restoring it changes nothing
+    std::fs::write(&path, CONFIG).unwrap();
+    let (status, body) = send(&router, "POST", "/admin/reload", None).await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["applied"], json!([]));
+}
+
+#[tokio::test]
+async fn rejected_plc_settings_are_rolled_back() {
+    let path = config_path("rollback");
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_password(Some("secret"));
+    let router = start(&path, &mock).await;
+
+    std::fs::write(&path, format!("{}\n[plc]\npassword = \"wrong\"\n", CONFIG)).unwrap();
+    let (status, body) = send(&router, "POST", "/admin/reload", None).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("reconnected with the previous ones"));
+    let (status, _) = send(&router, "GET", "/plc", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn reconnecting_on_reload_does_not_hold_up_other_requests() {
+    let path = config_path("slow");
+    let mock = MockPlc::new().with_db(1, 64);
+    // The three pooled connections connect at startup, reconnecting is slow
+    let scenario: FaultScenario = serde_json::from_value(json!({ "rules": [{
+        "fault": { "kind": "latency", "ms": 500 },
+        "operations": ["connect"],
+        "skip": 3,
+    }]}))
+    .unwrap();
+    let router = start_with_faults(&path, &mock, Some(Arc::new(FaultInjector::new(scenario)))).await;
+
+    std::fs::write(&path, format!("{}\n[plc]\nslot = 1\n", CONFIG)).unwrap();
+    let reload_router = router.clone();
+    let reload =
+        tokio::spawn(async move { send(&reload_router, "POST", "/admin/reload", None).await });
+    sleep(Duration::from_millis(200)).await;
+
+    // Answered while the pool is still reconnecting
+    let (status, _) = send(&router, "GET", "/tags", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!reload.is_finished());
+
+    let (status, body) = reload.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["applied"], json!(["plc"]));
+}
+
+#[tokio::test]
+async fn reload_leaves_the_heater_running_as_the_operator_left_it() {
+    let path = config_path("enabled");
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = start(&path, &mock).await;
+    let (status, _) = send(&router, "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    std::fs::write(
+        &path,
+        "[heater]\nenabled = false\ntarget_temp = 30.0\nkp = 2.0\ncycle_ms = 1000\n",
+    )
+    .unwrap();
+    let (status, body) = send(&router, "POST", "/admin/reload", None).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["applied"], json!(["heater"]));
+    assert_eq!(body["restart_required"], json!(["heater.enabled"]));
+    let (_, entries) = send(&router, "GET", "/audit?action=disable_heater", None).await;
+    assert_eq!(entries, json!([]));
+}
+
+#[tokio::test]
+async fn config_file_changes_are_picked_up() {
+    let path = config_path("watch");
+    let mock = MockPlc::new().with_db(1, 64);
+    let _router = start(&path, &mock).await;
+    assert!(wait_for_target_temp(&mock, 30.0, Duration::from_secs(10)).await);
+
+    // Make sure the modification time moves even on coarse filesystems
+    sleep(Duration::from_millis(1100)).await;
+    std::fs::write(&path, "[heater]\ntarget_temp = 50.0\ncycle_ms = 1000\n").unwrap();
+
+    let applied = wait_for_target_temp(&mock, 50.0, Duration::from_secs(10)).await;
+    std::fs::remove_file(&path).unwrap();
+    assert!(applied);
+}
@@ -0,0 +1,194 @@
+use crate::config::AppConfig;
+use chrono::NaiveTime;
+
+fn config_path(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("snap7-rust-{}-{}.toml", name, std::process::id()));
+    path.to_string_lossy().to_string()
+}
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn load_error(overrides: &[&str]) -> String {
+    let overrides: Vec<String> = overrides.iter().map(|entry| entry.to_string()).collect();
+    AppConfig::load(None, Vec::new(), &overrides)
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn defaults_are_valid() {
+    let config = AppConfig::load(None, Vec::new(), &[]).unwrap();
+
+    assert_eq!(config.http.bind, "0.0.0.0:3000");
+    assert_eq!(config.plc.address, "192.168.0.1");
+    assert_eq!(config.heater.target_temp, 30.0);
+    assert!(config.tags.table().find("temp_ai").is_some());
+    assert_eq!(config.clock.check_interval_s, 60);
+    assert_eq!(config.storage.backup_time(), NaiveTime::from_hms_opt(2, 0, 0).unwrap());
+}
+
+#[test]
+fn schedules_come_from_the_config() {
+    let config = AppConfig::load(
+        None,
+        Vec::new(),
+        &["clock.max_drift_ms=500".into(), "storage.backup_at=23:30".into()],
+    )
+    .unwrap();
+
+    assert_eq!(config.clock.max_drift_ms, 500);
+    assert_eq!(config.storage.backup_time(), NaiveTime::from_hms_opt(23, 30, 0).unwrap());
+}
+
+#[test]
+fn environment_overrides_file_and_flags_override_environment() {
+    let path = config_path("layers");
+    std::fs::write(
+        &path,
+        r#"
+[http]
+bind = "127.0.0.1:8080"
+
+[plc]
+address = "10.0.0.1"
+rack = 1
+
+[heater]
+target_temp = 45.0
+"#,
+    )
+    .unwrap();
+
+    let config = AppConfig::load(
+        Some(&path),
+        env(&[
+            ("SNAP7_PLC__ADDRESS", "10.0.0.2"),
+            ("SNAP7_HEATER__TARGET_TEMP", "50"),
+            ("SNAP7_CONFIG", "ignored.toml"),
+            ("HOME", "/root"),
+        ]),
+        &["heater.target_temp=55.5".to_string()],
+    )
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(config.http.bind, "127.0.0.1:8080");
+    assert_eq!(config.plc.address, "10.0.0.2");
+    assert_eq!(config.plc.rack, 1);
+    assert_eq!(config.plc.slot, 2);
+    assert_eq!(config.heater.target_temp, 55.5);
+}
+
+#[test]
+fn tag_table_and_storage_come_from_file() {
+    let path = config_path("tags");
+    std::fs::write(
+        &path,
+        r#"
+[storage]
+backups_dir = "/var/lib/snap7/backups"
+
+[[tags.table]]
+name = "temp_ai"
+db = 2
+offset = 0
+data_type = "int"
+
+[[tags.table]]
+name = "heater_enabled"
+db = 2
+offset = 2
+bit = 0
+data_type = "bool"
+
+[[tags.table]]
+name = "heater_on"
+db = 2
+offset = 2
+bit = 1
+data_type = "bool"
+
+[[tags.table]]
+name = "power_percentage"
+db = 2
+offset = 4
+data_type = "real"
+
+[[tags.table]]
+name = "target_temp"
+db = 2
+offset = 8
+data_type = "real"
+
+[[tags.table]]
+name = "current_temp"
+db = 2
+offset = 12
+data_type = "real"
+"#,
+    )
+    .unwrap();
+
+    let config = AppConfig::load(Some(&path), Vec::new(), &[]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(config.tags.table().find("heater_on").unwrap().db, 2);
+    assert_eq!(
+        config.storage.backups_dir.to_string_lossy(),
+        "/var/lib/snap7/backups"
+    );
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    assert!(load_error(&["http.bind=localhost"]).contains("address:port"));
+    assert!(load_error(&["plc.rack=9"]).contains("rack"));
+    assert!(load_error(&["pool.write_connections=0"]).contains("write_connections"));
+    assert!(load_error(&["storage.backups_keep=0"]).contains("backups_keep"));
+    assert!(load_error(&["storage.backup_at=25:00"]).contains("25:00 is not a time of day"));
+    assert!(load_error(&["clock.check_interval_s=0"]).contains("check_interval_s"));
+    assert!(load_error(&["http.tls.cert=missing.pem", "http.tls.key=missing.key"])
+        .contains("missing.pem does not exist"));
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let message = load_error(&["heater.target_tmp=40"]);
+
+    assert!(message.contains("target_tmp"), "{}", message);
+}
+
+#[test]
+fn tag_table_without_heater_tags_is_rejected() {
+    let path = config_path("missing-tags");
+    std::fs::write(
+        &path,
+        r#"
+[[tags.table]]
+name = "temp_ai"
+db = 1
+offset = 0
+data_type = "int"
+
+[[tags.table]]
+name = "temp_ai"
+db = 1
+offset = 2
+data_type = "int"
+"#,
+    )
+    .unwrap();
+
+    let message = AppConfig::load(Some(&path), Vec::new(), &[])
+        .unwrap_err()
+        .to_string();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(message.contains("temp_ai is defined twice"), "{}", message);
+    assert!(message.contains("heater_on"), "{}", message);
+}
@@ -0,0 +1,309 @@
+use super::harness::{eventually, send, test_config, TestApp};
+use crate::{
+    blocks::BlockKind,
+    config::ConfigSource,
+    plc::{FaultInjector, FaultScenario, MockPlc, PlcArea, PlcPool, PoolConfig},
+    routes::{create_app, AppState, PLCConfig},
+    simulator::SimulatedArea,
+    tags::{Quality, Tag, TagPoller, TagTable, TagType, TagValue},
+};
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::time::Duration;
+
+async fn start_with_faults(scenario: Value) -> TestApp {
+    let scenario: FaultScenario = serde_json::from_value(scenario).unwrap();
+    TestApp::start_with(PoolConfig {
+        request_timeout: Duration::from_millis(500),
+        faults: Some(Arc::new(FaultInjector::new(scenario))),
+        ..Default::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn item_error_is_reported_as_server_error() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "item_error", "message": "CPU : Address out of range" },
+        "operations": ["get_plc_status"],
+    }]}))
+    .await;
+
+    let (status, body) = app.get("/plc").await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("Address out of range"));
+}
+
+#[tokio::test]
+async fn hung_request_times_out() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "timeout", "after_ms": 1000 },
+        "operations": ["get_plc_status"],
+        "count": 1,
+    }]}))
+    .await;
+
+    let (status, body) = app.get("/plc").await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body["message"].as_str().unwrap().contains("timed out"));
+}
+
+#[tokio::test]
+async fn latency_spike_still_succeeds() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "latency", "ms": 200 },
+        "operations": ["get_plc_status"],
+    }]}))
+    .await;
+
+    let (status, body) = app.get("/plc").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Running");
+}
+
+#[tokio::test]
+async fn health_check_reconnects_after_disconnect() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "disconnect" },
+        "operations": ["get_plc_status"],
+        "count": 1,
+    }]}))
+    .await;
+
+    let (status, _) = app.get("/plc").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let (status, _) = app.get("/health_check/plc").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.get("/plc").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn stop_failure_leaves_cpu_running() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "item_error" },
+        "operations": ["plc_stop"],
+    }]}))
+    .await;
+
+    let (status, _) = app.get("/plc/stop").await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(app.simulator.cpu_status(), 0x08);
+}
+
+#[tokio::test]
+async fn heater_stays_off_on_corrupted_temperature() {
+    // 0xFFFF is far above the analog input range, the interlock must refuse to heat
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "corrupt" },
+        "operations": ["read_area", "read_multi_vars"],
+    }]}))
+    .await;
+
+    app.get("/heater/enable").await;
+
+    let heater_on = eventually(500, || app.simulator.bit(SimulatedArea::Outputs, 0, 1)).await;
+    assert!(!heater_on);
+}
+
+#[tokio::test]
+async fn skip_and_count_limit_when_faults_fire() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "item_error" },
+        "operations": ["get_plc_status"],
+        "skip": 1,
+        "count": 1,
+    }]}))
+    .await;
+
+    let (first, _) = app.get("/plc").await;
+    let (second, _) = app.get("/plc").await;
+    let (third, _) = app.get("/plc").await;
+
+    assert_eq!(first, StatusCode::OK);
+    assert_eq!(second, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(third, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn slow_plc_request_does_not_hold_up_other_requests() {
+    let app = start_with_faults(json!({ "rules": [{
+        "fault": { "kind": "latency", "ms": 2000 },
+        "operations": ["get_plc_date_time"],
+        "count": 1,
+    }]}))
+    .await;
+
+    let router = app.router.clone();
+    let slow = tokio::spawn(async move { send(&router, "GET", "/plc/clock", None).await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Answered while the clock read is still waiting for the PLC
+    let (status, _) = app.get("/tags").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!slow.is_finished());
+
+    slow.await.unwrap();
+}
+
+#[tokio::test]
+async fn block_upload_does_not_hold_up_other_requests() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.add_block(BlockKind::Ob, 1, vec![0x70; 256]);
+    let scenario: FaultScenario = serde_json::from_value(json!({ "rules": [{
+        "fault": { "kind": "latency", "ms": 1500 },
+        "operations": ["full_upload"],
+    }]}))
+    .unwrap();
+    let router = create_app(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            faults: Some(Arc::new(FaultInjector::new(scenario))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let upload_router = router.clone();
+    let upload = tokio::spawn(async move {
+        send(&upload_router, "POST", "/plc/blocks/OB/1/upload", None).await
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The status read goes to another connection and needs no lock held by the upload
+    let (status, body) = send(&router, "GET", "/plc", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status_code"], 0x08);
+    assert!(!upload.is_finished());
+
+    let (status, _) = upload.await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn block_download_reports_plc_errors_apart_from_missing_versions() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.add_block(BlockKind::Ob, 1, vec![0x70; 64]);
+    let scenario: FaultScenario = serde_json::from_value(json!({ "rules": [{
+        "fault": {
+            "kind": "item_error",
+            "message": "CPU : Function not authorized for current protection level",
+        },
+        "operations": ["get_plc_status"],
+    }]}))
+    .unwrap();
+    let router = create_app(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            faults: Some(Arc::new(FaultInjector::new(scenario))),
+            ..Default::default()
+        },
+    )
+    .await;
+    let (status, body) = send(&router, "POST", "/plc/backups", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let version = body["manifest"]["version"].as_str().unwrap().to_string();
+
+    let download = |version: &str| {
+        json!({ "version": version, "block_type": "OB", "number": 1, "dry_run": true })
+    };
+    let (status, _) = send(&router, "POST", "/plc/blocks", Some(download("20000101-000000"))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = send(&router, "POST", "/plc/blocks", Some(download(&version))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+    mock.set_reachable(false);
+    let (status, _) = send(&router, "POST", "/plc/blocks", Some(download(&version))).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+/// A mock-backed pool connected through the fault injector.
+async fn faulty_mock_pool(mock: &MockPlc, scenario: Value) -> PlcPool {
+    let scenario: FaultScenario = serde_json::from_value(scenario).unwrap();
+    let pool = PlcPool::new(PoolConfig {
+        client: mock.factory(),
+        faults: Some(Arc::new(FaultInjector::new(scenario))),
+        ..Default::default()
+    });
+    AppState::new(pool.clone(), PLCConfig::default())
+        .connect_to_plc()
+        .await
+        .unwrap();
+    pool
+}
+
+#[tokio::test]
+async fn bad_item_only_affects_its_tags() {
+    let mock = MockPlc::new().with_db(1, 4).with_db(2, 4);
+    mock.set_bytes(PlcArea::Db(1), 0, &11849u16.to_be_bytes());
+    mock.set_bytes(PlcArea::Db(2), 0, &500u16.to_be_bytes());
+    // One item per DB, the second one (DB2) fails
+    let pool = faulty_mock_pool(
+        &mock,
+        json!({ "rules": [{
+            "fault": { "kind": "bad_items", "items": [1] },
+            "operations": ["read_multi_vars"],
+        }]}),
+    )
+    .await;
+    let table = TagTable {
+        tags: vec![
+            Tag::new("temp_ai", 1, 0, TagType::Int),
+            Tag::new("level_ai", 2, 0, TagType::Int),
+        ],
+    };
+    let poller = TagPoller::new(table.clone(), Duration::from_millis(100));
+
+    let tags: Vec<_> = table.tags.iter().collect();
+    poller.poll(&pool, &tags).await;
+
+    let good = poller.reading("temp_ai").unwrap();
+    assert_eq!(good.quality, Quality::Good);
+    assert_eq!(good.value, Some(TagValue::Int(11849)));
+    let bad = poller.reading("level_ai").unwrap();
+    assert_eq!(bad.quality, Quality::BadCommFailure);
+    assert_eq!(poller.value("level_ai"), None);
+}
+
+#[tokio::test]
+async fn bad_item_in_multi_write_only_fails_its_tag() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let pool = faulty_mock_pool(
+        &mock,
+        json!({ "rules": [{
+            "fault": { "kind": "bad_items", "items": [0], "message": "CPU : Address out of range" },
+            "operations": ["write_multi_vars"],
+        }]}),
+    )
+    .await;
+    let poller = TagPoller::new(TagTable::heater_db1(), Duration::from_millis(100));
+
+    let result = poller
+        .write_all(
+            &pool,
+            &[
+                ("current_temp", TagValue::Real(21.5)),
+                ("target_temp", TagValue::Real(42.5)),
+            ],
+        )
+        .await;
+
+    let message = result.unwrap_err().to_string();
+    assert!(message.contains("current_temp: CPU : Address out of range"), "{}", message);
+    assert!(!message.contains("target_temp"), "{}", message);
+    let db1 = mock.area(PlcArea::Db(1)).unwrap();
+    assert_eq!(db1[8..12], 42.5f32.to_be_bytes());
+}
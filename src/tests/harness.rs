@@ -0,0 +1,173 @@
+use crate::{
+    config::{AppConfig, AuditConfig, AuthConfig, ConfigSource, StateConfig, StorageConfig},
+    plc::PoolConfig,
+    routes::{create_app, PLCConfig},
+    simulator::PlcSimulator,
+};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use tower::ServiceExt;
+
+// Every test gets its own simulator port so tests can run in parallel
+static NEXT_PORT: AtomicU16 = AtomicU16::new(21102);
+static NEXT_STATE_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// An empty state directory, so tests never restore each other's runtime state.
+pub fn state_dir() -> PathBuf {
+    let number = NEXT_STATE_DIR.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!(
+        "snap7-rust-state-{}-{}",
+        std::process::id(),
+        number
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// The default configuration with a fresh state directory, holding the audit log,
+/// backups, snapshots and block baselines too so tests never write into the working tree.
+/// Anonymous access is allowed, so tests without credentials may call every endpoint.
+pub fn test_config() -> AppConfig {
+    let dir = state_dir();
+    AppConfig {
+        audit: AuditConfig {
+            file: dir.join("audit.jsonl"),
+        },
+        storage: StorageConfig {
+            backups_dir: dir.join("backups"),
+            snapshots_dir: dir.join("snapshots"),
+            block_watch_dir: dir.join("block_watch"),
+            ..Default::default()
+        },
+        state: StateConfig {
+            dir,
+            ..Default::default()
+        },
+        auth: AuthConfig {
+            allow_anonymous: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// The router from `create_app` connected to a fresh in-process simulated PLC.
+pub struct TestApp {
+    pub router: Router,
+    pub simulator: PlcSimulator,
+    pub port: u16,
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        Self::start_with(PoolConfig::default()).await
+    }
+
+    pub async fn start_with(pool_config: PoolConfig) -> Self {
+        let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+        let simulator = PlcSimulator::start("127.0.0.1", port).expect("failed to start simulator");
+
+        let router = create_app(Self::config(port), ConfigSource::default(), pool_config).await;
+        TestApp {
+            router,
+            simulator,
+            port,
+        }
+    }
+
+    fn config(port: u16) -> AppConfig {
+        let mut plc = PLCConfig {
+            address: "127.0.0.1".to_string(),
+            ..Default::default()
+        };
+        plc.connection.port = port;
+        AppConfig {
+            plc,
+            ..test_config()
+        }
+    }
+
+    pub async fn request(
+        &self,
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        send(&self.router, method, uri, body).await
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.request("GET", uri, None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request("POST", uri, Some(body)).await
+    }
+}
+
+/// Sends one request through `router` and returns the status and JSON body.
+pub async fn send(
+    router: &Router,
+    method: &str,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    send_with_headers(router, method, uri, &[], body).await
+}
+
+/// `send` with extra request headers, such as credentials.
+pub async fn send_with_headers(
+    router: &Router,
+    method: &str,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, json)
+}
+
+/// Body for `/plc/configure_connection` pointing at a local port.
+pub fn connection_settings(port: u16) -> Value {
+    json!({
+        "address": "127.0.0.1",
+        "rack": 0,
+        "slot": 2,
+        "port": port,
+        "connect_timeout_ms": 500,
+    })
+}
+
+/// Polls `condition` every 50 ms until it holds or `timeout_ms` passes.
+pub async fn eventually(timeout_ms: u64, condition: impl Fn() -> bool) -> bool {
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_millis(timeout_ms);
+    while tokio::time::Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    }
+    condition()
+}
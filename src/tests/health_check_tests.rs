@@ -0,0 +1,68 @@
+use super::harness::{connection_settings, TestApp};
+use crate::plc::{FaultInjector, FaultScenario, PoolConfig};
+use axum::http::StatusCode;
+use serde_json::json;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn server_health_check_reports_healthy() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app.get("/health_check/server").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Server is healthy");
+}
+
+#[tokio::test]
+async fn plc_health_check_connects_to_simulator() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app.get("/health_check/plc").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Connected to PLC");
+    assert_eq!(body["address"], "127.0.0.1");
+    assert_eq!(body["rack"], 0);
+    assert_eq!(body["slot"], 2);
+}
+
+#[tokio::test]
+async fn plc_health_check_fails_without_plc() {
+    let app = TestApp::start().await;
+
+    // Nothing listens on this port
+    let (status, _) = app
+        .post(
+            "/plc/configure_connection",
+            connection_settings(app.port + 1000),
+        )
+        .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let (status, _) = app.get("/health_check/plc").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn plc_health_check_does_not_reconnect() {
+    // The three pooled connections connect at startup, any later connect fails
+    let scenario: FaultScenario = serde_json::from_value(json!({ "rules": [{
+        "fault": { "kind": "item_error", "message": "reconnected" },
+        "operations": ["connect"],
+        "skip": 3,
+    }]}))
+    .unwrap();
+    let app = TestApp::start_with(PoolConfig {
+        faults: Some(Arc::new(FaultInjector::new(scenario))),
+        ..Default::default()
+    })
+    .await;
+
+    let (status, body) = app.get("/health_check/plc").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["connections"].as_array().unwrap().len(), 3);
+
+    let (status, _) = app.get("/plc").await;
+    assert_eq!(status, StatusCode::OK);
+}
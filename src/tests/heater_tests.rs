@@ -0,0 +1,55 @@
+use super::harness::{eventually, TestApp};
+use crate::simulator::SimulatedArea;
+use axum::http::StatusCode;
+
+// Heater output Q0.1 and water-present input I0.0
+const OUTPUT_BYTE: usize = 0;
+const HEATER_BIT: u8 = 1;
+const WATER_BIT: u8 = 0;
+
+#[tokio::test]
+async fn enable_switches_heater_output_on_below_setpoint() {
+    let app = TestApp::start().await;
+    assert!(!app
+        .simulator
+        .bit(SimulatedArea::Outputs, OUTPUT_BYTE, HEATER_BIT));
+
+    let (status, body) = app.get("/heater/enable").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "Heater enabled");
+    // The simulated tank starts at 20 °C, well below the 30 °C setpoint
+    assert!(
+        eventually(2000, || app.simulator.bit(
+            SimulatedArea::Outputs,
+            OUTPUT_BYTE,
+            HEATER_BIT
+        ))
+        .await
+    );
+}
+
+#[tokio::test]
+async fn heater_stays_off_without_water() {
+    let app = TestApp::start().await;
+    app.simulator
+        .set_bit(SimulatedArea::Inputs, 0, WATER_BIT, false);
+
+    app.get("/heater/enable").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    assert!(!app
+        .simulator
+        .bit(SimulatedArea::Outputs, OUTPUT_BYTE, HEATER_BIT));
+}
+
+#[tokio::test]
+async fn disable_heater_responds() {
+    let app = TestApp::start().await;
+    app.get("/heater/enable").await;
+
+    let (status, body) = app.get("/heater/disable").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "Heater disabled");
+}
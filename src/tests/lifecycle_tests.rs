@@ -0,0 +1,47 @@
+use super::harness::{send, test_config};
+use crate::{
+    config::ConfigSource,
+    lifecycle,
+    plc::{MockPlc, PlcArea, PoolConfig},
+    routes::create_routes,
+};
+use axum::http::StatusCode;
+use tokio::time::{sleep, Duration, Instant};
+
+#[tokio::test]
+async fn stop_switches_heater_off_and_disconnects() {
+    // Water present and 20 °C, so the enabled heater switches Q0.1 on
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Inputs, 0, &[0x01]);
+    mock.set_bytes(PlcArea::Db(1), 0, &11849u16.to_be_bytes());
+    let app = create_routes(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let (status, _) = send(&app.router, "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while !mock.bit(PlcArea::Outputs, 0, 1) && Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert!(mock.bit(PlcArea::Outputs, 0, 1));
+    // heater_on (DB1.DBX2.2) as the control loop would leave it mid-window
+    mock.set_bytes(PlcArea::Db(1), 2, &[0x04]);
+
+    lifecycle::stop(&app.state).await.unwrap();
+
+    assert!(!mock.bit(PlcArea::Outputs, 0, 1));
+    assert!(!mock.bit(PlcArea::Db(1), 2, 2));
+    let (status, _) = send(&app.router, "GET", "/plc", None).await;
+    assert_ne!(status, StatusCode::OK);
+
+    // Nothing switches it back on once stopped
+    sleep(Duration::from_millis(500)).await;
+    assert!(!mock.bit(PlcArea::Outputs, 0, 1));
+}
@@ -0,0 +1,394 @@
+use super::harness::{send, test_config};
+use crate::{
+    blocks::BlockKind,
+    config::ConfigSource,
+    heater::Heater,
+    plc::{
+        FaultInjector, FaultScenario, MockPlc, PlcArea, PlcPool, PoolConfig, Priority, Recording,
+    },
+    routes::{create_app, AppState, PLCConfig},
+    tags::{TagPoller, TagTable, TagValue},
+};
+use axum::http::StatusCode;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+// Raw analog value of 20 °C in DB1.DBW0
+const RAW_20_C: [u8; 2] = 11849u16.to_be_bytes();
+
+/// A connected pool without the rest of the application.
+async fn connected_pool(config: PoolConfig) -> PlcPool {
+    let pool = PlcPool::new(config);
+    let app_state = AppState::new(pool.clone(), PLCConfig::default());
+    app_state.connect_to_plc().await.unwrap();
+    pool
+}
+
+async fn mock_pool(mock: &MockPlc) -> PlcPool {
+    connected_pool(PoolConfig {
+        client: mock.factory(),
+        ..Default::default()
+    })
+    .await
+}
+
+fn heater() -> Heater {
+    Heater {
+        target_temp: 30.0,
+        enabled: Arc::new(Mutex::new(false)),
+        task: Arc::new(Mutex::new(None)),
+    }
+}
+
+#[tokio::test]
+async fn heater_scales_temperature_from_mock() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Db(1), 0, &RAW_20_C);
+    let pool = mock_pool(&mock).await;
+
+    let temperature = heater().get_temperature(&pool).await.unwrap();
+
+    assert!((temperature - 20.0).abs() < 0.01, "got {}", temperature);
+}
+
+#[tokio::test]
+async fn heater_rejects_overrange_temperature() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Db(1), 0, &32767u16.to_be_bytes());
+    let pool = mock_pool(&mock).await;
+
+    let result = heater().get_temperature(&pool).await;
+
+    assert!(result.unwrap_err().to_string().contains("overrange"));
+}
+
+#[tokio::test]
+async fn tag_poller_reads_and_writes_through_mock() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Db(1), 0, &RAW_20_C);
+    let pool = mock_pool(&mock).await;
+    let poller = TagPoller::new(TagTable::heater_db1(), Duration::from_millis(100));
+
+    let table = poller.table();
+    let tags: Vec<_> = table.tags.iter().collect();
+    poller.poll(&pool, &tags).await;
+    assert_eq!(poller.value("temp_ai"), Some(TagValue::Int(11849)));
+
+    poller
+        .write_all(
+            &pool,
+            &[
+                ("target_temp", TagValue::Real(42.5)),
+                ("heater_on", TagValue::Bool(true)),
+            ],
+        )
+        .await
+        .unwrap();
+
+    let db1 = mock.area(PlcArea::Db(1)).unwrap();
+    assert_eq!(db1[8..12], 42.5f32.to_be_bytes());
+    assert!(mock.bit(PlcArea::Db(1), 2, 2));
+}
+
+#[tokio::test]
+async fn tag_write_is_not_skipped_inside_the_deadband() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Db(1), 4, &10.0f32.to_be_bytes());
+    let pool = mock_pool(&mock).await;
+    let poller = TagPoller::new(TagTable::heater_db1(), Duration::from_millis(100));
+    let table = poller.table();
+    let power = [table.find("power_percentage").unwrap()];
+    poller.poll(&pool, &power).await;
+
+    // Moved by less than the 0.5 deadband, so the reported value stays at 10
+    mock.set_bytes(PlcArea::Db(1), 4, &10.3f32.to_be_bytes());
+    poller.poll(&pool, &power).await;
+    assert_eq!(poller.value("power_percentage"), Some(TagValue::Real(10.0)));
+
+    let written = poller
+        .write(&pool, "power_percentage", TagValue::Real(10.0))
+        .await
+        .unwrap();
+    assert!(written);
+    assert_eq!(mock.area(PlcArea::Db(1)).unwrap()[4..8], 10.0f32.to_be_bytes());
+    let written = poller
+        .write(&pool, "power_percentage", TagValue::Real(10.0))
+        .await
+        .unwrap();
+    assert!(!written);
+}
+
+#[tokio::test]
+async fn write_all_names_rejected_tags() {
+    // DB1 too short for the real tags at offset 8 and up
+    let mock = MockPlc::new().with_db(1, 4);
+    let pool = mock_pool(&mock).await;
+    let poller = TagPoller::new(TagTable::heater_db1(), Duration::from_millis(100));
+
+    let result = poller
+        .write_all(
+            &pool,
+            &[
+                ("heater_on", TagValue::Bool(true)),
+                ("target_temp", TagValue::Real(42.5)),
+            ],
+        )
+        .await;
+
+    let message = result.unwrap_err().to_string();
+    assert!(message.contains("target_temp"), "{}", message);
+    assert!(!message.contains("heater_on"), "{}", message);
+    assert!(mock.bit(PlcArea::Db(1), 2, 2));
+}
+
+#[tokio::test]
+async fn controllers_run_against_mock() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let router = create_app(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let (status, body) = send(&router, "GET", "/plc", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status_code"], 0x08);
+
+    let (status, _) = send(&router, "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mock.status(), 0x04);
+}
+
+#[tokio::test]
+async fn protected_mock_rejects_control_without_password() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_password(Some("secret"));
+    let router = create_app(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let (status, _) = send(&router, "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &router,
+        "PUT",
+        "/plc/session_password",
+        Some(json!({ "password": "secret" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&router, "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn recording_replays_without_plc() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Db(1), 0, &RAW_20_C);
+    let recording = Recording::new();
+    let pool = connected_pool(PoolConfig {
+        client: recording.recorder(mock.factory()),
+        ..Default::default()
+    })
+    .await;
+
+    let recorded_temperature = heater().get_temperature(&pool).await.unwrap();
+    let recorded_status = pool
+        .read(Priority::Hmi, |client| client.get_plc_status())
+        .await
+        .unwrap();
+    assert!(recording
+        .exchanges()
+        .iter()
+        .any(|exchange| exchange.operation == "read_area"));
+
+    // Replay from a copy so nothing can reach the mock
+    let replay = Recording::from_exchanges(recording.exchanges());
+    let pool = connected_pool(PoolConfig {
+        client: replay.player(),
+        ..Default::default()
+    })
+    .await;
+
+    let temperature = heater().get_temperature(&pool).await.unwrap();
+    let status = pool
+        .read(Priority::Hmi, |client| client.get_plc_status())
+        .await
+        .unwrap();
+    assert_eq!(temperature, recorded_temperature);
+    assert_eq!(status, recorded_status);
+
+    // Every recorded answer is used once
+    let again = pool
+        .read(Priority::Hmi, |client| client.get_plc_status())
+        .await;
+    assert!(again
+        .unwrap_err()
+        .to_string()
+        .contains("No recorded response"));
+}
+
+#[tokio::test]
+async fn health_check_replaces_connections_after_outage() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let pool = mock_pool(&mock).await;
+    let app_state = AppState::new(pool.clone(), PLCConfig::default());
+
+    mock.set_reachable(false);
+    pool.check_health(app_state.connect_job()).await;
+    assert!(pool.status().iter().all(|connection| !connection.healthy));
+
+    mock.set_reachable(true);
+    pool.check_health(app_state.connect_job()).await;
+    assert!(pool.status().iter().all(|connection| connection.healthy));
+    assert!(pool
+        .status()
+        .iter()
+        .all(|connection| connection.replacements == 2));
+}
+
+#[tokio::test]
+async fn blocks_upload_and_download_through_mock() {
+    let mock = MockPlc::new().with_db(1, 64);
+    // MC7 header with the block type (FC) at byte 5 and the number at bytes 6 and 7
+    let block = vec![0x70, 0x70, 0x01, 0x01, 0x05, 0x0C, 0x00, 0x07, 0xAA];
+    mock.add_block(BlockKind::Fc, 7, block.clone());
+    let app_state = AppState::new(mock_pool(&mock).await, PLCConfig::default());
+
+    assert_eq!(
+        app_state.list_blocks_of_type(BlockKind::Fc).await.unwrap(),
+        vec![7]
+    );
+    let uploaded = app_state.upload_block(BlockKind::Fc, 7).await.unwrap();
+    assert_eq!(uploaded, block);
+
+    let mut copy = uploaded.clone();
+    copy[7] = 8;
+    app_state.download_block(&copy).await.unwrap();
+    assert_eq!(
+        app_state.list_blocks_of_type(BlockKind::Fc).await.unwrap(),
+        vec![7, 8]
+    );
+}
+
+#[tokio::test]
+async fn health_check_leaves_busy_connections_alone() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.add_block(BlockKind::Fc, 7, vec![0x70; 64]);
+    let scenario: FaultScenario = serde_json::from_value(json!({ "rules": [{
+        "fault": { "kind": "latency", "ms": 1000 },
+        "operations": ["full_upload"],
+    }]}))
+    .unwrap();
+    let pool = connected_pool(PoolConfig {
+        client: mock.factory(),
+        read_connections: 1,
+        health_check_timeout: Duration::from_millis(100),
+        faults: Some(Arc::new(FaultInjector::new(scenario))),
+        ..Default::default()
+    })
+    .await;
+    let app_state = AppState::new(pool.clone(), PLCConfig::default());
+
+    let uploading = app_state.clone();
+    let upload = tokio::spawn(async move { uploading.upload_block(BlockKind::Fc, 7).await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    pool.check_health(app_state.connect_job()).await;
+
+    assert!(upload.await.unwrap().is_ok());
+    assert!(pool
+        .status()
+        .iter()
+        .all(|connection| connection.healthy && connection.replacements == 0));
+}
+
+#[tokio::test]
+async fn program_changes_stand_until_the_baseline_is_accepted() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.add_block(BlockKind::Ob, 1, vec![0x70; 64]);
+    let router = create_app(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await;
+    let (status, body) = send(&router, "POST", "/plc/blocks/changes/check", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["change"].is_null());
+
+    mock.add_block(BlockKind::Fc, 1, vec![0x70; 64]);
+    for _ in 0..2 {
+        let (_, body) = send(&router, "POST", "/plc/blocks/changes/check", None).await;
+        assert_eq!(body["change"]["added"], json!(["FC1"]));
+    }
+    // Reported once, not on every check
+    let (_, body) = send(&router, "GET", "/plc/blocks/changes", None).await;
+    assert_eq!(body["changes"].as_array().unwrap().len(), 1);
+
+    let (status, _) = send(&router, "POST", "/plc/blocks/baseline", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(&router, "POST", "/plc/blocks/changes/check", None).await;
+    assert!(body["change"].is_null());
+
+    // Accepting the change is audited with the baseline it replaced
+    let (_, entries) = send(&router, "GET", "/audit?action=accept_block_baseline", None).await;
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert!(entries[0]["old_value"]["blocks"]["FC1"].is_null());
+    assert!(entries[0]["old_value"]["blocks"]["OB1"].is_object());
+    assert!(entries[0]["new_value"]["blocks"]["FC1"].is_object());
+}
+
+#[tokio::test]
+async fn connection_change_keeps_the_session_password() {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_password(Some("secret"));
+    let router = create_app(
+        test_config(),
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await;
+    let (status, _) = send(
+        &router,
+        "PUT",
+        "/plc/session_password",
+        Some(json!({ "password": "secret" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &router,
+        "POST",
+        "/plc/configure_connection",
+        Some(json!({ "address": "10.0.0.9", "rack": 0, "slot": 1 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The mock protects writes and control, both still go through
+    let reading = json!({ "value": 13824 });
+    let (status, body) = send(&router, "PUT", "/tags/temp_ai", Some(reading)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = send(&router, "GET", "/plc/stop", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
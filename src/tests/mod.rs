@@ -0,0 +1,14 @@
+mod audit_tests;
+mod auth_tests;
+mod authorization_tests;
+mod config_reload_tests;
+mod config_tests;
+mod fault_injection_tests;
+mod harness;
+mod health_check_tests;
+mod heater_tests;
+mod lifecycle_tests;
+mod mock_client_tests;
+mod plc_control_tests;
+mod recording_tests;
+mod state_store_tests;
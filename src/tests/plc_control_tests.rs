@@ -0,0 +1,105 @@
+use super::harness::{connection_settings, TestApp};
+use axum::http::StatusCode;
+use serde_json::json;
+
+const CPU_RUN: i32 = 0x08;
+const CPU_STOP: i32 = 0x04;
+
+#[tokio::test]
+async fn operating_mode_reports_running_cpu() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app.get("/plc").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status_code"], CPU_RUN);
+    assert_eq!(body["message"], "Running");
+}
+
+#[tokio::test]
+async fn stop_puts_cpu_in_stop() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app.get("/plc/stop").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "PLC Stopped");
+    assert_eq!(app.simulator.cpu_status(), CPU_STOP);
+
+    let (_, body) = app.get("/plc").await;
+    assert_eq!(body["message"], "Stopped");
+}
+
+#[tokio::test]
+async fn hot_start_runs_stopped_cpu() {
+    let app = TestApp::start().await;
+    app.get("/plc/stop").await;
+    assert_eq!(app.simulator.cpu_status(), CPU_STOP);
+
+    let (status, _) = app.get("/plc/hot_start").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.simulator.cpu_status(), CPU_RUN);
+}
+
+#[tokio::test]
+async fn cold_start_runs_stopped_cpu() {
+    let app = TestApp::start().await;
+    app.get("/plc/stop").await;
+    assert_eq!(app.simulator.cpu_status(), CPU_STOP);
+
+    let (status, _) = app.get("/plc/cold_start").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.simulator.cpu_status(), CPU_RUN);
+}
+
+#[tokio::test]
+async fn configure_connection_reconnects_with_new_settings() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app
+        .post("/plc/configure_connection", connection_settings(app.port))
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Config updated and connected to PLC");
+    let (status, _) = app.get("/plc").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn configure_connection_rejects_invalid_settings() {
+    let app = TestApp::start().await;
+
+    let (status, body) = app
+        .post(
+            "/plc/configure_connection",
+            json!({ "address": "127.0.0.1", "rack": 9, "slot": 2 }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Invalid connection settings"));
+
+    // The existing connection is left alone
+    let (status, _) = app.get("/plc").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn configure_connection_rejects_half_configured_tsaps() {
+    let app = TestApp::start().await;
+
+    let (status, _) = app
+        .post(
+            "/plc/configure_connection",
+            json!({ "address": "127.0.0.1", "rack": 0, "slot": 2, "local_tsap": 256 }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
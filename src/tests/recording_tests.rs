@@ -0,0 +1,108 @@
+use crate::{
+    plc::{MockPlc, PlcArea, PlcPool, PoolConfig, Priority, Recording},
+    routes::{AppState, PLCConfig},
+};
+
+fn recording_path(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("snap7-rust-{}-{}.jsonl", name, std::process::id()));
+    path.to_string_lossy().to_string()
+}
+
+async fn connected_pool(config: PoolConfig) -> PlcPool {
+    let pool = PlcPool::new(config);
+    let app_state = AppState::new(pool.clone(), PLCConfig::default());
+    app_state.connect_to_plc().await.unwrap();
+    pool
+}
+
+async fn read_db1(pool: &PlcPool) -> Result<Vec<u8>, anyhow::Error> {
+    pool.read(Priority::Control, |client| {
+        let mut buffer = vec![0u8; 4];
+        client.read_area(PlcArea::Db(1), 0, &mut buffer)?;
+        Ok(buffer)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn recording_file_replays_reads_and_errors() {
+    let path = recording_path("file");
+    let mock = MockPlc::new().with_db(1, 4);
+    mock.set_bytes(PlcArea::Db(1), 0, &[1, 2, 3, 4]);
+
+    let recording = Recording::create(&path).unwrap();
+    let pool = connected_pool(PoolConfig {
+        client: recording.recorder(mock.factory()),
+        ..Default::default()
+    })
+    .await;
+    let recorded = read_db1(&pool).await.unwrap();
+    let missing = pool
+        .read(Priority::Bulk, |client| client.db_get(9, 16))
+        .await
+        .unwrap_err();
+    drop(pool);
+
+    // The file holds one JSON line per exchange, with timing and the answer
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let read = lines
+        .iter()
+        .find(|line| line["operation"] == "read_area")
+        .unwrap();
+    assert_eq!(read["request"]["area"], serde_json::json!({ "db": 1 }));
+    assert!(read["at_ms"].is_u64() && read["duration_ms"].is_u64());
+    assert_eq!(read["response"]["Ok"], serde_json::json!([1, 2, 3, 4]));
+
+    let replay = Recording::load(&path).unwrap();
+    let pool = connected_pool(PoolConfig {
+        client: replay.player(),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(read_db1(&pool).await.unwrap(), recorded);
+    let replayed_error = pool
+        .read(Priority::Bulk, |client| client.db_get(9, 16))
+        .await
+        .unwrap_err();
+    assert_eq!(replayed_error.to_string(), missing.to_string());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn replay_accepts_writes_with_other_values() {
+    let mock = MockPlc::new().with_db(1, 4);
+    let recording = Recording::new();
+    let pool = connected_pool(PoolConfig {
+        client: recording.recorder(mock.factory()),
+        ..Default::default()
+    })
+    .await;
+    pool.write(Priority::Control, |client| {
+        client.write_area(PlcArea::Db(1), 0, &[9, 9])
+    })
+    .await
+    .unwrap();
+
+    let replay = Recording::from_exchanges(recording.exchanges());
+    let pool = connected_pool(PoolConfig {
+        client: replay.player(),
+        ..Default::default()
+    })
+    .await;
+
+    // Same operation, different data: answered from the recorded write
+    pool.write(Priority::Control, |client| {
+        client.write_area(PlcArea::Db(1), 0, &[7, 7])
+    })
+    .await
+    .unwrap();
+    // Reads must match exactly
+    let error = read_db1(&pool).await.unwrap_err();
+    assert!(error.to_string().contains("No recorded response"));
+}
@@ -0,0 +1,183 @@
+use super::harness::{send, state_dir, test_config};
+use crate::{
+    config::{AppConfig, ConfigSource, HeaterConfig, StartPolicy},
+    persistence::StateStore,
+    plc::{MockPlc, PlcArea, PoolConfig},
+    routes::{create_app, PLCConfig},
+};
+use axum::{http::StatusCode, Router};
+use pid::Pid;
+use serde_json::json;
+use tokio::time::{sleep, Duration, Instant};
+
+async fn start(config: AppConfig, mock: &MockPlc) -> Router {
+    create_app(
+        config,
+        ConfigSource::default(),
+        PoolConfig {
+            client: mock.factory(),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Water present and 20 °C, so an enabled heater switches Q0.1 on.
+fn cold_tank() -> MockPlc {
+    let mock = MockPlc::new().with_db(1, 64);
+    mock.set_bytes(PlcArea::Inputs, 0, &[0x01]);
+    mock.set_bytes(PlcArea::Db(1), 0, &11849u16.to_be_bytes());
+    mock
+}
+
+async fn heater_switched_on(mock: &MockPlc, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if mock.bit(PlcArea::Outputs, 0, 1) {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn connection_settings_survive_restart() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let config = test_config();
+    let router = start(config.clone(), &mock).await;
+
+    let (status, _) = send(
+        &router,
+        "POST",
+        "/plc/configure_connection",
+        Some(json!({ "address": "10.0.0.9", "rack": 0, "slot": 1 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let restarted = start(config.clone(), &mock).await;
+    let (_, body) = send(&restarted, "GET", "/health_check/plc", None).await;
+    assert_eq!(body["address"], "10.0.0.9");
+    assert_eq!(body["slot"], 1);
+
+    // An edit of the config file made while down wins over the saved settings
+    let mut edited = config;
+    edited.plc.address = "10.0.0.20".to_string();
+    let restarted = start(edited, &mock).await;
+    let (_, body) = send(&restarted, "GET", "/health_check/plc", None).await;
+    assert_eq!(body["address"], "10.0.0.20");
+    assert_eq!(body["slot"], 2);
+}
+
+#[tokio::test]
+async fn failed_connection_change_is_not_saved() {
+    let mock = MockPlc::new().with_db(1, 64);
+    let config = test_config();
+    let router = start(config.clone(), &mock).await;
+
+    mock.set_reachable(false);
+    let (status, _) = send(
+        &router,
+        "POST",
+        "/plc/configure_connection",
+        Some(json!({ "address": "10.0.0.9", "rack": 0, "slot": 1 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    mock.set_reachable(true);
+    let restarted = start(config.clone(), &mock).await;
+    let (_, body) = send(&restarted, "GET", "/health_check/plc", None).await;
+    assert_eq!(body["address"], config.plc.address);
+    assert_eq!(body["slot"], config.plc.slot);
+}
+
+#[tokio::test]
+async fn restore_policy_restarts_enabled_heater() {
+    let mut config = test_config();
+    config.state.start = StartPolicy::Restore;
+    let router = start(config.clone(), &cold_tank()).await;
+    let (status, _) = send(&router, "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mock = cold_tank();
+    let _restarted = start(config, &mock).await;
+
+    assert!(heater_switched_on(&mock, Duration::from_secs(3)).await);
+}
+
+#[tokio::test]
+async fn safe_start_leaves_heater_off() {
+    let config = test_config();
+    let router = start(config.clone(), &cold_tank()).await;
+    let (status, _) = send(&router, "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mock = cold_tank();
+    let _restarted = start(config, &mock).await;
+
+    assert!(!heater_switched_on(&mock, Duration::from_secs(1)).await);
+}
+
+#[tokio::test]
+async fn pid_is_not_saved_on_every_heater_cycle() {
+    let mut config = test_config();
+    config.heater.cycle_ms = 1000;
+    let mock = cold_tank();
+    let router = start(config.clone(), &mock).await;
+    let (status, _) = send(&router, "GET", "/heater/enable", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(heater_switched_on(&mock, Duration::from_secs(3)).await);
+
+    let saved = || StateStore::open(&config.state.dir).unwrap().state();
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while saved().pid.is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+    }
+    let first = saved();
+    assert!(first.pid.is_some());
+
+    // Nothing else writes the state file while the heater runs a few cycles
+    sleep(Duration::from_millis(2500)).await;
+    assert_eq!(saved().saved_at, first.saved_at);
+}
+
+#[test]
+fn pid_is_restored_only_with_unchanged_heater_settings() {
+    let dir = state_dir();
+    let heater = HeaterConfig::default();
+    let mut pid: Pid<f64> = Pid::new(heater.target_temp, heater.output_limit);
+    pid.p(heater.kp, 100.0).i(heater.ki, 100.0);
+    pid.next_control_output(20.0);
+    StateStore::open(&dir)
+        .unwrap()
+        .save_pid(&pid, &heater)
+        .unwrap();
+
+    let store = StateStore::open(&dir).unwrap();
+    let mut restored = store.pid(&heater).unwrap();
+    // The integrator carries on from the saved value
+    assert_eq!(
+        restored.next_control_output(20.0),
+        pid.next_control_output(20.0)
+    );
+
+    let changed = HeaterConfig {
+        target_temp: 40.0,
+        ..heater
+    };
+    assert!(store.pid(&changed).is_none());
+}
+
+#[test]
+fn unreadable_state_file_is_moved_aside() {
+    let dir = state_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("runtime_state.json"), "{ not json").unwrap();
+
+    let store = StateStore::open(&dir).unwrap();
+
+    assert!(store.connection(&PLCConfig::default()).is_none());
+    assert!(dir.join("runtime_state.json.corrupt").exists());
+}